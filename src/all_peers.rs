@@ -3,7 +3,7 @@
 macro_rules! list_of_all_specifier_classes {
     ($your_macro:ident) => {
         $your_macro!($crate::ws_client_peer::WsClientClass);
-        #[cfg(feature = "ssl")]
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
         $your_macro!($crate::ws_client_peer::WsClientSecureClass);
         $your_macro!($crate::ws_server_peer::WsTcpServerClass);
         $your_macro!($crate::ws_server_peer::WsInetdServerClass);
@@ -13,7 +13,7 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::ws_lowlevel_peer::WsLlClientClass);
         $your_macro!($crate::ws_lowlevel_peer::WsLlServerClass);
 
-        #[cfg(feature = "ssl")]
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
         $your_macro!($crate::ssl_peer::WssListenClass);
 
         $your_macro!($crate::http_peer::HttpRequestClass);
@@ -31,11 +31,11 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::net_peer::TcpConnectClass);
         $your_macro!($crate::net_peer::TcpListenClass);
 
-        #[cfg(feature = "ssl")]
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
         $your_macro!($crate::ssl_peer::TlsConnectClass);
-        #[cfg(feature = "ssl")]
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
         $your_macro!($crate::ssl_peer::TlsAcceptClass);
-        #[cfg(feature = "ssl")]
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
         $your_macro!($crate::ssl_peer::TlsListenClass);
 
         #[cfg(feature = "tokio-process")]
@@ -57,6 +57,7 @@ macro_rules! list_of_all_specifier_classes {
 
         $your_macro!($crate::net_peer::UdpConnectClass);
         $your_macro!($crate::net_peer::UdpListenClass);
+        $your_macro!($crate::net_peer::UdpServerClass);
 
         #[cfg(all(unix, feature = "unix_stdio"))]
         $your_macro!($crate::stdio_peer::OpenAsyncClass);
@@ -73,6 +74,8 @@ macro_rules! list_of_all_specifier_classes {
         #[cfg(unix)]
         $your_macro!($crate::unix_peer::UnixDgramClass);
         #[cfg(unix)]
+        $your_macro!($crate::sd_listen_peer::SdListenClass);
+        #[cfg(unix)]
         $your_macro!($crate::unix_peer::AbstractConnectClass);
         #[cfg(unix)]
         $your_macro!($crate::unix_peer::AbstractListenClass);
@@ -84,6 +87,11 @@ macro_rules! list_of_all_specifier_classes {
 
         $your_macro!($crate::line_peer::Message2LineClass);
         $your_macro!($crate::line_peer::Line2MessageClass);
+        $your_macro!($crate::line_peer::LineClass);
+        $your_macro!($crate::lengthprefixed_peer::LengthPrefixedClass);
+        $your_macro!($crate::framing_peer::NetstringClass);
+        $your_macro!($crate::framing_peer::CobsClass);
+        $your_macro!($crate::framing_peer::SlipClass);
         $your_macro!($crate::foreachmsg_peer::ForeachmsgClass);
         $your_macro!($crate::mirror_peer::MirrorClass);
         $your_macro!($crate::mirror_peer::LiteralReplyClass);
@@ -100,8 +108,15 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::unix_peer::unix_seqpacket_peer::SeqpacketListenClass);
 
         $your_macro!($crate::jsonrpc_peer::JsonRpcClass);
+        $your_macro!($crate::jsonrpc_peer::JsonRpcServerClass);
+        #[cfg(feature = "jq")]
+        $your_macro!($crate::jq_peer::JqClass);
+        #[cfg(feature = "filter")]
+        $your_macro!($crate::filter_peer::FilterClass);
 
         $your_macro!($crate::socks5_peer::SocksProxyClass);
         $your_macro!($crate::socks5_peer::SocksBindClass);
+        $your_macro!($crate::proxy_proto_peer::ProxyProtoAcceptClass);
+        $your_macro!($crate::proxy_proto_peer::ProxyProtoSendClass);
     };
 }
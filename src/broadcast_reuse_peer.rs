@@ -31,9 +31,10 @@ impl Specifier for BroadcastReuser {
         let mut reuser = p.global(GlobalState::default).clone();
         let bs = p.program_options.buffer_size;
         let ql = p.program_options.broadcast_queue_len;
+        let max = p.program_options.max_message_size;
         let l2r = p.left_to_right.clone();
         let inner = || self.0.construct(p).get_only_first_conn(l2r);
-        once(connection_reuser(&mut reuser, inner, bs, ql))
+        once(connection_reuser(&mut reuser, inner, bs, ql, max))
     }
     specifier_boilerplate!(singleconnect has_subspec globalstate);
     self_0_is_subspecifier!(...);
@@ -84,9 +85,40 @@ struct PeerHandleR(
     HBroadCaster,
     mpsc::Receiver<SailingBuffer>,
     BroadcastClientIndex,
+    /// Message waiting for a bigger buffer
+    Option<SailingBuffer>,
+    /// Maximum size of buffer to ask for
+    usize,
 );
 struct InnerPeerReader(HBroadCaster, Vec<u8>);
 
+fn broadcast(clients: &mut Clients, msg: &[u8]) {
+    if clients.is_empty() {
+        info!("Dropping broadcast due to no clients being connected");
+        return;
+    };
+    let sb = Rc::new(msg.to_vec());
+    for (_, client) in clients.iter_mut() {
+        match client.start_send(sb.clone()) {
+            Ok(AsyncSink::Ready) => match client.poll_complete() {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::NotReady) => {
+                    warn!("A client's sink is NotReady for poll_complete");
+                }
+                Err(e) => {
+                    warn!("A client's sink is in error state: {}", e);
+                }
+            },
+            Ok(AsyncSink::NotReady(_)) => {
+                warn!("A client's sink is NotReady for start_send");
+            }
+            Err(e) => {
+                warn!("A client's sink is in error state: {}", e);
+            }
+        };
+    }
+}
+
 impl Future for InnerPeerReader {
     type Item = ();
     type Error = ();
@@ -99,36 +131,20 @@ impl Future for InnerPeerReader {
                     info!("Underlying peer finished");
                     return Ok(futures::Async::Ready(()));
                 }
-                Ok(n) => {
-                    if me.clients.is_empty() {
-                        info!("Dropping broadcast due to no clients being connected");
-                        continue;
-                    };
-                    let sb = Rc::new(self.1[0..n].to_vec());
-                    for (_, client) in me.clients.iter_mut() {
-                        match client.start_send(sb.clone()) {
-                            Ok(AsyncSink::Ready) => match client.poll_complete() {
-                                Ok(Async::Ready(())) => {}
-                                Ok(Async::NotReady) => {
-                                    warn!("A client's sink is NotReady for poll_complete");
-                                }
-                                Err(e) => {
-                                    warn!("A client's sink is in error state: {}", e);
-                                }
-                            },
-                            Ok(AsyncSink::NotReady(_)) => {
-                                warn!("A client's sink is NotReady for start_send");
-                            }
-                            Err(e) => {
-                                warn!("A client's sink is in error state: {}", e);
-                            }
-                        };
-                    }
-                }
+                Ok(n) => broadcast(&mut me.clients, &self.1[0..n]),
                 Err(e) => {
                     if e.kind() == ::std::io::ErrorKind::WouldBlock {
                         return Ok(Async::NotReady);
                     }
+                    if crate::readdebt::is_empty_message(&e) {
+                        broadcast(&mut me.clients, b"");
+                        continue;
+                    }
+                    if let Some(n) = crate::readdebt::requested_buffer_size(&e) {
+                        debug!("Growing broadcast buffer to {} bytes", n);
+                        self.1.resize(n, 0);
+                        continue;
+                    }
                     error!("Inner peer read failed: {}", e);
                     return Err(());
                 }
@@ -151,9 +167,24 @@ impl Drop for PeerHandleR {
 impl Read for PeerHandleR {
     fn read(&mut self, b: &mut [u8]) -> Result<usize, IoError> {
         loop {
-            return match self.1.poll() {
+            let polled = match self.3.take() {
+                Some(v) => Ok(Async::Ready(Some(v))),
+                None => self.1.poll(),
+            };
+            return match polled {
                 Ok(Async::Ready(Some(v))) => {
+                    if v.is_empty() {
+                        return Err(crate::readdebt::empty_message());
+                    }
                     if v.len() > b.len() {
+                        if v.len() <= self.4 {
+                            let l = v.len();
+                            debug!("Asking for a bigger buffer for message of {} bytes", l);
+                            self.3 = Some(v);
+                            return Err(crate::util::io_other_error(
+                                crate::readdebt::MessageTooLong(l),
+                            ));
+                        }
                         error!("Too big message dropped");
                         continue;
                     }
@@ -203,7 +234,7 @@ impl AsyncWrite for PeerHandleW {
     }
 }
 
-fn makeclient(ps: HBroadCaster, queue_len: usize) -> Peer {
+fn makeclient(ps: HBroadCaster, queue_len: usize, max_message_size: usize) -> Peer {
     let (send, recv) = mpsc::channel(queue_len);
     let k = ps
         .borrow_mut()
@@ -211,7 +242,7 @@ fn makeclient(ps: HBroadCaster, queue_len: usize) -> Peer {
         .expect("Assertion failed 16291")
         .clients
         .insert(send);
-    let ph1 = PeerHandleR(ps.clone(), recv, k);
+    let ph1 = PeerHandleR(ps.clone(), recv, k, None, max_message_size);
     let ph2 = PeerHandleW(ps);
     Peer::new(ph1, ph2, None /* TODO */)
 }
@@ -221,6 +252,7 @@ pub fn connection_reuser<F: FnOnce() -> BoxedNewPeerFuture>(
     inner_peer: F,
     buffer_size: usize,
     queue_len: usize,
+    max_message_size: usize,
 ) -> BoxedNewPeerFuture {
     let need_init = s.borrow().is_none();
 
@@ -239,11 +271,65 @@ pub fn connection_reuser<F: FnOnce() -> BoxedNewPeerFuture>(
             }
 
             let ps: HBroadCaster = rc.clone();
-            ok(makeclient(ps, queue_len))
+            ok(makeclient(ps, queue_len, max_message_size))
         })) as BoxedNewPeerFuture
     } else {
         info!("Reusing");
         let ps: HBroadCaster = rc.clone();
-        Box::new(ok(makeclient(ps, queue_len))) as BoxedNewPeerFuture
+        Box::new(ok(makeclient(ps, queue_len, max_message_size))) as BoxedNewPeerFuture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mirror_peer::get_mirror_peer;
+    use crate::my_copy::{copy, CopyOptions};
+    use crate::readdebt::DebtHandling;
+
+    /// Records each write as a separate message
+    struct Messages(Rc<RefCell<Vec<Vec<u8>>>>);
+    impl Write for Messages {
+        fn write(&mut self, b: &[u8]) -> Result<usize, IoError> {
+            self.0.borrow_mut().push(b.to_vec());
+            Ok(b.len())
+        }
+        fn flush(&mut self) -> Result<(), IoError> {
+            Ok(())
+        }
+    }
+    impl AsyncWrite for Messages {
+        fn shutdown(&mut self) -> futures::Poll<(), IoError> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    #[test]
+    fn message_longer_than_buffer() {
+        let msg: Vec<u8> = (0..100).collect();
+        let received = Rc::new(RefCell::new(vec![]));
+        let r2 = received.clone();
+        let m2 = msg.clone();
+        let prog = futures::future::lazy(move || {
+            let mut gs = GlobalState::default();
+            let inner = || get_mirror_peer(DebtHandling::Warn, Some(1000));
+            connection_reuser(&mut gs, inner, 8, 16, 1000).and_then(move |client| {
+                let opts = CopyOptions {
+                    stop_on_reader_zero_read: true,
+                    once: true,
+                    buffer_size: 8,
+                    max_buffer_size: 1000,
+                    skip: false,
+                    max_ops: None,
+                };
+                let Peer(r, w, ..) = client;
+                let send = tokio_io::io::write_all(w, m2).map(|_| ());
+                let recv = copy(r, Messages(r2), opts).map(|_| ());
+                send.join(recv).map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+            })
+        });
+        let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+        rt.block_on(prog).unwrap();
+        assert_eq!(*received.borrow(), vec![msg]);
     }
 }
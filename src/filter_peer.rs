@@ -0,0 +1,256 @@
+use futures::future::Future;
+use futures::sync::mpsc;
+use futures::Stream;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::transform_peer::{transform_peer, MessageTransform};
+use regex::bytes::Regex;
+
+use super::options::FilterMessageType;
+use super::{spawn_hack, Peer};
+use super::{ConstructParams, PeerConstructor, Specifier};
+
+#[derive(Debug)]
+pub struct Filter<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for Filter<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let opts = &cp.program_options;
+        let tee = opts.filter_tee.clone().map(|spec| {
+            let tee = RefCell::new(Tee {
+                spec,
+                cp: Some(cp.clone()),
+                tx: None,
+            });
+            Box::new(move |msg: &[u8]| tee.borrow_mut().send(msg)) as TeeFn
+        });
+        let cfg = Rc::new(FilterConfig {
+            regex: opts.filter_regex.clone(),
+            prefix: opts.filter_prefix.clone(),
+            mtype: opts.filter_type,
+            min_size: opts.filter_min_size,
+            max_size: opts.filter_max_size,
+            invert: opts.filter_invert,
+            tee,
+        });
+        let direction = opts.filter_direction;
+        let buffer_size = opts.buffer_size;
+        let grow_limit = if opts.whole_messages {
+            Some(opts.max_message_size)
+        } else {
+            None
+        };
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, _| {
+            let t = || Some(Box::new(FilterTransform(cfg.clone())) as Box<dyn MessageTransform>);
+            let r = if direction.reads() { t() } else { None };
+            let w = if direction.writes() { t() } else { None };
+            transform_peer(p, r, w, buffer_size, grow_limit)
+        })
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = FilterClass,
+    target = Filter,
+    prefixes = ["filter:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+[A] Pass only messages matching all of the specified conditions, drop other ones.
+
+Conditions are --filter-regex, --filter-prefix, --filter-type, --filter-min-size
+and --filter-max-size. WebSocket message type is not known at this point,
+so --filter-type treats valid UTF-8 messages as text and others as binary.
+--filter-invert drops matching messages instead. --filter-direction chooses which messages
+are checked (default is both). With --filter-tee dropped messages are sent to the given
+specifier instead of being discarded (connected on first dropped message).
+
+Regular expressions use the syntax of Rust `regex` crate and are matched on message bytes.
+
+Example: don't let clients send messages starting with `!` to a shared backend
+
+    websocat -E -t ws-l:127.0.0.1:8080 filter:broadcast:tcp:127.0.0.1:1234 --filter-prefix '!' --filter-invert --filter-direction write
+
+Example: show only pings from server, save everything else to a file
+
+    websocat -t - filter:ws://127.0.0.1:8080 --filter-regex '"type": *"ping"' --filter-direction read --filter-tee appendfile:other.txt
+"#
+);
+
+/// Connection to `--filter-tee` specifier, established on first use
+struct Tee {
+    spec: Rc<dyn Specifier>,
+    cp: Option<ConstructParams>,
+    tx: Option<mpsc::UnboundedSender<Vec<u8>>>,
+}
+
+impl Tee {
+    fn send(&mut self, msg: &[u8]) {
+        if let Some(cp) = self.cp.take() {
+            let (tx, rx) = mpsc::unbounded::<Vec<u8>>();
+            self.tx = Some(tx);
+            let l2r = cp.left_to_right.clone();
+            let peer = self.spec.construct(cp).get_only_first_conn(l2r);
+            spawn_hack(
+                peer.map_err(|e| error!("--filter-tee: {}", e))
+                    .and_then(|Peer(r, w, hup, _)| {
+                        rx.fold(w, |w, msg| {
+                            tokio_io::io::write_all(w, msg)
+                                .and_then(|(w, _)| tokio_io::io::flush(w))
+                                .map_err(|e| error!("--filter-tee: {}", e))
+                        })
+                        .map(move |_| drop((r, hup)))
+                    }),
+            );
+        }
+        if let Some(ref tx) = self.tx {
+            if tx.unbounded_send(msg.to_vec()).is_err() {
+                debug!("--filter-tee connection is gone, dropping the message");
+            }
+        }
+    }
+}
+
+type TeeFn = Box<dyn Fn(&[u8])>;
+
+struct FilterConfig {
+    regex: Option<Rc<Regex>>,
+    prefix: Option<Vec<u8>>,
+    mtype: Option<FilterMessageType>,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+    invert: bool,
+    /// Receives dropped messages
+    tee: Option<TeeFn>,
+}
+
+impl FilterConfig {
+    fn matches(&self, msg: &[u8]) -> bool {
+        if self.min_size.is_some_and(|x| msg.len() < x) || self.max_size.is_some_and(|x| msg.len() > x) {
+            return false;
+        }
+        if let Some(ref p) = self.prefix {
+            if !msg.starts_with(p) {
+                return false;
+            }
+        }
+        if let Some(t) = self.mtype {
+            let text = std::str::from_utf8(msg).is_ok();
+            if text != (t == FilterMessageType::Text) {
+                return false;
+            }
+        }
+        if let Some(ref re) = self.regex {
+            if !re.is_match(msg) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+struct FilterTransform(Rc<FilterConfig>);
+
+impl MessageTransform for FilterTransform {
+    fn transform(&mut self, msg: &[u8]) -> Vec<Vec<u8>> {
+        let cfg = &self.0;
+        if cfg.matches(msg) != cfg.invert {
+            return vec![msg.to_vec()];
+        }
+        debug!("Dropping a message of {} bytes", msg.len());
+        if let Some(ref tee) = cfg.tee {
+            tee(msg);
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg() -> FilterConfig {
+        FilterConfig {
+            regex: None,
+            prefix: None,
+            mtype: None,
+            min_size: None,
+            max_size: None,
+            invert: false,
+            tee: None,
+        }
+    }
+
+    #[test]
+    fn matches() {
+        assert!(cfg().matches(b""));
+
+        let c = FilterConfig {
+            prefix: Some(b"!".to_vec()),
+            ..cfg()
+        };
+        assert!(c.matches(b"!x"));
+        assert!(!c.matches(b"x!"));
+        assert!(!c.matches(b""));
+
+        let c = FilterConfig {
+            min_size: Some(2),
+            max_size: Some(3),
+            ..cfg()
+        };
+        assert!(!c.matches(b"a"));
+        assert!(c.matches(b"ab"));
+        assert!(c.matches(b"abc"));
+        assert!(!c.matches(b"abcd"));
+
+        let c = FilterConfig {
+            mtype: Some(FilterMessageType::Text),
+            ..cfg()
+        };
+        assert!(c.matches("ä".as_bytes()));
+        assert!(!c.matches(b"\xFF"));
+        let c = FilterConfig {
+            mtype: Some(FilterMessageType::Binary),
+            ..cfg()
+        };
+        assert!(!c.matches(b"abc"));
+        assert!(c.matches(b"\xFF"));
+
+        let c = FilterConfig {
+            regex: Some(Rc::new(Regex::new(r#""type": *"ping""#).unwrap())),
+            prefix: Some(b"{".to_vec()),
+            ..cfg()
+        };
+        assert!(c.matches(br#"{"type": "ping"}"#));
+        assert!(!c.matches(br#"{"type": "pong"}"#));
+        assert!(!c.matches(br#"["type": "ping"]"#));
+        let c = FilterConfig {
+            regex: Some(Rc::new(Regex::new(r"(?-u)^\xFF").unwrap())),
+            ..cfg()
+        };
+        assert!(c.matches(b"\xFFabc"));
+        assert!(!c.matches(b"abc"));
+    }
+
+    #[test]
+    fn invert_and_tee() {
+        let dropped = Rc::new(RefCell::new(vec![]));
+        let d = dropped.clone();
+        let c = FilterConfig {
+            prefix: Some(b"!".to_vec()),
+            invert: true,
+            tee: Some(Box::new(move |msg: &[u8]| d.borrow_mut().push(msg.to_vec()))),
+            ..cfg()
+        };
+        let mut t = FilterTransform(Rc::new(c));
+        assert_eq!(t.transform(b"hello"), vec![b"hello".to_vec()]);
+        assert!(t.transform(b"!secret").is_empty());
+        assert_eq!(t.transform(b""), vec![b"".to_vec()]);
+        assert_eq!(*dropped.borrow(), vec![b"!secret".to_vec()]);
+    }
+}
@@ -153,7 +153,9 @@ impl Read for PeerHandle {
                     return Ok(0);
                 }
                 Err(e) => {
-                    if e.kind() == ::std::io::ErrorKind::WouldBlock {
+                    if e.kind() == ::std::io::ErrorKind::WouldBlock
+                        || crate::readdebt::is_empty_message(&e)
+                    {
                         return Err(e);
                     }
                     warn!("{}", e);
@@ -0,0 +1,712 @@
+//! Message framing codecs for byte streams: `netstring:`, `cobs:` and `slip:`.
+//! Writer part is also used by `lengthprefixed:`.
+
+use futures::future::ok;
+
+use std::rc::Rc;
+
+use super::{BoxedNewPeerFuture, Peer};
+use super::{ConstructParams, PeerConstructor, Specifier};
+
+use std::io::{Error as IoError, Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+pub trait FrameEncoder {
+    fn encode(&self, msg: &[u8], out: &mut Vec<u8>) -> Result<(), String>;
+}
+
+pub enum Decoded {
+    NeedMore,
+    /// Number of consumed bytes and decoded message
+    Frame(usize, Vec<u8>),
+    /// Number of bytes to skip and error description
+    Malformed(usize, String),
+    /// Number of bytes to skip silently, e.g. delimiters between frames
+    Skip(usize),
+}
+
+pub trait FrameDecoder {
+    fn decode(&self, buf: &[u8]) -> Decoded;
+    /// Byte to resynchronize at after skipping a malformed or too long frame
+    fn delimiter(&self) -> u8;
+    /// Header length and message length of the frame at the beginning of `buf`
+    /// if its header tells it. Such frame is the header, the message as is and the delimiter,
+    /// so a too long frame can be skipped or split without decoding it.
+    fn frame_header(&self, _buf: &[u8]) -> Option<(usize, usize)> {
+        None
+    }
+    /// Decode beginning of an incomplete frame, so that the rest of the frame
+    /// still decodes as a frame. Used to split frames which are too long to be buffered whole.
+    fn decode_part(&self, _buf: &[u8]) -> Decoded {
+        Decoded::NeedMore
+    }
+}
+
+macro_rules! framing_specifier {
+    ($name:ident, $codec:expr) => {
+        #[derive(Debug)]
+        pub struct $name<T: Specifier>(pub T);
+        impl<T: Specifier> Specifier for $name<T> {
+            fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+                let opts = &cp.program_options;
+                let strict = opts.linemode_strict;
+                let grow_limit = if opts.whole_messages {
+                    Some(opts.max_message_size)
+                } else {
+                    None
+                };
+                let inner = self.0.construct(cp.clone());
+                inner.map(move |p, _| framed_peer(p, $codec, strict, grow_limit))
+            }
+            specifier_boilerplate!(noglobalstate has_subspec);
+            self_0_is_subspecifier!(proxy_is_multiconnect);
+        }
+    };
+}
+
+framing_specifier!(Netstring, NetstringCodec);
+specifier_class!(
+    name = NetstringClass,
+    target = Netstring,
+    prefixes = ["netstring:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Turn stream of netstrings (like `5:hello,`) into messages and back. [A]
+
+Malformed netstrings are skipped with a warning, or abort the connection in --strict mode.
+Messages longer than buffer size (-B) and --max-message-size are split,
+or dropped in --strict mode.
+
+Example:
+
+    websocat -b ws-l:127.0.0.1:8080 netstring:tcp:127.0.0.1:1234
+"#
+);
+
+framing_specifier!(Cobs, CobsCodec);
+specifier_class!(
+    name = CobsClass,
+    target = Cobs,
+    prefixes = ["cobs:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Turn stream of COBS-encoded zero-terminated frames into messages and back. [A]
+
+Consistent Overhead Byte Stuffing allows binary messages to be delimited by zero bytes.
+Malformed frames are skipped with a warning, or abort the connection in --strict mode.
+Messages longer than buffer size (-B) and --max-message-size are split,
+or dropped in --strict mode.
+
+Example: talk to a microcontroller over serial port
+
+    websocat -b ws-l:127.0.0.1:8080 cobs:open:/dev/ttyUSB0
+"#
+);
+
+framing_specifier!(Slip, SlipCodec);
+specifier_class!(
+    name = SlipClass,
+    target = Slip,
+    prefixes = ["slip:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Turn stream of SLIP (RFC 1055) frames into messages and back. [A]
+
+Outgoing frames both start and end with END byte. Empty frames are ignored,
+so zero-length messages can't be transferred.
+Malformed frames are skipped with a warning, or abort the connection in --strict mode.
+Messages longer than buffer size (-B) and --max-message-size are split,
+or dropped in --strict mode.
+
+Example:
+
+    websocat -b ws-l:127.0.0.1:8080 slip:open:/dev/ttyUSB0
+"#
+);
+
+#[derive(Debug, Clone, Copy)]
+pub struct NetstringCodec;
+
+impl FrameEncoder for NetstringCodec {
+    fn encode(&self, msg: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        out.extend_from_slice(format!("{}:", msg.len()).as_bytes());
+        out.extend_from_slice(msg);
+        out.push(b',');
+        Ok(())
+    }
+}
+
+/// Longer length is surely garbage
+const NETSTRING_MAX_DIGITS: usize = 19;
+
+impl FrameDecoder for NetstringCodec {
+    fn decode(&self, buf: &[u8]) -> Decoded {
+        let skip = || buf.iter().position(|&c| c == b',').map_or(buf.len(), |x| x + 1);
+        let colon = match buf.iter().position(|&c| !c.is_ascii_digit()) {
+            None if buf.len() > NETSTRING_MAX_DIGITS => {
+                return Decoded::Malformed(skip(), "Netstring length is too long".to_string())
+            }
+            None => return Decoded::NeedMore,
+            Some(i) if i == 0 || buf[i] != b':' => {
+                return Decoded::Malformed(skip(), "Invalid netstring length".to_string())
+            }
+            Some(i) => i,
+        };
+        let len: usize = match std::str::from_utf8(&buf[..colon]).unwrap().parse() {
+            Ok(x) => x,
+            Err(_) => return Decoded::Malformed(skip(), "Invalid netstring length".to_string()),
+        };
+        let end = match (colon + 1).checked_add(len) {
+            Some(x) if x < usize::MAX => x,
+            _ => return Decoded::Malformed(skip(), "Netstring length is too long".to_string()),
+        };
+        if buf.len() <= end {
+            return Decoded::NeedMore;
+        }
+        if buf[end] != b',' {
+            return Decoded::Malformed(skip(), "Netstring does not end with a comma".to_string());
+        }
+        Decoded::Frame(end + 1, buf[colon + 1..end].to_vec())
+    }
+    fn delimiter(&self) -> u8 {
+        b','
+    }
+    fn frame_header(&self, buf: &[u8]) -> Option<(usize, usize)> {
+        let colon = buf.iter().position(|&c| c == b':')?;
+        let len: usize = std::str::from_utf8(&buf[..colon]).ok()?.parse().ok()?;
+        Some((colon + 1, len))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CobsCodec;
+
+impl FrameEncoder for CobsCodec {
+    fn encode(&self, msg: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        let mut code_idx = out.len();
+        out.push(0);
+        let mut code = 1u8;
+        for &b in msg {
+            if b != 0 {
+                out.push(b);
+                code += 1;
+            }
+            if b == 0 || code == 0xFF {
+                out[code_idx] = code;
+                code_idx = out.len();
+                out.push(0);
+                code = 1;
+            }
+        }
+        out[code_idx] = code;
+        out.push(0);
+        Ok(())
+    }
+}
+
+impl FrameDecoder for CobsCodec {
+    fn decode(&self, buf: &[u8]) -> Decoded {
+        let end = match buf.iter().position(|&c| c == 0) {
+            None => return Decoded::NeedMore,
+            // Empty message is encoded as [1, 0], sole zero is just a delimiter
+            Some(0) => return Decoded::Skip(1),
+            Some(x) => x,
+        };
+        let frame = &buf[..end];
+        let mut msg = Vec::with_capacity(frame.len());
+        let mut i = 0;
+        while i < frame.len() {
+            let code = frame[i] as usize;
+            let next = i + code;
+            if next > frame.len() {
+                return Decoded::Malformed(end + 1, "Invalid COBS frame".to_string());
+            }
+            msg.extend_from_slice(&frame[i + 1..next]);
+            i = next;
+            if code < 0xFF && i < frame.len() {
+                msg.push(0);
+            }
+        }
+        Decoded::Frame(end + 1, msg)
+    }
+    fn delimiter(&self) -> u8 {
+        0
+    }
+    fn decode_part(&self, buf: &[u8]) -> Decoded {
+        let mut msg = vec![];
+        let mut i = 0;
+        // Only whole blocks followed by more data, as the zero after a block depends on it
+        while i < buf.len() {
+            let code = buf[i] as usize;
+            let next = i + code;
+            if next >= buf.len() {
+                break;
+            }
+            msg.extend_from_slice(&buf[i + 1..next]);
+            if code < 0xFF {
+                msg.push(0);
+            }
+            i = next;
+        }
+        if i == 0 {
+            return Decoded::NeedMore;
+        }
+        Decoded::Frame(i, msg)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlipCodec;
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+impl FrameEncoder for SlipCodec {
+    fn encode(&self, msg: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        out.push(SLIP_END);
+        for &b in msg {
+            match b {
+                SLIP_END => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+                SLIP_ESC => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                b => out.push(b),
+            }
+        }
+        out.push(SLIP_END);
+        Ok(())
+    }
+}
+
+impl FrameDecoder for SlipCodec {
+    fn decode(&self, buf: &[u8]) -> Decoded {
+        let end = match buf.iter().position(|&c| c == SLIP_END) {
+            None => return Decoded::NeedMore,
+            // Empty frames are used for resynchronisation and can't carry empty messages
+            Some(0) => return Decoded::Skip(1),
+            Some(x) => x,
+        };
+        let mut msg = Vec::with_capacity(end);
+        let mut it = buf[..end].iter();
+        while let Some(&b) = it.next() {
+            msg.push(match b {
+                SLIP_ESC => match it.next() {
+                    Some(&SLIP_ESC_END) => SLIP_END,
+                    Some(&SLIP_ESC_ESC) => SLIP_ESC,
+                    _ => {
+                        return Decoded::Malformed(
+                            end + 1,
+                            "Invalid SLIP escape sequence".to_string(),
+                        )
+                    }
+                },
+                b => b,
+            });
+        }
+        Decoded::Frame(end + 1, msg)
+    }
+    fn delimiter(&self) -> u8 {
+        SLIP_END
+    }
+    fn decode_part(&self, buf: &[u8]) -> Decoded {
+        let mut msg = vec![];
+        let mut i = 0;
+        while i < buf.len() {
+            msg.push(match buf[i] {
+                SLIP_ESC => match buf.get(i + 1) {
+                    Some(&SLIP_ESC_END) => SLIP_END,
+                    Some(&SLIP_ESC_ESC) => SLIP_ESC,
+                    None => break,
+                    _ => {
+                        return Decoded::Malformed(
+                            buf.len(),
+                            "Invalid SLIP escape sequence".to_string(),
+                        )
+                    }
+                },
+                b => b,
+            });
+            i += if buf[i] == SLIP_ESC { 2 } else { 1 };
+        }
+        if i == 0 {
+            return Decoded::NeedMore;
+        }
+        Decoded::Frame(i, msg)
+    }
+}
+
+pub fn framed_peer<C>(
+    inner_peer: Peer,
+    codec: C,
+    strict: bool,
+    grow_limit: Option<usize>,
+) -> BoxedNewPeerFuture
+where
+    C: FrameEncoder + FrameDecoder + Copy + 'static,
+{
+    let r = FramedReader {
+        inner: inner_peer.0,
+        codec,
+        queue: vec![],
+        leftover: vec![],
+        resync: false,
+        skip: 0,
+        passthrough: 0,
+        splitting: false,
+        strict,
+        grow_limit,
+    };
+    let w = FramedWriter::new(inner_peer.1, codec);
+    let thepeer = Peer::new(r, w, inner_peer.2);
+    Box::new(ok(thepeer)) as BoxedNewPeerFuture
+}
+
+struct FramedReader<C: FrameDecoder> {
+    inner: Box<dyn AsyncRead>,
+    codec: C,
+    queue: Vec<u8>,
+    /// Rest of a message which was too long for the buffer
+    leftover: Vec<u8>,
+    /// Skipping data up to the next delimiter
+    resync: bool,
+    /// Bytes of a too long frame still to be skipped
+    skip: usize,
+    /// Bytes of a too long message still to be delivered as is, in parts
+    passthrough: usize,
+    /// Decoding a too long frame in parts
+    splitting: bool,
+    strict: bool,
+    /// Ask for a bigger buffer instead of splitting messages up to this size
+    grow_limit: Option<usize>,
+}
+
+impl<C: FrameDecoder> FramedReader<C> {
+    fn malformed(&mut self, skip: usize, e: String) -> Result<(), IoError> {
+        if self.strict {
+            return Err(crate::simple_err(e));
+        }
+        warn!("{}, skipping {} bytes", e, skip);
+        drop(self.queue.drain(..skip));
+        Ok(())
+    }
+
+    fn can_grow(&self, len: usize, buflen: usize) -> bool {
+        len > buflen && self.grow_limit.is_some_and(|x| len <= x)
+    }
+
+    /// Returns whether the message should be dropped instead of split
+    fn too_long(&self, len: Option<usize>, buflen: usize) -> bool {
+        let len = len.map_or(String::new(), |x| format!(" of {} bytes", x));
+        if self.strict {
+            error!("Dropping too long message{} because of buffer (-B option) is only {} bytes", len, buflen);
+        } else {
+            warn!("Splitting too long message{} because of buffer (-B option) is only {} bytes", len, buflen);
+        }
+        self.strict
+    }
+}
+
+impl<C: FrameDecoder> Read for FramedReader<C> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        loop {
+            if !self.leftover.is_empty() {
+                let n = self.leftover.len().min(buf.len());
+                buf[..n].copy_from_slice(&self.leftover[..n]);
+                drop(self.leftover.drain(..n));
+                return Ok(n);
+            }
+            if self.skip > 0 {
+                let n = self.skip.min(self.queue.len());
+                drop(self.queue.drain(..n));
+                self.skip -= n;
+            }
+            if self.passthrough > 0 && !self.queue.is_empty() {
+                let n = self.passthrough.min(self.queue.len()).min(buf.len());
+                buf[..n].copy_from_slice(&self.queue[..n]);
+                drop(self.queue.drain(..n));
+                self.passthrough -= n;
+                // Drop the delimiter after the message
+                self.resync = self.passthrough == 0;
+                return Ok(n);
+            }
+            if self.resync {
+                let d = self.codec.delimiter();
+                match self.queue.iter().position(|&c| c == d) {
+                    Some(i) => {
+                        drop(self.queue.drain(..=i));
+                        self.resync = false;
+                    }
+                    None => self.queue.clear(),
+                }
+            }
+            if !self.resync && self.skip == 0 && self.passthrough == 0 {
+                match self.codec.decode(&self.queue) {
+                    Decoded::Frame(n, msg) => {
+                        if !self.splitting && self.can_grow(msg.len(), buf.len()) {
+                            // Frame is decoded again when a bigger buffer comes
+                            let l = msg.len();
+                            return Err(crate::util::io_other_error(crate::readdebt::MessageTooLong(l)));
+                        }
+                        drop(self.queue.drain(..n));
+                        if std::mem::replace(&mut self.splitting, false) && msg.is_empty() {
+                            // Nothing left of a split message
+                            continue;
+                        }
+                        if msg.is_empty() {
+                            return Err(crate::readdebt::empty_message());
+                        }
+                        if msg.len() > buf.len() {
+                            if !self.too_long(Some(msg.len()), buf.len()) {
+                                self.leftover = msg;
+                            }
+                            continue;
+                        }
+                        buf[..msg.len()].copy_from_slice(&msg);
+                        return Ok(msg.len());
+                    }
+                    Decoded::Malformed(n, e) => {
+                        self.malformed(n, e)?;
+                        continue;
+                    }
+                    Decoded::Skip(n) => {
+                        drop(self.queue.drain(..n));
+                        continue;
+                    }
+                    Decoded::NeedMore => (),
+                }
+                // Incomplete frame which is surely too long is skipped or split
+                // without waiting for all of it
+                let limit = self.grow_limit.unwrap_or(0).max(buf.len());
+                if let Some((header, len)) = self.codec.frame_header(&self.queue) {
+                    if self.can_grow(len, buf.len()) {
+                        return Err(crate::util::io_other_error(crate::readdebt::MessageTooLong(len)));
+                    }
+                    if len > buf.len() {
+                        drop(self.queue.drain(..header));
+                        if self.too_long(Some(len), buf.len()) {
+                            self.skip = len + 1;
+                        } else {
+                            self.passthrough = len;
+                        }
+                        continue;
+                    }
+                } else if self.queue.len() > 2 * limit + 32 {
+                    // Encoded frame may be up to twice as long as the message
+                    if !self.splitting {
+                        if self.too_long(None, buf.len()) {
+                            self.resync = true;
+                            continue;
+                        }
+                        self.splitting = true;
+                    }
+                    match self.codec.decode_part(&self.queue) {
+                        Decoded::Frame(n, part) => {
+                            drop(self.queue.drain(..n));
+                            self.leftover = part;
+                            continue;
+                        }
+                        Decoded::Malformed(n, e) => {
+                            self.malformed(n, e)?;
+                            self.resync = true;
+                            self.splitting = false;
+                            continue;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            // `buf` is used as a temporary space here
+            let n = self.inner.read(buf)?;
+            if n == 0 {
+                if !self.queue.is_empty() && !self.resync {
+                    warn!("Throwing away {} bytes of incomplete frame", self.queue.len());
+                }
+                return Ok(0);
+            }
+            self.queue.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+impl<C: FrameDecoder> AsyncRead for FramedReader<C> {}
+
+/// Encodes each written buffer as a separate frame, including empty ones
+pub struct FramedWriter<C: FrameEncoder> {
+    inner: Box<dyn AsyncWrite>,
+    codec: C,
+    /// Encoded data not yet accepted by inner writer
+    pending: Vec<u8>,
+}
+
+impl<C: FrameEncoder> FramedWriter<C> {
+    pub fn new(inner: Box<dyn AsyncWrite>, codec: C) -> Self {
+        FramedWriter {
+            inner,
+            codec,
+            pending: vec![],
+        }
+    }
+
+    fn write_pending(&mut self) -> Result<(), IoError> {
+        while !self.pending.is_empty() {
+            let n = self.inner.write(&self.pending)?;
+            if n == 0 {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+            drop(self.pending.drain(..n));
+        }
+        Ok(())
+    }
+}
+
+impl<C: FrameEncoder> Write for FramedWriter<C> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.write_pending()?;
+        self.codec
+            .encode(buf, &mut self.pending)
+            .map_err(crate::simple_err)?;
+        match self.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+            Ok(()) => (),
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+impl<C: FrameEncoder> AsyncWrite for FramedWriter<C> {
+    fn shutdown(&mut self) -> futures::Poll<(), IoError> {
+        try_nb!(self.write_pending());
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<C: FrameEncoder + FrameDecoder>(codec: C, msg: &[u8]) -> Vec<u8> {
+        let mut e = vec![];
+        codec.encode(msg, &mut e).unwrap();
+        let mut skipped = 0;
+        loop {
+            match codec.decode(&e[skipped..]) {
+                Decoded::Frame(n, m) => {
+                    assert_eq!(skipped + n, e.len());
+                    assert_eq!(m, msg);
+                    return e;
+                }
+                Decoded::Skip(n) => skipped += n,
+                _ => panic!("failed to decode {:?}", e),
+            }
+        }
+    }
+
+    fn malformed<C: FrameDecoder>(codec: C, buf: &[u8]) -> usize {
+        match codec.decode(buf) {
+            Decoded::Malformed(n, _) => n,
+            _ => panic!("{:?} is not malformed", buf),
+        }
+    }
+
+    #[test]
+    fn netstring() {
+        assert_eq!(roundtrip(NetstringCodec, b"hello"), b"5:hello,");
+        assert_eq!(roundtrip(NetstringCodec, b""), b"0:,");
+        assert!(matches!(NetstringCodec.decode(b"5:hello"), Decoded::NeedMore));
+        assert_eq!(malformed(NetstringCodec, b"5:hello;3:abc,"), 14);
+        assert_eq!(malformed(NetstringCodec, b"x:,1:a,"), 3);
+        assert_eq!(malformed(NetstringCodec, b"99999999999999999999"), 20);
+        assert_eq!(NetstringCodec.frame_header(b"12:abc"), Some((3, 12)));
+        assert_eq!(NetstringCodec.frame_header(b"12"), None);
+    }
+
+    #[test]
+    fn cobs() {
+        assert_eq!(roundtrip(CobsCodec, b""), [1, 0]);
+        assert_eq!(roundtrip(CobsCodec, b"\0"), [1, 1, 0]);
+        assert_eq!(roundtrip(CobsCodec, b"a\0b"), [2, b'a', 2, b'b', 0]);
+        let mut run = vec![7; 254];
+        assert_eq!(roundtrip(CobsCodec, &run).len(), 257);
+        run.push(0);
+        assert_eq!(roundtrip(CobsCodec, &run).len(), 258);
+        run.extend_from_slice(&[7; 300]);
+        roundtrip(CobsCodec, &run);
+        assert_eq!(malformed(CobsCodec, &[5, 1, 0, 1, 0]), 3);
+    }
+
+    #[test]
+    fn slip() {
+        assert_eq!(roundtrip(SlipCodec, b"ab"), [SLIP_END, b'a', b'b', SLIP_END]);
+        assert_eq!(
+            roundtrip(SlipCodec, &[SLIP_END, 1, SLIP_ESC]),
+            [SLIP_END, SLIP_ESC, SLIP_ESC_END, 1, SLIP_ESC, SLIP_ESC_ESC, SLIP_END]
+        );
+        assert_eq!(malformed(SlipCodec, &[SLIP_ESC, 1, SLIP_END]), 3);
+        assert_eq!(malformed(SlipCodec, &[1, SLIP_ESC, SLIP_END]), 3);
+    }
+
+    fn reader<C: FrameDecoder>(codec: C, data: Vec<u8>, strict: bool) -> FramedReader<C> {
+        FramedReader {
+            inner: Box::new(std::io::Cursor::new(data)),
+            codec,
+            queue: vec![],
+            leftover: vec![],
+            resync: false,
+            skip: 0,
+            passthrough: 0,
+            splitting: false,
+            strict,
+            grow_limit: None,
+        }
+    }
+
+    fn read_all<C: FrameDecoder>(mut r: FramedReader<C>) -> Vec<Vec<u8>> {
+        let mut buf = [0; 8];
+        let mut msgs = vec![];
+        loop {
+            match r.read(&mut buf).unwrap() {
+                0 => return msgs,
+                n => msgs.push(buf[..n].to_vec()),
+            }
+        }
+    }
+
+    #[test]
+    fn too_long() {
+        let mut data = b"100:".to_vec();
+        data.extend_from_slice(&[b','; 100]);
+        data.extend_from_slice(b",2:ok,");
+        assert_eq!(read_all(reader(NetstringCodec, data.clone(), true)), [b"ok"]);
+        let msgs = read_all(reader(NetstringCodec, data, false));
+        assert_eq!(msgs.len(), 14);
+        assert_eq!(msgs[..13].concat(), [b','; 100]);
+        assert_eq!(msgs[13], b"ok");
+
+        too_long_delimited(CobsCodec);
+        too_long_delimited(SlipCodec);
+    }
+
+    fn too_long_delimited<C: FrameEncoder + FrameDecoder + Copy>(codec: C) {
+        let mut msg: Vec<u8> = (0..=255).collect();
+        msg.push(0);
+        let mut data = vec![];
+        codec.encode(&msg, &mut data).unwrap();
+        codec.encode(b"ok", &mut data).unwrap();
+        assert_eq!(read_all(reader(codec, data.clone(), true)), [b"ok"]);
+        let mut msgs = read_all(reader(codec, data, false));
+        assert_eq!(msgs.pop().unwrap(), b"ok");
+        assert_eq!(msgs.concat(), msg);
+    }
+}
@@ -84,6 +84,7 @@ pub fn http_serve(
 
     let co = CopyOptions {
         buffer_size: 1024,
+        max_buffer_size: 1024,
         once: false,
         stop_on_reader_zero_read: true,
         skip: false,
@@ -97,6 +98,7 @@ pub fn http_serve(
                 .and_then(move |(_len, _, conn)| {
                     let co2 = CopyOptions {
                         buffer_size: 65536,
+                        max_buffer_size: 65536,
                         once: false,
                         stop_on_reader_zero_read: true,
                         skip: false,
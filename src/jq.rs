@@ -0,0 +1,86 @@
+//! jq filters for `jq:` overlay, implemented by `jaq` crates.
+
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Native, RcIter};
+
+pub use jaq_json::Val;
+
+/// Compiled jq filter
+pub struct Filter(jaq_core::Filter<Native<Val>>);
+
+impl std::fmt::Debug for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Filter")
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+    fn from_str(code: &str) -> Result<Filter, String> {
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let arena = Arena::default();
+        let modules = loader
+            .load(&arena, File { code, path: () })
+            .map_err(|e| {
+                use jaq_core::load::Error;
+                let at = match e.into_iter().next().map(|x| x.1) {
+                    Some(Error::Lex(x)) => x.first().map(|x| x.1),
+                    Some(Error::Parse(x)) => x.first().map(|x| x.1),
+                    _ => None,
+                };
+                match at {
+                    Some(x) if !x.is_empty() => format!("unexpected input at `{}`", x),
+                    _ => "unexpected end of filter".to_string(),
+                }
+            })?;
+        let filter = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|e| {
+                let names: Vec<&str> = e.iter().flat_map(|x| x.1.iter().map(|y| y.0)).collect();
+                format!("undefined {}", names.join(", "))
+            })?;
+        Ok(Filter(filter))
+    }
+}
+
+impl Filter {
+    /// Run the filter on one input, collecting all outputs.
+    /// Stops on first error.
+    pub fn run(&self, input: Val) -> Result<Vec<Val>, String> {
+        let inputs = RcIter::new(std::iter::empty());
+        self.0
+            .run((Ctx::new([], &inputs), input))
+            .map(|x| x.map_err(|e| e.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Val};
+
+    fn jq(f: &str, input: &str) -> Result<Vec<String>, String> {
+        let f: Filter = f.parse()?;
+        let v: serde_json::Value = serde_json::from_str(input).unwrap();
+        Ok(f.run(Val::from(v))?.iter().map(|x| x.to_string()).collect())
+    }
+
+    #[test]
+    fn outputs() {
+        assert_eq!(jq(".a.b", r#"{"a":{"b":[1,"x"]}}"#).unwrap(), [r#"[1,"x"]"#]);
+        assert_eq!(jq(".[] | select(. > 1)", "[1,2,3]").unwrap(), ["2", "3"]);
+        assert_eq!(jq("{z: .a, a: .b}", r#"{"b":1.5,"a":2}"#).unwrap(), [r#"{"z":2,"a":1.5}"#]);
+        assert_eq!(jq("empty", "1").unwrap(), Vec::<String>::new());
+        assert_eq!(jq(".", r#"{"y":1,"x":2}"#).unwrap(), [r#"{"y":1,"x":2}"#]);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(jq(".[", "1").unwrap_err().contains("unexpected"));
+        assert!(jq("nosuchfunction", "1").unwrap_err().contains("nosuchfunction"));
+        assert!(jq(".a", "1").is_err());
+        assert!(jq(r#"1, error("x"), 2"#, "1").is_err());
+        assert_eq!(jq(".a?", "1").unwrap(), Vec::<String>::new());
+    }
+}
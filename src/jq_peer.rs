@@ -0,0 +1,152 @@
+//! `jq:` overlay
+
+use std::rc::Rc;
+
+use super::jq::{Filter, Val};
+use super::transform_peer::{transform_peer, MessageTransform};
+use super::{ConstructParams, PeerConstructor, Specifier};
+
+#[derive(Debug)]
+pub struct Jq<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for Jq<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let opts = &cp.program_options;
+        let filter = match opts.jq_filter {
+            Some(ref x) => x.clone(),
+            None => return PeerConstructor::Error("jq: overlay requires --jq option".into()),
+        };
+        let cfg = Rc::new(JqConfig {
+            filter,
+            raw_output: opts.jq_raw_output,
+            drop_non_json: opts.jq_drop_non_json,
+        });
+        let direction = opts.jq_direction;
+        let buffer_size = opts.buffer_size;
+        let grow_limit = if opts.whole_messages {
+            Some(opts.max_message_size)
+        } else {
+            None
+        };
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, _| {
+            let t = || Some(Box::new(JqTransform(cfg.clone())) as Box<dyn MessageTransform>);
+            let r = if direction.reads() { t() } else { None };
+            let w = if direction.writes() { t() } else { None };
+            transform_peer(p, r, w, buffer_size, grow_limit)
+        })
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = JqClass,
+    target = Jq,
+    prefixes = ["jq:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+[A] Apply --jq filter to each JSON message, like piping it to `jq -c`.
+
+Each output of the filter becomes a separate message; messages for which
+the filter outputs nothing (e.g. `select` did not match) are dropped.
+Use --jq-direction to choose which messages are processed (default is messages
+read from the underlying specifier), --jq-raw-output to get strings unquoted
+and --jq-drop-non-json to drop messages which are not JSON instead of passing them.
+Filter errors are logged and the message is dropped.
+
+The filter language is jq as implemented by the `jaq` crate, without
+date and time formatting functions.
+
+Example: show only names of big trades
+
+    websocat -t --jq 'select(.size > 100) | .name' - jq:ws://127.0.0.1:8080/trades
+
+Example: reshape outgoing messages
+
+    websocat -t --jq '{cmd: .[0], args: .[1]}' --jq-direction write - jq:ws://127.0.0.1:8080
+"#
+);
+
+struct JqConfig {
+    filter: Rc<Filter>,
+    raw_output: bool,
+    drop_non_json: bool,
+}
+
+struct JqTransform(Rc<JqConfig>);
+
+impl MessageTransform for JqTransform {
+    fn transform(&mut self, msg: &[u8]) -> Vec<Vec<u8>> {
+        let cfg = &self.0;
+        let v = match serde_json::from_slice::<serde_json::Value>(msg) {
+            Ok(x) => Val::from(x),
+            Err(_) if cfg.drop_non_json => {
+                debug!("Dropping non-JSON message");
+                return vec![];
+            }
+            Err(_) => return vec![msg.to_vec()],
+        };
+        let out = match cfg.filter.run(v) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("jq: {}, dropping the message", e);
+                return vec![];
+            }
+        };
+        // Keep line-based framing if message had it
+        let newline = msg.ends_with(b"\n");
+        out.into_iter()
+            .map(|x| {
+                let mut m = match x {
+                    Val::Str(ref s) if cfg.raw_output => s.as_bytes().to_vec(),
+                    x => x.to_string().into_bytes(),
+                };
+                if newline {
+                    m.push(b'\n');
+                }
+                m
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(filter: &str, raw_output: bool, drop_non_json: bool, msg: &str) -> Vec<String> {
+        let cfg = JqConfig {
+            filter: Rc::new(filter.parse().unwrap()),
+            raw_output,
+            drop_non_json,
+        };
+        JqTransform(Rc::new(cfg))
+            .transform(msg.as_bytes())
+            .into_iter()
+            .map(|x| String::from_utf8(x).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn transform() {
+        assert_eq!(t(".a", false, false, r#"{"a":"x"}"#), [r#""x""#]);
+        assert_eq!(t(".a", true, false, r#"{"a":"x"}"#), ["x"]);
+        assert_eq!(t(".a", true, false, r#"{"a":[1]}"#), ["[1]"]);
+        assert_eq!(t(".[]", false, false, "[1, 2]\n"), ["1\n", "2\n"]);
+        assert_eq!(t("select(. > 5)", false, false, "1"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn non_json() {
+        assert_eq!(t(".a", false, false, "hello"), ["hello"]);
+        assert_eq!(t(".a", false, true, "hello"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn filter_error() {
+        assert_eq!(t(".a", false, false, "[1]"), Vec::<String>::new());
+        assert_eq!(t("1, error(\"x\")", false, false, "0"), Vec::<String>::new());
+    }
+}
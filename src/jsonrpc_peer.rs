@@ -1,12 +1,19 @@
 use futures::future::ok;
+use futures::Future;
 
+use std::cell::RefCell;
+use futures::task::Task;
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use super::{BoxedNewPeerFuture, Peer};
+use serde_json::{json, Value};
+use super::readdebt::{DebtHandling, ProcessMessageResult, ReadDebt, ZeroMessagesHandling};
+use super::{spawn_hack, BoxedNewPeerFuture, Peer};
 use super::{ConstructParams, PeerConstructor, Specifier};
 
-use std::io::Read;
-use tokio_io::AsyncRead;
+use std::io::{Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
 
 use std::io::Error as IoError;
 
@@ -14,8 +21,20 @@ use std::io::Error as IoError;
 pub struct JsonRpc<T: Specifier>(pub T);
 impl<T: Specifier> Specifier for JsonRpc<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let opts = &cp.program_options;
+        let raw = opts.jsonrpc_raw;
+        let timeout = match opts.jsonrpc_timeout_millis {
+            0 => None,
+            x => Some(Duration::from_millis(x)),
+        };
+        let buffer_size = opts.buffer_size;
+        let grow_limit = if opts.whole_messages {
+            Some(opts.max_message_size)
+        } else {
+            None
+        };
         let inner = self.0.construct(cp.clone());
-        inner.map(move |p, _| jsonrpc_peer(p))
+        inner.map(move |p, _| jsonrpc_peer(p, raw, timeout, buffer_size, grow_limit))
     }
     specifier_boilerplate!(noglobalstate has_subspec);
     self_0_is_subspecifier!(proxy_is_multiconnect);
@@ -33,98 +52,1098 @@ specifier_class!(
 
 For simpler manual testing of websocket-based JSON-RPC services
 
-Example: TODO
+Parameters after method name can be specified as:
+
+* JSON array or object: `abc [1,"x"]`, `abc {"a":1}`
+* comma-separated JSON values: `abc 1, "x"`
+* shell-like words: `abc 1 x 'y z'` becomes `[1,"x","y z"]`.
+  Unquoted words which are valid JSON (numbers, `true`, `null`, `[1,2]`) are used as is,
+  other words and quoted words become strings.
+* named parameters: `abc a=1 b=x` becomes `{"a":1,"b":"x"}`
+
+Prefix method name with `!` to send a notification (no id, no reply expected): `!ping`.
+Lines that can't be turned into a valid request are reported in log and not sent.
+
+Replies are matched to requests by id and shown with method name and latency,
+with results pretty-printed, like
+
+    #1 abc (12 ms):
+    [
+      3
+    ]
+
+Errors are shown as `#1 abc (12 ms) error -32601: Method not found`.
+Messages from server which are not replies are shown as `notification method:`
+or `request #id method:`. Non-JSON messages are passed through unchanged.
+Requests without reply in --jsonrpc-timeout milliseconds are reported in log.
+Use --jsonrpc-raw to get replies unchanged.
+
+Example:
+
+    websocat --jsonrpc ws://127.0.0.1:8080
 "#
 );
 
-pub fn jsonrpc_peer(inner_peer: Peer) -> BoxedNewPeerFuture {
-    let filtered = JsonRpcWrapper(inner_peer.0, 1);
-    let thepeer = Peer::new(filtered, inner_peer.1, inner_peer.2);
+pub fn jsonrpc_peer(
+    inner_peer: Peer,
+    raw: bool,
+    timeout: Option<Duration>,
+    buffer_size: usize,
+    grow_limit: Option<usize>,
+) -> BoxedNewPeerFuture {
+    let state = Rc::new(RefCell::new(JsonRpcState::default()));
+    let r = JsonRpcWrapper {
+        inner: inner_peer.0,
+        next_id: 1,
+        state: state.clone(),
+        timeout,
+        buf: vec![0; buffer_size],
+        debt: ReadDebt(None, DebtHandling::Warn, ZeroMessagesHandling::Drop, grow_limit),
+    };
+    let w = JsonRpcReplyWriter {
+        inner: inner_peer.1,
+        state,
+        raw,
+        pending: vec![],
+    };
+    let thepeer = Peer::new(r, w, inner_peer.2);
     Box::new(ok(thepeer)) as BoxedNewPeerFuture
 }
-struct JsonRpcWrapper(Box<dyn AsyncRead>, u64);
+
+struct Outstanding {
+    method: String,
+    sent: Instant,
+}
+
+/// Number of timed out requests remembered to recognize their late replies
+const MAX_TIMED_OUT: usize = 64;
+
+/// Requests are keyed by JSON text of their ids
+#[derive(Default)]
+struct JsonRpcState {
+    outstanding: HashMap<String, Outstanding>,
+    timed_out: VecDeque<(String, Outstanding)>,
+}
+
+impl JsonRpcState {
+    /// Forget the request, returning it and whether it has timed out
+    fn take(&mut self, id: &Value) -> Option<(Outstanding, bool)> {
+        let key = id.to_string();
+        if let Some(o) = self.outstanding.remove(&key) {
+            return Some((o, false));
+        }
+        let i = self.timed_out.iter().position(|x| x.0 == key)?;
+        self.timed_out.remove(i).map(|x| (x.1, true))
+    }
+}
+
+struct JsonRpcWrapper {
+    inner: Box<dyn AsyncRead>,
+    next_id: u64,
+    state: Rc<RefCell<JsonRpcState>>,
+    timeout: Option<Duration>,
+    buf: Vec<u8>,
+    debt: ReadDebt,
+}
+
+impl JsonRpcWrapper {
+    fn track(&mut self, id: u64, method: String) {
+        let key = Value::from(id).to_string();
+        self.state.borrow_mut().outstanding.insert(
+            key.clone(),
+            Outstanding {
+                method,
+                sent: Instant::now(),
+            },
+        );
+        if let Some(t) = self.timeout {
+            let state = self.state.clone();
+            spawn_hack(
+                tokio_timer::Delay::new(Instant::now() + t)
+                    .map_err(|e| error!("tokio-timer's Delay: {}", e))
+                    .map(move |()| {
+                        let mut state = state.borrow_mut();
+                        if let Some(o) = state.outstanding.remove(&key) {
+                            warn!(
+                                "No reply to JSON-RPC request #{} {} in {} ms",
+                                id,
+                                o.method,
+                                t.as_millis()
+                            );
+                            if state.timed_out.len() >= MAX_TIMED_OUT {
+                                state.timed_out.pop_front();
+                            }
+                            state.timed_out.push_back((key, o));
+                        }
+                    }),
+            );
+        }
+    }
+}
+
+/// Word of a command line, with the position of the first unquoted `=`
+struct Word {
+    text: String,
+    eq: Option<usize>,
+    /// Some part after `=` (or the whole word) was quoted
+    quoted_value: bool,
+}
+
+/// Split string to words like shell does: whitespace separates words,
+/// `'...'` and `"..."` quote, backslash escapes the next character.
+fn shell_words(s: &str) -> Result<Vec<Word>, String> {
+    let mut words = vec![];
+    let mut cur: Option<Word> = None;
+    let mut chars = s.chars();
+    fn word(cur: &mut Option<Word>) -> &mut Word {
+        cur.get_or_insert_with(|| Word {
+            text: String::new(),
+            eq: None,
+            quoted_value: false,
+        })
+    }
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(w) = cur.take() {
+                    words.push(w);
+                }
+            }
+            '\\' => match chars.next() {
+                Some(x) => word(&mut cur).text.push(x),
+                None => return Err("Trailing backslash".to_string()),
+            },
+            '\'' | '"' => {
+                let w = word(&mut cur);
+                w.quoted_value = true;
+                loop {
+                    match chars.next() {
+                        None => return Err(format!("Unterminated {} quote", c)),
+                        Some(x) if x == c => break,
+                        Some('\\') if c == '"' => match chars.next() {
+                            Some(x) => w.text.push(x),
+                            None => return Err(format!("Unterminated {} quote", c)),
+                        },
+                        Some(x) => w.text.push(x),
+                    }
+                }
+            }
+            '=' => {
+                let w = word(&mut cur);
+                if w.eq.is_none() && !w.text.is_empty() && !w.quoted_value {
+                    w.eq = Some(w.text.len());
+                }
+                w.text.push(c);
+            }
+            c => word(&mut cur).text.push(c),
+        }
+    }
+    if let Some(w) = cur.take() {
+        words.push(w);
+    }
+    Ok(words)
+}
+
+/// Unquoted words are JSON values if they look like ones, otherwise strings
+fn word_value(text: &str, quoted: bool) -> Value {
+    if quoted {
+        return Value::String(text.to_string());
+    }
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+/// Parse parameters part of a `jsonrpc:` command line.
+///
+/// Accepts a JSON array or object, comma-separated JSON values
+/// or shell-like words, either positional or `key=value`.
+fn parse_params(s: &str) -> Result<Value, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(Value::Array(vec![]));
+    }
+    if s.starts_with('[') || s.starts_with('{') {
+        return serde_json::from_str(s).map_err(|e| format!("Invalid JSON: {}", e));
+    }
+    if let Ok(v) = serde_json::from_str(&format!("[{}]", s)) {
+        return Ok(v);
+    }
+    let words = shell_words(s)?;
+    let named = words.iter().filter(|w| w.eq.is_some()).count();
+    if named == 0 {
+        return Ok(Value::Array(
+            words.iter().map(|w| word_value(&w.text, w.quoted_value)).collect(),
+        ));
+    }
+    if named != words.len() {
+        return Err("Positional and named (key=value) parameters can't be mixed".to_string());
+    }
+    let mut members = serde_json::Map::new();
+    for w in words {
+        let eq = w.eq.unwrap();
+        let key = w.text[..eq].to_string();
+        if members.contains_key(&key) {
+            return Err(format!("Duplicate parameter {}", key));
+        }
+        members.insert(key, word_value(&w.text[eq + 1..], w.quoted_value));
+    }
+    Ok(Value::Object(members))
+}
+
+/// Request made from a `jsonrpc:` command line like `method 1 2`
+struct CommandLine {
+    method: String,
+    /// `!method` sends a notification
+    notification: bool,
+    params: Value,
+}
+
+fn parse_command_line(line: &str) -> Result<Option<CommandLine>, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let (method, rest) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], &line[i..]),
+        None => (line, ""),
+    };
+    let (method, notification) = match method.strip_prefix('!') {
+        Some(x) => (x, true),
+        None => (method, false),
+    };
+    if method.is_empty() {
+        return Err("Method name is missing".to_string());
+    }
+    Ok(Some(CommandLine {
+        method: method.to_string(),
+        notification,
+        params: parse_params(rest)?,
+    }))
+}
+
+impl JsonRpcWrapper {
+    /// Make request out of a command line, `None` if nothing should be sent
+    fn make_request(&mut self, line: &[u8]) -> Option<Vec<u8>> {
+        let line = match std::str::from_utf8(line) {
+            Ok(x) => x,
+            Err(_) => {
+                error!("JSON-RPC command line is not valid UTF-8, not sending it");
+                return None;
+            }
+        };
+        let cl = match parse_command_line(line) {
+            Ok(Some(x)) => x,
+            Ok(None) => return None,
+            Err(e) => {
+                error!("Invalid JSON-RPC command line, not sending it: {}", e);
+                return None;
+            }
+        };
+        let mut req = json!({"jsonrpc": "2.0"});
+        if !cl.notification {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.track(id, cl.method.clone());
+            req["id"] = json!(id);
+        }
+        req["method"] = Value::String(cl.method);
+        req["params"] = cl.params;
+        Some(format!("{}\n", req).into_bytes())
+    }
+}
 
 impl Read for JsonRpcWrapper {
     fn read(&mut self, b: &mut [u8]) -> Result<usize, IoError> {
-        let l = b.len();
-        assert!(l > 1);
-        let n = match self.0.read(&mut b[..l]) {
+        loop {
+            if let Some(ret) = self.debt.check_debt(b) {
+                return ret;
+            }
+            let n = match self.inner.read(&mut self.buf) {
+                Ok(0) => return Ok(0),
+                Ok(x) => x,
+                Err(ref e) if super::readdebt::is_empty_message(e) => continue,
+                Err(e) => return Err(e),
+            };
+            let line = self.buf[..n].to_vec();
+            if let Some(req) = self.make_request(&line) {
+                match self.debt.process_message(b, &req) {
+                    ProcessMessageResult::Return(x) => return x,
+                    ProcessMessageResult::Recurse => continue,
+                }
+            }
+        }
+    }
+}
+impl AsyncRead for JsonRpcWrapper {}
+
+fn pretty(v: &Value) -> String {
+    serde_json::to_string_pretty(v).unwrap_or_default()
+}
+
+/// Shows replies from server in readable form
+struct JsonRpcReplyWriter {
+    inner: Box<dyn AsyncWrite>,
+    state: Rc<RefCell<JsonRpcState>>,
+    raw: bool,
+    /// Lines not yet accepted by inner writer, each written as a separate message
+    pending: Vec<Vec<u8>>,
+}
+
+impl JsonRpcReplyWriter {
+    fn describe_reply(&self, id: &Value, out: &mut String) {
+        match self.state.borrow_mut().take(id) {
+            Some((o, late)) => {
+                out.push_str(&format!(
+                    "#{} {} ({} ms{})",
+                    id,
+                    o.method,
+                    o.sent.elapsed().as_millis(),
+                    if late { ", late" } else { "" }
+                ));
+            }
+            None => out.push_str(&format!("#{} (unknown request)", id)),
+        }
+    }
+
+    /// Readable form of a single message, `None` if it does not look like JSON-RPC
+    fn format(&self, v: &Value) -> Option<String> {
+        let mut out = String::new();
+        let id = v.get("id").filter(|x| **x != Value::Null);
+        if let Some(method) = v.get("method").and_then(|x| x.as_str()) {
+            match id {
+                Some(id) => out.push_str(&format!("request #{} {}", id, method)),
+                None => out.push_str(&format!("notification {}", method)),
+            }
+            if let Some(p) = v.get("params") {
+                out.push_str(":\n");
+                out.push_str(&pretty(p));
+            }
+            return Some(out);
+        }
+        if let Some(result) = v.get("result") {
+            self.describe_reply(id.unwrap_or(&Value::Null), &mut out);
+            out.push_str(":\n");
+            out.push_str(&pretty(result));
+            return Some(out);
+        }
+        if let Some(e) = v.get("error") {
+            self.describe_reply(id.unwrap_or(&Value::Null), &mut out);
+            out.push_str(" error");
+            if let Some(code) = e.get("code") {
+                out.push_str(&format!(" {}", code));
+            }
+            if let Some(msg) = e.get("message").and_then(|x| x.as_str()) {
+                out.push_str(&format!(": {}", msg));
+            }
+            if let Some(data) = e.get("data") {
+                out.push('\n');
+                out.push_str(&pretty(data));
+            }
+            return Some(out);
+        }
+        None
+    }
+
+    fn format_message(&self, buf: &[u8]) -> Option<String> {
+        let v: Value = serde_json::from_slice(buf).ok()?;
+        match v {
+            Value::Array(ref a) if !a.is_empty() => {
+                let parts: Option<Vec<String>> = a.iter().map(|x| self.format(x)).collect();
+                parts.map(|x| x.join("\n"))
+            }
+            ref x => self.format(x),
+        }
+    }
+
+    fn write_pending(&mut self) -> Result<(), IoError> {
+        while !self.pending.is_empty() {
+            let n = self.inner.write(&self.pending[0])?;
+            if n < self.pending[0].len() {
+                drop(self.pending[0].drain(..n));
+                continue;
+            }
+            self.pending.remove(0);
+        }
+        Ok(())
+    }
+}
+
+impl Write for JsonRpcReplyWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.write_pending()?;
+        let formatted = if self.raw { None } else { self.format_message(buf) };
+        match formatted {
+            Some(text) => {
+                for line in text.split('\n') {
+                    let mut line = line.as_bytes().to_vec();
+                    line.push(b'\n');
+                    self.pending.push(line);
+                }
+            }
+            None => {
+                // Still forget the request if the reply is shown as is
+                if let Ok(v) = serde_json::from_slice::<Value>(buf) {
+                    if let Some(id) = v.get("id") {
+                        self.state.borrow_mut().take(id);
+                    }
+                }
+                return self.inner.write(buf);
+            }
+        }
+        match self.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+            Ok(()) => (),
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+impl AsyncWrite for JsonRpcReplyWriter {
+    fn shutdown(&mut self) -> futures::Poll<(), IoError> {
+        try_nb!(self.write_pending());
+        self.inner.shutdown()
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonRpcServer<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for JsonRpcServer<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let grow_limit = if cp.program_options.whole_messages {
+            Some(cp.program_options.max_message_size)
+        } else {
+            None
+        };
+        let buffer_size = cp.program_options.buffer_size;
+        let max_message_size = cp.program_options.max_message_size;
+        // Handler gets one process per call, and replies must come in order
+        let mut opts = (*cp.program_options).clone();
+        opts.exec_per_message = true;
+        opts.exec_per_message_report = true;
+        opts.exec_max_parallel = 1;
+        opts.whole_messages = true;
+        let cp2 = ConstructParams {
+            program_options: Rc::new(opts),
+            ..cp
+        };
+        let inner = self.0.construct(cp2);
+        inner.map(move |p, _| jsonrpc_server_peer(p, buffer_size, max_message_size, grow_limit))
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = JsonRpcServerClass,
+    target = JsonRpcServer,
+    prefixes = ["jsonrpc-server:", "jsonrpc-serve:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+[A] Serve JSON-RPC 2.0 requests using a command as a handler. Reverse of `jsonrpc:`.
+
+Each call from a request (or a batch) is passed to the underlying `exec:`,
+`cmd:` or `sh-c:` specifier as a message like `method [1,2]\n`
+(params as compact JSON, `[]` if absent). A new process is started for each call,
+as with --exec-per-message, one at a time.
+
+Exit code 0 means success: stdout becomes the result (parsed as JSON if possible,
+otherwise a string with trailing newline removed; empty output means `null`).
+Otherwise error -32000 is replied, with stderr (or exit status) as the message.
+Malformed requests are answered with standard errors without running the handler.
+Nothing is replied to notifications, but they are still passed to the handler.
+
+Example: expose a script as JSON-RPC service
+
+    websocat -E ws-l:127.0.0.1:8080 jsonrpc-server:sh-c:'read m p; echo "{\"m\":\"$m\",\"p\":$p}"'
+"#
+);
+
+pub fn jsonrpc_server_peer(
+    inner_peer: Peer,
+    buffer_size: usize,
+    max_message_size: usize,
+    grow_limit: Option<usize>,
+) -> BoxedNewPeerFuture {
+    let state = Rc::new(RefCell::new(JsonRpcServerState::default()));
+    let r = JsonRpcResponder {
+        inner: inner_peer.0,
+        state: state.clone(),
+        buf: vec![0; buffer_size],
+        max_message_size,
+        debt: ReadDebt(None, DebtHandling::Warn, ZeroMessagesHandling::Deliver, grow_limit),
+    };
+    let w = JsonRpcRequestWriter {
+        inner: inner_peer.1,
+        state,
+        pending: VecDeque::new(),
+    };
+    let thepeer = Peer::new(r, w, inner_peer.2);
+    Box::new(ok(thepeer)) as BoxedNewPeerFuture
+}
+
+/// Request waiting for a reply from the handler
+struct Expected {
+    /// `None` for notifications
+    id: Option<Value>,
+    batch: Option<u64>,
+}
+
+struct Batch {
+    remaining: usize,
+    responses: Vec<Value>,
+}
+
+#[derive(Default)]
+struct JsonRpcServerState {
+    expected: VecDeque<Expected>,
+    batches: HashMap<u64, Batch>,
+    next_batch: u64,
+    /// Responses ready to be sent to client
+    ready: VecDeque<Vec<u8>>,
+    reader: Option<Task>,
+}
+
+impl JsonRpcServerState {
+    fn respond(&mut self, v: Value) {
+        self.ready.push_back(v.to_string().into_bytes());
+        if let Some(t) = self.reader.take() {
+            t.notify();
+        }
+    }
+
+    /// Add a response for a call that was `dispatched` to the handler,
+    /// or an error response while the batch is still being dispatched
+    fn add_to_batch(&mut self, batch: u64, v: Value, dispatched: bool) {
+        let done = match self.batches.get_mut(&batch) {
+            Some(b) => {
+                b.responses.push(v);
+                if dispatched {
+                    b.remaining -= 1;
+                }
+                dispatched && b.remaining == 0
+            }
+            None => return,
+        };
+        if done {
+            self.finish_batch(batch);
+        }
+    }
+
+    fn finish_batch(&mut self, batch: u64) {
+        if let Some(b) = self.batches.remove(&batch) {
+            if !b.responses.is_empty() {
+                self.respond(Value::Array(b.responses));
+            }
+        }
+    }
+}
+
+fn obj(members: Vec<(&str, Value)>) -> Value {
+    Value::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+fn result_response(id: Value, result: Value) -> Value {
+    obj(vec![
+        ("jsonrpc", Value::String("2.0".to_string())),
+        ("id", id),
+        ("result", result),
+    ])
+}
+
+fn error_response(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut e = vec![
+        ("code", Value::from(code)),
+        ("message", Value::String(message.to_string())),
+    ];
+    if let Some(d) = data {
+        e.push(("data", d));
+    }
+    obj(vec![
+        ("jsonrpc", Value::String("2.0".to_string())),
+        ("id", id),
+        ("error", obj(e)),
+    ])
+}
+
+/// Handler output as a result value
+fn output_to_value(s: &str) -> Value {
+    let t = s.trim();
+    if t.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_str(t)
+        .unwrap_or_else(|_| Value::String(s.trim_end_matches(['\n', '\r']).to_string()))
+}
+
+/// Make a response from handler's reply, which is normally a `--exec-per-message-report` object
+fn reply_to_response(id: Value, reply: &[u8]) -> Value {
+    let text = String::from_utf8_lossy(reply);
+    let report = serde_json::from_str::<Value>(&text)
+        .ok()
+        .filter(|v| v.get("exit_code").is_some() && v.get("stdout").is_some());
+    let report = match report {
+        Some(x) => x,
+        None => return result_response(id, output_to_value(&text)),
+    };
+    let stdout = report.get("stdout").and_then(|x| x.as_str()).unwrap_or("");
+    let stderr = report.get("stderr").and_then(|x| x.as_str()).unwrap_or("");
+    let exit_code = report.get("exit_code").cloned().unwrap_or(Value::Null);
+    let timed_out = report.get("timed_out") == Some(&Value::Bool(true));
+    if exit_code.as_u64() == Some(0) && !timed_out {
+        return result_response(id, output_to_value(stdout));
+    }
+    let message = if !stderr.trim().is_empty() {
+        stderr.trim().to_string()
+    } else if timed_out {
+        "Timed out".to_string()
+    } else if exit_code == Value::Null {
+        "Handler was terminated".to_string()
+    } else {
+        format!("Exit code {}", exit_code)
+    };
+    let data = obj(vec![
+        ("exit_code", exit_code),
+        ("stdout", Value::String(stdout.to_string())),
+    ]);
+    error_response(id, -32000, &message, Some(data))
+}
+
+/// Accepts requests from client, passes calls to the handler
+struct JsonRpcRequestWriter {
+    inner: Box<dyn AsyncWrite>,
+    state: Rc<RefCell<JsonRpcServerState>>,
+    /// Calls not yet accepted by the handler, each written as a separate message
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl JsonRpcRequestWriter {
+    /// Queue a call for the handler. Returns error response for invalid requests.
+    fn call(&mut self, v: &Value, batch: Option<u64>) -> Option<Value> {
+        let id = match v.get("id") {
+            None => None,
+            Some(x @ Value::Null) | Some(x @ Value::Number(_)) | Some(x @ Value::String(_)) => {
+                Some(x.clone())
+            }
+            Some(_) => return Some(error_response(Value::Null, -32600, "Invalid Request", None)),
+        };
+        let invalid = |id: Option<Value>| {
+            Some(error_response(id.unwrap_or(Value::Null), -32600, "Invalid Request", None))
+        };
+        if v.get("jsonrpc").and_then(|x| x.as_str()) != Some("2.0") {
+            return invalid(id);
+        }
+        let method = match v.get("method").and_then(|x| x.as_str()) {
+            Some(x) => x,
+            None => return invalid(id),
+        };
+        let params = match v.get("params") {
+            None => Value::Array(vec![]),
+            Some(x @ Value::Array(_)) | Some(x @ Value::Object(_)) => x.clone(),
+            Some(_) => return invalid(id),
+        };
+        if method.is_empty() || method.contains(char::is_whitespace) {
+            // Can't be passed to handler unambiguously
+            return id.map(|id| error_response(id, -32601, "Method not found", None));
+        }
+        self.pending
+            .push_back(format!("{} {}\n", method, params).into_bytes());
+        self.state
+            .borrow_mut()
+            .expected
+            .push_back(Expected { id, batch });
+        None
+    }
+
+    fn handle_request(&mut self, buf: &[u8]) {
+        let v = serde_json::from_slice::<Value>(buf);
+        let v = match v {
             Ok(x) => x,
+            Err(e) => {
+                info!("Invalid JSON-RPC request: {}", e);
+                let r = error_response(Value::Null, -32700, "Parse error", None);
+                self.state.borrow_mut().respond(r);
+                return;
+            }
+        };
+        let calls = match v {
+            Value::Array(ref a) if a.is_empty() => {
+                let r = error_response(Value::Null, -32600, "Invalid Request", None);
+                self.state.borrow_mut().respond(r);
+                return;
+            }
+            Value::Array(a) => a,
+            x => {
+                if let Some(r) = self.call(&x, None) {
+                    self.state.borrow_mut().respond(r);
+                }
+                return;
+            }
+        };
+        let batch = {
+            let mut st = self.state.borrow_mut();
+            st.next_batch += 1;
+            let b = st.next_batch;
+            st.batches.insert(
+                b,
+                Batch {
+                    remaining: 0,
+                    responses: vec![],
+                },
+            );
+            b
+        };
+        for c in calls.iter() {
+            let expects_reply = c.get("id").is_some();
+            let r = self.call(c, if expects_reply { Some(batch) } else { None });
+            let mut st = self.state.borrow_mut();
+            match r {
+                Some(r) => st.add_to_batch(batch, r, false),
+                None if expects_reply => st.batches.get_mut(&batch).unwrap().remaining += 1,
+                None => (),
+            }
+        }
+        let mut st = self.state.borrow_mut();
+        if st.batches.get(&batch).is_some_and(|b| b.remaining == 0) {
+            st.finish_batch(batch);
+        }
+    }
+
+    fn write_pending(&mut self) -> Result<(), IoError> {
+        while let Some(m) = self.pending.front_mut() {
+            let n = self.inner.write(m)?;
+            if n < m.len() {
+                drop(m.drain(..n));
+                continue;
+            }
+            self.pending.pop_front();
+        }
+        Ok(())
+    }
+}
+
+impl Write for JsonRpcRequestWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.write_pending()?;
+        self.handle_request(buf);
+        match self.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
             Err(e) => return Err(e),
+            Ok(()) => (),
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+impl AsyncWrite for JsonRpcRequestWriter {
+    fn shutdown(&mut self) -> futures::Poll<(), IoError> {
+        try_nb!(self.write_pending());
+        self.inner.shutdown()
+    }
+}
+
+/// Turns handler's replies into responses to client
+struct JsonRpcResponder {
+    inner: Box<dyn AsyncRead>,
+    state: Rc<RefCell<JsonRpcServerState>>,
+    buf: Vec<u8>,
+    max_message_size: usize,
+    debt: ReadDebt,
+}
+
+impl JsonRpcResponder {
+    fn handle_reply(&mut self, n: usize) {
+        let mut st = self.state.borrow_mut();
+        let e = match st.expected.pop_front() {
+            Some(x) => x,
+            None => {
+                warn!("Unexpected output from JSON-RPC handler, ignoring it");
+                return;
+            }
         };
-        if n == 0 {
-            return Ok(0);
-        }
-        let mut method = Vec::with_capacity(20);
-        let mut params = Vec::with_capacity(20);
-        enum PS {
-            BeforeMethodName,
-            InsideMethodName,
-            AfterMethodName,
-            InsideParams,
-        }
-        let mut s = PS::BeforeMethodName;
-        for &c in b[..n].iter() {
-            match s {
-                PS::BeforeMethodName => {
-                    if c == b' ' || c == b'\t' || c == b'\n' {
-                        // ignore
-                    } else {
-                        method.push(c);
-                        s = PS::InsideMethodName;
-                    }
+        let id = match e.id {
+            Some(x) => x,
+            None => {
+                debug!("Discarding reply to a notification");
+                return;
+            }
+        };
+        let r = reply_to_response(id, &self.buf[..n]);
+        match e.batch {
+            Some(b) => st.add_to_batch(b, r, true),
+            None => st.respond(r),
+        }
+    }
+}
+
+impl Read for JsonRpcResponder {
+    fn read(&mut self, b: &mut [u8]) -> Result<usize, IoError> {
+        loop {
+            if let Some(ret) = self.debt.check_debt(b) {
+                return ret;
+            }
+            let ready = self.state.borrow_mut().ready.pop_front();
+            if let Some(m) = ready {
+                match self.debt.process_message(b, &m) {
+                    ProcessMessageResult::Return(x) => return x,
+                    ProcessMessageResult::Recurse => continue,
                 }
-                PS::InsideMethodName => {
-                    if c == b' ' || c == b'\t' || c == b'\n' {
-                        s = PS::AfterMethodName;
-                    } else {
-                        method.push(c);
+            }
+            match self.inner.read(&mut self.buf) {
+                Ok(0) => {
+                    let n = self.state.borrow().expected.len();
+                    if n > 0 {
+                        warn!("JSON-RPC handler finished with {} calls unanswered", n);
                     }
+                    return Ok(0);
                 }
-                PS::AfterMethodName => {
-                    if c == b' ' || c == b'\t' || c == b'\n' {
-                        // ignore
-                    } else {
-                        params.push(c);
-                        s = PS::InsideParams;
+                Ok(n) => self.handle_reply(n),
+                Err(ref e) if super::readdebt::is_empty_message(e) => self.handle_reply(0),
+                Err(ref e) if super::readdebt::requested_buffer_size(e).is_some() => {
+                    let n = super::readdebt::requested_buffer_size(e).unwrap();
+                    if n > self.max_message_size {
+                        return Err(super::simple_err(format!(
+                            "Reply from JSON-RPC handler is too big: {} bytes",
+                            n
+                        )));
                     }
+                    self.buf.resize(n, 0);
                 }
-                PS::InsideParams => {
-                    params.push(c);
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        self.state.borrow_mut().reader = Some(futures::task::current());
+                    }
+                    return Err(e);
                 }
             }
         }
+    }
+}
+impl AsyncRead for JsonRpcResponder {}
 
-        let mut bb = ::std::io::Cursor::new(b);
-        use std::io::Write;
-        //{"jsonrpc":"2.0","id":412, "method":"abc", "params":[1,2]}
-        let _ = bb.write_all(b"{\"jsonrpc\":\"2.0\",\"id\":");
-        let _ = bb.write_all(format!("{}", self.1).as_bytes());
-        self.1 += 1;
-        let _ = bb.write_all(b", \"method\":\"");
-        let _ = bb.write_all(&method);
-        let _ = bb.write_all(b"\", \"params\":");
-        let needs_brackets = params.is_empty() || params[0] != b'{' && params[0] != b'[';
-        if !params.is_empty() && params[params.len() - 1] == b'\n' {
-            let l = params.len() - 1;
-            params.truncate(l);
-        }
-        if !params.is_empty() && params[params.len() - 1] == b'\r' {
-            let l = params.len() - 1;
-            params.truncate(l);
-        }
-        if needs_brackets {
-            let _ = bb.write_all(b"[");
-        }
-        let _ = bb.write_all(&params);
-        if needs_brackets {
-            let _ = bb.write_all(b"]");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(s: &str) -> String {
+        parse_params(s).unwrap().to_string()
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(p(""), "[]");
+        assert_eq!(p("[1, 2]"), "[1,2]");
+        assert_eq!(p("1,2"), "[1,2]");
+        assert_eq!(p(r#"{"a": 1}"#), r#"{"a":1}"#);
+        assert_eq!(p(r#"1 x 'y z' "3" [4]"#), r#"[1,"x","y z","3",[4]]"#);
+        assert_eq!(p(r#"a=1 b=x c="q w" d=e=f"#), r#"{"a":1,"b":"x","c":"q w","d":"e=f"}"#);
+        assert!(parse_params(r#"{"a":1"#).is_err());
+        assert!(parse_params("a=1 2").is_err());
+        assert!(parse_params("'x").is_err());
+        assert!(parse_params("a=1 a=2").is_err());
+    }
+
+    fn reply_writer() -> JsonRpcReplyWriter {
+        let mut state = JsonRpcState::default();
+        for (id, method) in &[("1", "abc"), ("\"x\"", "str")] {
+            let o = Outstanding {
+                method: method.to_string(),
+                sent: Instant::now(),
+            };
+            state.outstanding.insert(id.to_string(), o);
         }
-        let _ = bb.write_all(b"}\n");
-        if bb.position() as usize == l {
-            warn!("Buffer too small, JSON RPC message may be truncated.");
+        let o = Outstanding {
+            method: "slow".to_string(),
+            sent: Instant::now(),
+        };
+        state.timed_out.push_back(("2".to_string(), o));
+        JsonRpcReplyWriter {
+            inner: Box::new(std::io::Cursor::new(vec![])),
+            state: Rc::new(RefCell::new(state)),
+            raw: false,
+            pending: vec![],
         }
-        Ok(bb.position() as usize)
+    }
+
+    fn f(w: &JsonRpcReplyWriter, s: &str) -> Option<String> {
+        w.format_message(s.as_bytes())
+    }
+
+    #[test]
+    fn format() {
+        let w = reply_writer();
+        let r = f(&w, r#"{"jsonrpc":"2.0","id":1,"result":[3]}"#).unwrap();
+        assert!(r.starts_with("#1 abc ("), "{}", r);
+        assert!(r.ends_with(" ms):\n[\n  3\n]"), "{}", r);
+        let r = f(&w, r#"{"jsonrpc":"2.0","id":1,"result":null}"#).unwrap();
+        assert_eq!(r, "#1 (unknown request):\nnull");
+        let r = f(&w, r#"{"id":"x","error":{"code":-32601,"message":"Method not found"}}"#).unwrap();
+        assert!(r.starts_with("#\"x\" str ("), "{}", r);
+        assert!(r.ends_with(" ms) error -32601: Method not found"), "{}", r);
+        let r = f(&w, r#"{"id":2,"result":1}"#).unwrap();
+        assert!(r.ends_with(" ms, late):\n1"), "{}", r);
+        assert!(w.state.borrow().outstanding.is_empty());
+        assert!(w.state.borrow().timed_out.is_empty());
+        let r = f(&w, r#"{"id":null,"error":{"code":-32700,"message":"Parse error","data":"x"}}"#);
+        assert_eq!(r.unwrap(), "#null (unknown request) error -32700: Parse error\n\"x\"");
+    }
+
+    #[test]
+    fn format_message() {
+        let w = reply_writer();
+        assert_eq!(f(&w, r#"{"method":"tick"}"#).unwrap(), "notification tick");
+        assert_eq!(
+            f(&w, r#"{"method":"ask","id":7,"params":[1]}"#).unwrap(),
+            "request #7 ask:\n[\n  1\n]"
+        );
+        assert_eq!(
+            f(&w, r#"[{"method":"a"},{"method":"b"}]"#).unwrap(),
+            "notification a\nnotification b"
+        );
+        assert_eq!(f(&w, "hello"), None);
+        assert_eq!(f(&w, "[]"), None);
+        assert_eq!(f(&w, r#"{"x":1}"#), None);
+        assert_eq!(f(&w, r#"[{"method":"a"},2]"#), None);
+    }
+
+    fn v(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    fn report(exit_code: &str, stdout: &str, stderr: &str, timed_out: bool) -> Vec<u8> {
+        let r = json!({
+            "stdout": stdout,
+            "stderr": stderr,
+            "exit_code": v(exit_code),
+            "timed_out": timed_out,
+        });
+        r.to_string().into_bytes()
+    }
+
+    #[test]
+    fn responses() {
+        let id = || json!(5);
+        let r = |x: &[u8]| reply_to_response(id(), x).to_string();
+        assert_eq!(r(&report("0", "{\"a\": 1}\n", "", false)), r#"{"jsonrpc":"2.0","id":5,"result":{"a":1}}"#);
+        assert_eq!(r(&report("0", "text\n", "x", false)), r#"{"jsonrpc":"2.0","id":5,"result":"text"}"#);
+        assert_eq!(r(&report("0", "", "", false)), r#"{"jsonrpc":"2.0","id":5,"result":null}"#);
+        assert_eq!(r(b"not a report\n"), r#"{"jsonrpc":"2.0","id":5,"result":"not a report"}"#);
+        let e = |x: Vec<u8>| {
+            let resp = reply_to_response(id(), &x);
+            assert_eq!(resp["error"]["code"], json!(-32000));
+            resp["error"]["message"].as_str().unwrap().to_string()
+        };
+        assert_eq!(e(report("3", "", "bad thing\n", false)), "bad thing");
+        assert_eq!(e(report("3", "", "", false)), "Exit code 3");
+        assert_eq!(e(report("null", "", "", true)), "Timed out");
+        assert_eq!(e(report("null", "", "", false)), "Handler was terminated");
+        let resp = reply_to_response(id(), &report("2", "out", "", false));
+        assert_eq!(resp["error"]["data"], json!({"exit_code": 2, "stdout": "out"}));
+    }
+
+    fn server() -> (JsonRpcRequestWriter, JsonRpcResponder) {
+        let state = Rc::new(RefCell::new(JsonRpcServerState::default()));
+        let w = JsonRpcRequestWriter {
+            inner: Box::new(std::io::Cursor::new(vec![])),
+            state: state.clone(),
+            pending: VecDeque::new(),
+        };
+        let r = JsonRpcResponder {
+            inner: Box::new(std::io::Cursor::new(vec![])),
+            state,
+            buf: vec![0; 1024],
+            max_message_size: 1024,
+            debt: ReadDebt(None, DebtHandling::Warn, ZeroMessagesHandling::Deliver, None),
+        };
+        (w, r)
+    }
+
+    /// Pass the reply to the oldest call
+    fn reply(r: &mut JsonRpcResponder, text: &str) {
+        r.buf[..text.len()].copy_from_slice(text.as_bytes());
+        r.handle_reply(text.len());
+    }
+
+    fn ready(w: &JsonRpcRequestWriter) -> Vec<String> {
+        let mut st = w.state.borrow_mut();
+        st.ready.drain(..).map(|x| String::from_utf8(x).unwrap()).collect()
+    }
+
+    fn invalid(id: &str) -> String {
+        format!(r#"{{"jsonrpc":"2.0","id":{},"error":{{"code":-32600,"message":"Invalid Request"}}}}"#, id)
+    }
+
+    #[test]
+    fn call() {
+        let (mut w, _) = server();
+        let mut c = |s: &str| w.call(&v(s), None).map(|x| x.to_string());
+        assert_eq!(c(r#"{"jsonrpc":"2.0","id":1,"method":"m","params":{"a":1}}"#), None);
+        assert_eq!(c(r#"{"jsonrpc":"2.0","method":"n"}"#), None);
+        assert_eq!(c(r#"{"jsonrpc":"2.0","id":{},"method":"m"}"#), Some(invalid("null")));
+        assert_eq!(c(r#"{"id":2,"method":"m"}"#), Some(invalid("2")));
+        assert_eq!(c(r#"{"jsonrpc":"2.0","id":"x","method":3}"#), Some(invalid(r#""x""#)));
+        assert_eq!(c(r#"{"jsonrpc":"2.0","id":3,"method":"m","params":1}"#), Some(invalid("3")));
+        let r = c(r#"{"jsonrpc":"2.0","id":4,"method":"a b"}"#).unwrap();
+        assert!(r.contains(r#""id":4,"error":{"code":-32601"#), "{}", r);
+        assert_eq!(c(r#"{"jsonrpc":"2.0","method":""}"#), None);
+        assert_eq!(w.pending, vec![b"m {\"a\":1}\n".to_vec(), b"n []\n".to_vec()]);
+        let st = w.state.borrow();
+        let ids: Vec<_> = st.expected.iter().map(|x| x.id.clone()).collect();
+        assert_eq!(ids, vec![Some(json!(1)), None]);
+    }
+
+    #[test]
+    fn requests() {
+        let (mut w, mut r) = server();
+        w.handle_request(b"{");
+        assert_eq!(
+            ready(&w),
+            vec![r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32700,"message":"Parse error"}}"#]
+        );
+        w.handle_request(b"[]");
+        assert_eq!(ready(&w), vec![invalid("null")]);
+        w.handle_request(br#"[1, {"jsonrpc":"2.0"}]"#);
+        assert_eq!(ready(&w), vec![format!("[{},{}]", invalid("null"), invalid("null"))]);
+        assert!(w.state.borrow().batches.is_empty());
+
+        w.handle_request(br#"[{"jsonrpc":"2.0","method":"a"},{"jsonrpc":"2.0","method":"b"}]"#);
+        assert!(w.state.borrow().batches.is_empty());
+        reply(&mut r, "ignored");
+        reply(&mut r, "ignored");
+        assert!(ready(&w).is_empty());
+
+        w.handle_request(br#"{"jsonrpc":"2.0","id":7,"method":"single"}"#);
+        reply(&mut r, "one");
+        assert_eq!(ready(&w), vec![r#"{"jsonrpc":"2.0","id":7,"result":"one"}"#]);
+
+        let batch = br#"[
+            {"jsonrpc":"2.0","id":1,"method":"x"},
+            {"jsonrpc":"2.0","method":"notify"},
+            {"id":2},
+            {"jsonrpc":"2.0","id":3,"method":"y"}
+        ]"#;
+        w.handle_request(batch);
+        reply(&mut r, "first");
+        reply(&mut r, "ignored");
+        assert!(ready(&w).is_empty());
+        reply(&mut r, "third");
+        assert_eq!(
+            ready(&w),
+            vec![format!(
+                "[{},{},{}]",
+                invalid("2"),
+                r#"{"jsonrpc":"2.0","id":1,"result":"first"}"#,
+                r#"{"jsonrpc":"2.0","id":3,"result":"third"}"#
+            )]
+        );
+        assert!(w.state.borrow().batches.is_empty());
+        assert!(w.state.borrow().expected.is_empty());
     }
 }
-impl AsyncRead for JsonRpcWrapper {}
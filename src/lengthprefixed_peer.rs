@@ -0,0 +1,352 @@
+use futures::future::ok;
+
+use std::rc::Rc;
+
+use super::{BoxedNewPeerFuture, Peer};
+use super::{ConstructParams, PeerConstructor, Specifier};
+
+use std::io::{Error as IoError, Read};
+use tokio_io::AsyncRead;
+
+use super::framing_peer::{FrameEncoder, FramedWriter};
+
+#[derive(Debug)]
+pub struct LengthPrefixed<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for LengthPrefixed<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let opts = &cp.program_options;
+        let prefix = if opts.lengthprefixed_varint {
+            LengthPrefix::Varint
+        } else {
+            LengthPrefix::Fixed {
+                nbytes: opts.lengthprefixed_nbytes,
+                little_endian: opts.lengthprefixed_little_endian,
+            }
+        };
+        let strict = opts.linemode_strict;
+        let grow_limit = if opts.whole_messages {
+            Some(opts.max_message_size)
+        } else {
+            None
+        };
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, _| lengthprefixed_peer(p, prefix, strict, grow_limit))
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = LengthPrefixedClass,
+    target = LengthPrefixed,
+    prefixes = ["lengthprefixed:", "length-prefixed:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Turn stream of length-prefixed frames into messages and back. [A]
+
+Each message written is sent as its length followed by the message itself.
+Incoming byte stream is split into messages according to such lengths.
+Unlike `line2msg:`, arbitrary binary messages survive the trip unchanged.
+
+Length is 4-byte big-endian by default.
+Use --lengthprefixed-nbytes (1, 2, 4 or 8), --lengthprefixed-little-endian
+or --lengthprefixed-varint (LEB128, as in Protocol Buffers) to change that.
+
+Messages longer than buffer size (-B) and --max-message-size are split,
+or dropped in --strict mode.
+
+Example: transfer binary WebSocket messages over TCP without losing boundaries
+
+    websocat -b ws-l:127.0.0.1:8080 lengthprefixed:tcp:127.0.0.1:1234
+"#
+);
+
+#[derive(Debug, Clone, Copy)]
+pub enum LengthPrefix {
+    Fixed { nbytes: usize, little_endian: bool },
+    Varint,
+}
+
+const MAX_VARINT_LEN: usize = 10;
+
+impl LengthPrefix {
+    fn encode_length(self, len: usize, out: &mut Vec<u8>) -> Result<(), String> {
+        let len = len as u64;
+        match self {
+            LengthPrefix::Fixed {
+                nbytes,
+                little_endian,
+            } => {
+                if nbytes < 8 && len >> (8 * nbytes) != 0 {
+                    return Err(format!(
+                        "Message of {} bytes does not fit in {}-byte length prefix",
+                        len, nbytes
+                    ));
+                }
+                let b = if little_endian {
+                    len.to_le_bytes()
+                } else {
+                    len.to_be_bytes()
+                };
+                if little_endian {
+                    out.extend_from_slice(&b[..nbytes]);
+                } else {
+                    out.extend_from_slice(&b[8 - nbytes..]);
+                }
+            }
+            LengthPrefix::Varint => {
+                let mut x = len;
+                loop {
+                    let c = (x & 0x7F) as u8;
+                    x >>= 7;
+                    if x == 0 {
+                        out.push(c);
+                        break;
+                    }
+                    out.push(c | 0x80);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns header length and message length, or `None` if more bytes are needed
+    fn decode(self, buf: &[u8]) -> Result<Option<(usize, u64)>, String> {
+        match self {
+            LengthPrefix::Fixed {
+                nbytes,
+                little_endian,
+            } => {
+                if buf.len() < nbytes {
+                    return Ok(None);
+                }
+                let mut b = [0u8; 8];
+                let len = if little_endian {
+                    b[..nbytes].copy_from_slice(&buf[..nbytes]);
+                    u64::from_le_bytes(b)
+                } else {
+                    b[8 - nbytes..].copy_from_slice(&buf[..nbytes]);
+                    u64::from_be_bytes(b)
+                };
+                Ok(Some((nbytes, len)))
+            }
+            LengthPrefix::Varint => {
+                let mut len = 0u64;
+                for (i, &c) in buf.iter().enumerate().take(MAX_VARINT_LEN) {
+                    len |= u64::from(c & 0x7F) << (7 * i);
+                    if c & 0x80 == 0 {
+                        return Ok(Some((i + 1, len)));
+                    }
+                }
+                if buf.len() >= MAX_VARINT_LEN {
+                    return Err("Invalid varint length prefix".to_string());
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl FrameEncoder for LengthPrefix {
+    fn encode(&self, msg: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        self.encode_length(msg.len(), out)?;
+        out.extend_from_slice(msg);
+        Ok(())
+    }
+}
+
+pub fn lengthprefixed_peer(
+    inner_peer: Peer,
+    prefix: LengthPrefix,
+    strict: bool,
+    grow_limit: Option<usize>,
+) -> BoxedNewPeerFuture {
+    let r = LengthPrefixedReader {
+        inner: inner_peer.0,
+        prefix,
+        queue: vec![],
+        continuation: 0,
+        drop_too_long: strict,
+        grow_limit,
+    };
+    let w = FramedWriter::new(inner_peer.1, prefix);
+    let thepeer = Peer::new(r, w, inner_peer.2);
+    Box::new(ok(thepeer)) as BoxedNewPeerFuture
+}
+
+struct LengthPrefixedReader {
+    inner: Box<dyn AsyncRead>,
+    prefix: LengthPrefix,
+    queue: Vec<u8>,
+    /// Remaining bytes of a message which is too long to be delivered at once
+    continuation: u64,
+    drop_too_long: bool,
+    /// Ask for a bigger buffer instead of splitting messages up to this size
+    grow_limit: Option<usize>,
+}
+
+impl LengthPrefixedReader {
+    /// Deliver (or skip) bytes of a too long message
+    fn continue_long_message(&mut self, buf: &mut [u8]) -> Result<Option<usize>, IoError> {
+        let lim = (self.continuation.min(buf.len() as u64)) as usize;
+        let n = if !self.queue.is_empty() {
+            let n = lim.min(self.queue.len());
+            buf[..n].copy_from_slice(&self.queue[..n]);
+            drop(self.queue.drain(..n));
+            n
+        } else {
+            let n = self.inner.read(&mut buf[..lim])?;
+            if n == 0 {
+                warn!("Incomplete message at the end of stream");
+                return Ok(Some(0));
+            }
+            n
+        };
+        self.continuation -= n as u64;
+        if self.drop_too_long {
+            Ok(None)
+        } else {
+            Ok(Some(n))
+        }
+    }
+}
+
+impl Read for LengthPrefixedReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        loop {
+            if self.continuation > 0 {
+                match self.continue_long_message(buf)? {
+                    Some(n) => return Ok(n),
+                    None => continue,
+                }
+            }
+            if let Some((h, len)) = self.prefix.decode(&self.queue).map_err(crate::simple_err)? {
+                if len == 0 {
+                    drop(self.queue.drain(..h));
+                    return Err(crate::readdebt::empty_message());
+                }
+                if len > buf.len() as u64 && self.grow_limit.is_some_and(|x| len <= x as u64) {
+                    let l = len as usize;
+                    return Err(crate::util::io_other_error(crate::readdebt::MessageTooLong(l)));
+                }
+                if len > buf.len() as u64 {
+                    if self.drop_too_long {
+                        error!("Dropping too long message of {} bytes because of buffer (-B option) is only {} bytes", len, buf.len());
+                    } else {
+                        warn!("Splitting too long message of {} bytes because of buffer (-B option) is only {} bytes", len, buf.len());
+                    }
+                    drop(self.queue.drain(..h));
+                    self.continuation = len;
+                    continue;
+                }
+                let len = len as usize;
+                if self.queue.len() >= h + len {
+                    buf[..len].copy_from_slice(&self.queue[h..h + len]);
+                    drop(self.queue.drain(..h + len));
+                    return Ok(len);
+                }
+            }
+            // `buf` is used as a temporary space here
+            let n = self.inner.read(buf)?;
+            if n == 0 {
+                if !self.queue.is_empty() {
+                    warn!("Throwing away {} bytes of incomplete message", self.queue.len());
+                }
+                return Ok(0);
+            }
+            self.queue.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+impl AsyncRead for LengthPrefixedReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIXES: [LengthPrefix; 7] = [
+        LengthPrefix::Varint,
+        LengthPrefix::Fixed { nbytes: 1, little_endian: false },
+        LengthPrefix::Fixed { nbytes: 2, little_endian: false },
+        LengthPrefix::Fixed { nbytes: 2, little_endian: true },
+        LengthPrefix::Fixed { nbytes: 4, little_endian: false },
+        LengthPrefix::Fixed { nbytes: 4, little_endian: true },
+        LengthPrefix::Fixed { nbytes: 8, little_endian: true },
+    ];
+
+    fn encoded(p: LengthPrefix, msg: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        p.encode(msg, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn header_bytes() {
+        let be2 = LengthPrefix::Fixed { nbytes: 2, little_endian: false };
+        let le4 = LengthPrefix::Fixed { nbytes: 4, little_endian: true };
+        assert_eq!(encoded(be2, b"abc"), b"\x00\x03abc");
+        assert_eq!(encoded(le4, b"abc"), b"\x03\x00\x00\x00abc");
+        assert_eq!(encoded(LengthPrefix::Varint, &[7; 300])[..2], [0xAC, 0x02]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        for &p in &PREFIXES {
+            for &len in &[0usize, 1, 127, 128, 255, 300] {
+                if let LengthPrefix::Fixed { nbytes: 1, .. } = p {
+                    if len > 255 {
+                        continue;
+                    }
+                }
+                let msg = vec![b'x'; len];
+                let e = encoded(p, &msg);
+                let (h, l) = p.decode(&e).unwrap().unwrap();
+                assert_eq!(l, len as u64, "{:?}", p);
+                assert_eq!(&e[h..], &msg[..]);
+            }
+        }
+        let mut out = vec![];
+        LengthPrefix::Varint.encode_length(usize::MAX, &mut out).unwrap();
+        assert_eq!(LengthPrefix::Varint.decode(&out), Ok(Some((10, usize::MAX as u64))));
+    }
+
+    #[test]
+    fn truncated() {
+        for &p in &PREFIXES {
+            let e = encoded(p, &[1; 200]);
+            let h = p.decode(&e).unwrap().unwrap().0;
+            for i in 0..h {
+                assert_eq!(p.decode(&e[..i]), Ok(None), "{:?}", p);
+            }
+        }
+        assert!(LengthPrefix::Varint.decode(&[0xFF; 10]).is_err());
+        let be1 = LengthPrefix::Fixed { nbytes: 1, little_endian: false };
+        assert!(be1.encode(&[0; 256], &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn reader() {
+        let p = LengthPrefix::Fixed { nbytes: 2, little_endian: false };
+        let mut data = encoded(p, b"hello");
+        data.extend(encoded(p, b""));
+        data.extend(encoded(p, b"world"));
+        data.extend(&encoded(p, b"incomplete")[..6]);
+        let mut r = LengthPrefixedReader {
+            inner: Box::new(std::io::Cursor::new(data)),
+            prefix: p,
+            queue: vec![],
+            continuation: 0,
+            drop_too_long: false,
+            grow_limit: None,
+        };
+        let mut buf = [0; 16];
+        assert_eq!(r.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert!(crate::readdebt::is_empty_message(&r.read(&mut buf).unwrap_err()));
+        assert_eq!(r.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"world");
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+    }
+}
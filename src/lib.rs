@@ -30,6 +30,7 @@ pub use http_bytes::http;
 
 extern crate tk_listen;
 extern crate net2;
+extern crate socket2;
 
 #[macro_use]
 extern crate log;
@@ -64,10 +65,15 @@ impl WebsocatConfiguration1 {
     /// Is allowed to call blocking calls
     /// happens only at start of websocat
     pub fn parse1(self) -> Result<WebsocatConfiguration2> {
+        let mut sni_routes = vec![];
+        for (name, spec) in &self.opts.sni_routes {
+            sni_routes.push((name.clone(), SpecifierStack::from_str(spec)?));
+        }
         Ok(WebsocatConfiguration2 {
             opts: self.opts,
             s1: SpecifierStack::from_str(self.addr1.as_str())?,
             s2: SpecifierStack::from_str(self.addr2.as_str())?,
+            sni_routes,
         })
     }
 }
@@ -83,14 +89,24 @@ pub struct WebsocatConfiguration2 {
     pub opts: Options,
     pub s1: SpecifierStack,
     pub s2: SpecifierStack,
+    /// Alternative right side specifiers from `--sni-route`, linted like `s2`
+    pub sni_routes: Vec<(String, SpecifierStack)>,
 }
 
 impl WebsocatConfiguration2 {
     pub fn parse2(self) -> Result<WebsocatConfiguration3> {
+        #[allow(unused_mut)]
+        let mut s2 = Specifier::from_stack(&self.s2)?;
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+        {
+            if !self.sni_routes.is_empty() {
+                s2 = ssl_peer::SniRoute::wrap(s2, &self.sni_routes)?;
+            }
+        }
         Ok(WebsocatConfiguration3 {
             opts: self.opts,
             s1: Specifier::from_stack(&self.s1)?,
-            s2: Specifier::from_stack(&self.s2)?,
+            s2,
         })
     }
 }
@@ -110,6 +126,17 @@ impl WebsocatConfiguration3 {
     {
         serve(self.s1, self.s2, self.opts, onerror)
     }
+
+    /// Resolves to exit code of `exec:` process when serving only one connection
+    pub fn serve_with_exit_code<OE>(
+        self,
+        onerror: std::rc::Rc<OE>,
+    ) -> impl Future<Item = Option<i32>, Error = ()>
+    where
+        OE: Fn(Box<dyn std::error::Error>) + 'static,
+    {
+        serve_with_exit_code(self.s1, self.s2, self.opts, onerror)
+    }
 }
 
 pub mod options;
@@ -130,6 +157,10 @@ pub struct LeftSpecToRightSpec {
     client_addr: Option<String>,
     /// All incoming HTTP headers
     headers: Vec<(String, String)>,
+    /// Server name (SNI) requested by TLS client
+    tls_server_name: Option<String>,
+    /// Local address:port the client connected to, if it is TCP
+    server_addr: Option<String>,
 }
 
 pub type L2rWriter = Rc<RefCell<LeftSpecToRightSpec>>;
@@ -144,7 +175,22 @@ pub enum L2rUser {
 /// Resolves if/when TCP socket gets reset
 pub type HupToken = Box<dyn Future<Item=(), Error=Box<dyn std::error::Error>>>;
 
-pub struct Peer(Box<dyn AsyncRead>, Box<dyn AsyncWrite>, Option<HupToken>);
+/// Out-of-band notification for a peer, as opposed to a message written to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerEvent {
+    /// One of `reuse-raw:` clients sharing this peer has disconnected
+    ClientDisconnected,
+}
+
+/// Receives `PeerEvent`s for peers which can react to them
+pub type EventSink = Rc<dyn Fn(PeerEvent)>;
+
+pub struct Peer(
+    Box<dyn AsyncRead>,
+    Box<dyn AsyncWrite>,
+    Option<HupToken>,
+    Option<EventSink>,
+);
 
 pub type BoxedNewPeerFuture = Box<dyn Future<Item = Peer, Error = Box<dyn std::error::Error>>>;
 pub type BoxedNewPeerStream = Box<dyn Stream<Item = Peer, Error = Box<dyn std::error::Error>>>;
@@ -180,6 +226,8 @@ pub mod http_peer;
 
 #[cfg(feature = "tokio-process")]
 pub mod process_peer;
+#[cfg(all(unix, feature = "tokio-process", feature = "pty"))]
+pub mod process_pty;
 
 
 #[cfg(all(windows,feature = "windows_named_pipes"))]
@@ -187,19 +235,36 @@ pub mod windows_np_peer;
 
 #[cfg(unix)]
 pub mod unix_peer;
+#[cfg(unix)]
+pub mod sd_listen_peer;
 
 pub mod broadcast_reuse_peer;
 pub mod jsonrpc_peer;
+pub mod transform_peer;
+#[cfg(feature = "jq")]
+pub mod jq_peer;
+#[cfg(feature = "filter")]
+pub mod filter_peer;
 pub mod line_peer;
+pub mod lengthprefixed_peer;
+pub mod framing_peer;
 pub mod foreachmsg_peer;
 pub mod primitive_reuse_peer;
 pub mod reconnect_peer;
 
 pub mod socks5_peer;
-#[cfg(feature = "ssl")]
+pub mod proxy_proto_peer;
+#[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
 pub mod ssl_peer;
+#[cfg(feature = "ssl")]
+pub mod native_tls_peer;
+#[cfg(all(feature = "ssl_rustls", not(feature = "ssl")))]
+pub mod rustls_peer;
 
 pub mod specparse;
+pub mod resolve;
+#[cfg(feature = "jq")]
+pub mod jq;
 
 pub type PeerOverlay = Rc<dyn Fn(Peer, L2rUser) -> BoxedNewPeerFuture>;
 
@@ -241,4 +306,4 @@ pub struct Session {
 }
 
 pub mod sessionserve;
-pub use crate::sessionserve::serve;
+pub use crate::sessionserve::{serve, serve_with_exit_code};
@@ -2,6 +2,7 @@ use futures::future::ok;
 
 use std::rc::Rc;
 
+use super::framing_peer::{FrameEncoder, FramedWriter};
 use super::{BoxedNewPeerFuture, Peer};
 use super::{ConstructParams, PeerConstructor, Specifier};
 
@@ -39,9 +40,7 @@ Reverse of the `line2msg:`.
 
 Unless --null-terminated, replaces both newlines (\x0A) and carriage returns (\x0D) with spaces (\x20) for each read.
 
-Does not affect writing at all. Use this specifier on both ends to get bi-directional behaviour.
-
-Automatically inserted by --line option on top of the stack containing a websocket.
+Does not affect writing at all. Use `line:` on the other end to get bi-directional behaviour.
 
 Example: TODO
 "#
@@ -54,8 +53,9 @@ impl<T: Specifier> Specifier for Line2Message<T> {
         let retain_newlines = !cp.program_options.linemode_strip_newlines;
         let strict = cp.program_options.linemode_strict;
         let nullt = cp.program_options.linemode_zero_terminated;
+        let grow_limit = grow_limit(&cp);
         let inner = self.0.construct(cp.clone());
-        inner.map(move |p, _| line2packet_peer(p, retain_newlines, strict, nullt))
+        inner.map(move |p, _| line2packet_peer(p, retain_newlines, strict, nullt, grow_limit))
     }
     specifier_boilerplate!(noglobalstate has_subspec);
     self_0_is_subspecifier!(proxy_is_multiconnect);
@@ -76,14 +76,115 @@ coming from underlying specifier, buffering up or splitting content as needed.
 
 Reverse of the `msg2line:`.
 
-Does not affect writing at all. Use this specifier on both ends to get bi-directional behaviour.
-
-Automatically inserted by --line option at the top of the stack opposite to websocket-containing stack.
+Does not affect writing at all. See `line:` for bi-directional version.
 
 Example: TODO
 "#
 );
 
+#[derive(Debug)]
+pub struct Line<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for Line<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let retain_newlines = !cp.program_options.linemode_strip_newlines;
+        let strict = cp.program_options.linemode_strict;
+        let nullt = cp.program_options.linemode_zero_terminated;
+        let grow_limit = grow_limit(&cp);
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, _| line_peer(p, retain_newlines, strict, nullt, grow_limit))
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = LineClass,
+    target = Line,
+    prefixes = ["line:"],
+    arg_handling = subspec,
+    overlay = true,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Line filter: turn lines from byte stream into messages and messages into lines. [A]
+
+Reading works like in `line2msg:`. Each written message gets inner newlines
+(and carriage returns) replaced by spaces and gets terminated by one newline,
+like in `msg2line:`. With --null-terminated, zero bytes are used instead.
+Lines longer than buffer size (-B) and --max-message-size are split,
+or dropped in --strict mode.
+
+Automatically inserted by --text mode on top of the stream-oriented stack
+when the other stack is message-oriented.
+
+Example: connect a line-based TCP service to WebSocket clients, one message per line
+
+    websocat -t ws-l:127.0.0.1:8080 line:tcp:127.0.0.1:1234
+"#
+);
+
+/// Lines up to this size are not split, but delivered whole with a bigger buffer
+fn grow_limit(cp: &ConstructParams) -> Option<usize> {
+    if cp.program_options.whole_messages {
+        Some(cp.program_options.max_message_size)
+    } else {
+        None
+    }
+}
+
+pub fn line_peer(
+    inner_peer: Peer,
+    retain_newlines: bool,
+    strict: bool,
+    null_terminated: bool,
+    grow_limit: Option<usize>,
+) -> BoxedNewPeerFuture {
+    let r = Line2PacketWrapper {
+        inner: inner_peer.0,
+        queue: vec![],
+        retain_newlines,
+        allow_incomplete_lines: !strict,
+        drop_too_long_lines: strict,
+        eof: false,
+        inner_eof: false,
+        null_terminated,
+        grow_limit,
+    };
+    let w = FramedWriter::new(inner_peer.1, LineEncoder { null_terminated });
+    let thepeer = Peer::new(r, w, inner_peer.2);
+    Box::new(ok(thepeer)) as BoxedNewPeerFuture
+}
+
+/// Write side of `line:`, does the same as `Packet2LineWrapper`
+struct LineEncoder {
+    null_terminated: bool,
+}
+
+impl FrameEncoder for LineEncoder {
+    fn encode(&self, msg: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        let mut msg = msg;
+        if self.null_terminated {
+            if let Some((&b'\x00', rest)) = msg.split_last() {
+                msg = rest;
+            }
+            if msg.contains(&b'\x00') {
+                warn!("zero byte in a message in null-terminated mode");
+            }
+            out.extend_from_slice(msg);
+            out.push(b'\x00');
+        } else {
+            if let Some((&b'\n', rest)) = msg.split_last() {
+                msg = rest;
+            }
+            if let Some((&b'\r', rest)) = msg.split_last() {
+                msg = rest;
+            }
+            out.extend(msg.iter().map(|&c| if c == b'\n' || c == b'\r' { b' ' } else { c }));
+            out.push(b'\n');
+        }
+        Ok(())
+    }
+}
+
 pub fn packet2line_peer(inner_peer: Peer, null_terminated: bool) -> BoxedNewPeerFuture {
     let filtered = Packet2LineWrapper(inner_peer.0, null_terminated);
     let thepeer = Peer::new(filtered, inner_peer.1, inner_peer.2);
@@ -96,12 +197,12 @@ impl Read for Packet2LineWrapper {
         let l = b.len();
         assert!(l > 1);
         let mut n = match self.0.read(&mut b[..(l - 1)]) {
+            Ok(0) => return Ok(0),
             Ok(x) => x,
+            // Empty message becomes empty line
+            Err(ref e) if crate::readdebt::is_empty_message(e) => 0,
             Err(e) => return Err(e),
         };
-        if n == 0 {
-            return Ok(n);
-        }
         if !self.1 {
             // newline-terminated
 
@@ -145,6 +246,7 @@ pub fn line2packet_peer(
     retain_newlines: bool,
     strict: bool,
     null_terminated: bool,
+    grow_limit: Option<usize>,
 ) -> BoxedNewPeerFuture {
     let filtered = Line2PacketWrapper {
         inner: inner_peer.0,
@@ -153,7 +255,9 @@ pub fn line2packet_peer(
         allow_incomplete_lines: !strict,
         drop_too_long_lines: strict,
         eof: false,
+        inner_eof: false,
         null_terminated,
+        grow_limit,
     };
     let thepeer = Peer::new(filtered, inner_peer.1, inner_peer.2);
     Box::new(ok(thepeer)) as BoxedNewPeerFuture
@@ -165,10 +269,22 @@ struct Line2PacketWrapper {
     allow_incomplete_lines: bool,
     drop_too_long_lines: bool,
     eof: bool,
+    /// Inner reader has ended, but the incomplete line in `queue` is not delivered yet
+    inner_eof: bool,
     null_terminated: bool,
+    /// Ask for a bigger buffer instead of splitting lines up to this size
+    grow_limit: Option<usize>,
 }
 
 impl Line2PacketWrapper {
+    fn too_long_error(&self, n: usize, buf: &[u8]) -> Option<IoError> {
+        if n > buf.len() && self.grow_limit.is_some_and(|x| n <= x) {
+            Some(crate::util::io_other_error(crate::readdebt::MessageTooLong(n)))
+        } else {
+            None
+        }
+    }
+
     #[cfg_attr(feature = "cargo-clippy", allow(collapsible_if))]
     fn deliver_the_line(&mut self, buf: &mut [u8], mut n: usize) -> Option<usize> {
         if n > buf.len() {
@@ -181,19 +297,23 @@ impl Line2PacketWrapper {
                 n = buf.len();
             }
         } else {
+            let line_len = n;
             if !self.retain_newlines && !self.null_terminated {
-                if n > 0 && (buf[n - 1] == b'\n') {
+                if n > 0 && (self.queue[n - 1] == b'\n') {
                     n -= 1
                 }
-                if n > 0 && (buf[n - 1] == b'\r') {
+                if n > 0 && (self.queue[n - 1] == b'\r') {
                     n -= 1
                 }
             }
             if self.null_terminated {
-                if n > 0 && (buf[n - 1] == b'\x00') {
+                if n > 0 && (self.queue[n - 1] == b'\x00') {
                     n -= 1
                 }
             }
+            buf[0..n].copy_from_slice(&self.queue[0..n]);
+            drop(self.queue.drain(0..line_len));
+            return Some(n);
         }
 
         buf[0..n].copy_from_slice(&self.queue[0..n]);
@@ -203,8 +323,18 @@ impl Line2PacketWrapper {
 }
 
 impl Read for Line2PacketWrapper {
-    #[cfg_attr(feature = "cargo-clippy", allow(collapsible_if))]
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        match self.read_line(buf) {
+            // Empty line after stripping the newline is not the end of stream
+            Ok(0) if !self.eof => Err(crate::readdebt::empty_message()),
+            r => r,
+        }
+    }
+}
+
+impl Line2PacketWrapper {
+    #[cfg_attr(feature = "cargo-clippy", allow(collapsible_if))]
+    fn read_line(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
         //eprint!("ql={} ", self.queue.len());
         if self.eof {
             return Ok(0);
@@ -222,19 +352,32 @@ impl Read for Line2PacketWrapper {
 
         if let Some(mut n) = queued_line_len {
             n += 1;
+            if let Some(e) = self.too_long_error(n, buf) {
+                return Err(e);
+            }
             if let Some(nn) = self.deliver_the_line(buf, n) {
                 Ok(nn)
             } else {
                 // line dropped, recursing
-                self.read(buf)
+                self.read_line(buf)
             }
         } else {
-            let mut n = match self.inner.read(buf) {
-                Ok(x) => x,
-                Err(e) => return Err(e),
+            let mut n = if self.inner_eof {
+                0
+            } else {
+                match self.inner.read(buf) {
+                    Ok(x) => x,
+                    Err(e) => return Err(e),
+                }
             };
 
             if n == 0 {
+                self.inner_eof = true;
+                if self.allow_incomplete_lines {
+                    if let Some(e) = self.too_long_error(self.queue.len(), buf) {
+                        return Err(e);
+                    }
+                }
                 self.eof = true;
                 if !self.queue.is_empty() {
                     if self.allow_incomplete_lines {
@@ -282,7 +425,7 @@ impl Read for Line2PacketWrapper {
                 // Just queue up and recurse
                 self.queue.extend_from_slice(&buf[0..n]);
                 //eprintln!(" recurse");
-                self.read(buf)
+                self.read_line(buf)
             }
         }
     }
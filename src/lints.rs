@@ -164,7 +164,7 @@ impl WebsocatConfiguration2 {
     }
 
     pub fn contains_class(&self, x: &'static str) -> bool {
-        self.s1.contains(x) || self.s2.contains(x)
+        self.s1.contains(x) || self.s2.contains(x) || self.sni_routes.iter().any(|r| r.1.contains(x))
     }
 
     pub fn get_exec_parameter(&self) -> Option<&str> {
@@ -233,27 +233,24 @@ impl WebsocatConfiguration2 {
     }
 
     fn l_linemode(&mut self) -> Result<()> {
-        if !self.opts.no_auto_linemode && self.opts.websocket_text_mode {
+        // In --exec-per-message mode processes already get and produce whole messages,
+        // with --exec-pty the terminal needs raw keystrokes
+        let raw_exec = (self.opts.exec_per_message || self.opts.exec_pty) && self.exec_used();
+        if !self.opts.no_auto_linemode && self.opts.websocket_text_mode && !raw_exec {
+            // Single bidirectional overlay on the stream side, so the message side
+            // is left intact even if it is shared by a reuser
             match (self.s1.is_stream_oriented(), self.s2.is_stream_oriented()) {
                 (false, false) => {}
                 (true, true) => {}
                 (true, false) => {
                     info!("Auto-inserting the line mode");
-                    self.s1.insert_line_class_in_proper_place(Rc::new(
-                        super::line_peer::Line2MessageClass,
-                    ));
-                    self.s2.insert_line_class_in_proper_place(Rc::new(
-                        super::line_peer::Message2LineClass,
-                    ));
+                    self.s1
+                        .insert_line_class_in_proper_place(Rc::new(super::line_peer::LineClass));
                 }
                 (false, true) => {
                     info!("Auto-inserting the line mode");
-                    self.s2.insert_line_class_in_proper_place(Rc::new(
-                        super::line_peer::Line2MessageClass,
-                    ));
-                    self.s1.insert_line_class_in_proper_place(Rc::new(
-                        super::line_peer::Message2LineClass,
-                    ));
+                    self.s2
+                        .insert_line_class_in_proper_place(Rc::new(super::line_peer::LineClass));
                 }
             }
         };
@@ -289,6 +286,32 @@ impl WebsocatConfiguration2 {
                 on_warning("Warning: you specified exec: without the corresponding --exec-args at the end of command line. Unlike in cmd: or sh-c:, spaces inside exec:'s direct parameter are interpreted as part of program name, not as separator.");
             }
         }
+
+        if self.opts.exec_pty && self.opts.exec_per_message {
+            Err("--exec-pty and --exec-per-message are mutually exclusive")?;
+        }
+        if self.opts.exec_pty && self.opts.exec_stderr != crate::options::ExecStderr::Inherit {
+            on_warning("--exec-stderr is ignored with --exec-pty, stderr goes to the terminal");
+        }
+        if self.opts.exec_restart != crate::options::ExecRestart::Never
+            && (self.opts.exec_per_message || self.opts.exec_pty)
+        {
+            on_warning("--exec-restart is not supported with --exec-per-message or --exec-pty");
+        }
+        if self.opts.exec_max_parallel == 0 {
+            Err("--exec-max-parallel must be at least 1")?;
+        }
+        if !self.opts.exec_per_message
+            && (self.opts.exec_timeout.is_some() || self.opts.exec_per_message_report)
+        {
+            on_warning("--exec-timeout and --exec-per-message-report are meaningless without --exec-per-message");
+        }
+        if self.opts.exec_per_message
+            && !self.opts.exec_per_message_report
+            && self.opts.exec_stderr == crate::options::ExecStderr::Message
+        {
+            on_warning("--exec-stderr message is logged in --exec-per-message mode, use --exec-per-message-report to send stderr");
+        }
         Ok(())
     }
     fn l_uri_staticfiles(&mut self, on_warning: &OnWarning) -> Result<()> {
@@ -324,7 +347,10 @@ impl WebsocatConfiguration2 {
             if !self.exec_used() {
                 on_warning("-e (--set-environment) is meaningless without a exec: or sh-c: or cmd: address");
             }
-            if !self.contains_class("TcpListenClass") && !self.contains_class("WsServerClass") {
+            if !self.contains_class("TcpListenClass")
+                && !self.contains_class("WsServerClass")
+                && !self.contains_class("UdpServerClass")
+            {
                 on_warning("-e (--set-environment) is currently meaningless without a websocket server and/or TCP listener");
             }
         }
@@ -358,7 +384,7 @@ impl WebsocatConfiguration2 {
         secure: bool,
     ) -> Result<()> {
         let url = if secure {
-            #[cfg(not(feature = "ssl"))]
+            #[cfg(not(any(feature = "ssl", feature = "ssl_rustls")))]
             {
                 Err("SSL support not compiled in")?;
             }
@@ -413,7 +439,7 @@ impl WebsocatConfiguration2 {
         s.overlays
             .push(SpecifierNode{cls: Rc::new(super::ws_client_peer::WsConnectClass)});
         if secure {
-            #[cfg(feature = "ssl")]
+            #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
             s.overlays.push(SpecifierNode{cls: Rc::new(super::ssl_peer::TlsConnectClass)});
         }
         s.overlays.push(SpecifierNode{cls: Rc::new(super::socks5_peer::SocksProxyClass)});
@@ -469,10 +495,64 @@ impl WebsocatConfiguration2 {
         Ok(())
     }
 
-    #[cfg(feature = "ssl")]
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
     fn l_ssl(&mut self, _on_warning: &OnWarning) -> Result<()> {
-        if self.contains_class("TlsAcceptClass") ^ self.opts.pkcs12_der.is_some() {
-            Err("SSL listerer and --pkcs12-der option should go together")?;
+        let tls_client_options_used = !self.opts.tls_ca_files.is_empty()
+            || self.opts.tls_no_system_roots
+            || !self.opts.tls_pin_cert_sha256.is_empty()
+            || !self.opts.tls_pin_pubkey_sha256.is_empty();
+        if tls_client_options_used
+            && !self.contains_class("TlsConnectClass")
+            && !self.contains_class("WsClientSecureClass")
+        {
+            _on_warning("--tls-ca, --tls-no-system-roots and --tls-pin-* options are only effective for wss:// or tls-connect:");
+        }
+        let tls_handshake_options_used = !self.opts.tls_alpn.is_empty()
+            || self.opts.tls_min_version.is_some()
+            || self.opts.tls_max_version.is_some();
+        if tls_handshake_options_used
+            && !self.contains_class("TlsConnectClass")
+            && !self.contains_class("WsClientSecureClass")
+            && !self.contains_class("TlsAcceptClass")
+        {
+            _on_warning("--tls-alpn, --tls-min-version and --tls-max-version options are only effective for TLS connections");
+        }
+        if let (Some(min), Some(max)) = (self.opts.tls_min_version, self.opts.tls_max_version) {
+            if min > max {
+                Err("--tls-min-version is greater than --tls-max-version")?;
+            }
+        }
+        if self.opts.tls_no_system_roots
+            && self.opts.tls_ca_files.is_empty()
+            && !self.opts.tls_insecure
+        {
+            _on_warning("--tls-no-system-roots without --tls-ca leaves no trusted certificates. Expect all TLS connections to fail.");
+        }
+        let have_pem = self.opts.pem_cert.is_some() || self.opts.pem_key.is_some();
+        if self.opts.pem_cert.is_some() ^ self.opts.pem_key.is_some() {
+            Err("--pem-cert and --pem-key options should go together")?;
+        }
+        if have_pem && self.opts.pkcs12_der.is_some() {
+            Err("Specify either --pkcs12-der or --pem-cert/--pem-key, not both")?;
+        }
+        for (name, _) in &self.opts.tls_sni_certs {
+            if !self.opts.tls_sni_keys.iter().any(|(n, _)| n == name) {
+                Err(format!("--sni-pem-cert for {} has no matching --sni-pem-key", name))?;
+            }
+        }
+        for (name, _) in &self.opts.tls_sni_keys {
+            if !self.opts.tls_sni_certs.iter().any(|(n, _)| n == name) {
+                Err(format!("--sni-pem-key for {} has no matching --sni-pem-cert", name))?;
+            }
+        }
+        let have_sni = !self.opts.tls_sni_certs.is_empty();
+        if self.contains_class("TlsAcceptClass")
+            ^ (self.opts.pkcs12_der.is_some() || have_pem || have_sni)
+        {
+            Err("SSL listerer and --pkcs12-der (or --pem-cert and --pem-key, or --sni-pem-cert) option should go together")?;
+        }
+        if !self.opts.sni_routes.is_empty() && !self.contains_class("TlsAcceptClass") {
+            _on_warning("--sni-route is only effective for TLS listeners");
         }
         #[cfg(target_os = "macos")]
         {
@@ -575,6 +655,82 @@ impl WebsocatConfiguration2 {
                 return Err("--udp-multicast-loop is not applicable without --udp-multicast")?;
             }
         }
+        if self.opts.udp_max_peers == 0 {
+            return Err("--udp-max-peers must be at least 1")?;
+        }
+        if self.opts.udp_idle_timeout == 0 {
+            return Err("--udp-idle-timeout must be at least 1 second")?;
+        }
+        Ok(())
+    }
+
+    fn l_lengthprefixed(&mut self, on_warning: &OnWarning) -> Result<()> {
+        let used = self.contains_class("LengthPrefixedClass");
+        if !used && (self.opts.lengthprefixed_varint || self.opts.lengthprefixed_little_endian) {
+            on_warning("--lengthprefixed-* options are meaningless without a lengthprefixed: overlay");
+        }
+        Ok(())
+    }
+
+    fn l_whole_messages(&mut self) {
+        if !self.s1.is_stream_oriented()
+            && !self.s2.is_stream_oriented()
+            && !self.sni_routes.iter().any(|r| r.1.is_stream_oriented())
+            && self.opts.max_message_size > self.opts.buffer_size
+        {
+            debug!("Copying whole messages, up to {} bytes", self.opts.max_message_size);
+            self.opts.whole_messages = true;
+        }
+    }
+
+    /// Apply right side fixups to `--sni-route` specifiers as well
+    fn l_sni_routes(&mut self, multiconnect: bool) -> Result<()> {
+        let mut routes = std::mem::take(&mut self.sni_routes);
+        for (_, s) in &mut routes {
+            std::mem::swap(&mut self.s2, s);
+            let mut reuser_has_been_inserted = false;
+            let ret = self
+                .l_stdio(multiconnect, &mut reuser_has_been_inserted, self.opts.asyncstdio)
+                .and_then(|()| self.l_reuser(reuser_has_been_inserted))
+                .and_then(|()| self.l_linemode())
+                .and_then(|()| self.l_reuser_for_append(multiconnect));
+            std::mem::swap(&mut self.s2, s);
+            ret?;
+        }
+        self.sni_routes = routes;
+        Ok(())
+    }
+
+    fn l_resolve(&mut self) -> Result<()> {
+        if self.opts.prefer_ipv4 && self.opts.prefer_ipv6 {
+            return Err("--prefer-ipv4 and --prefer-ipv6 are mutually exclusive")?;
+        }
+        Ok(())
+    }
+
+    fn l_tcp(&mut self) -> Result<()> {
+        if self.opts.tcp_keepalive.is_none()
+            && (self.opts.tcp_keepalive_interval.is_some() || self.opts.tcp_keepalive_count.is_some())
+        {
+            return Err("--tcp-keepalive-interval and --tcp-keepalive-count require --tcp-keepalive")?;
+        }
+        Ok(())
+    }
+
+    /// `jsonrpc-server:` matches handler's replies to calls by order,
+    /// which is only guaranteed with a process per call
+    fn l_jsonrpc_server(&mut self) -> Result<()> {
+        let stacks = [&self.s1, &self.s2];
+        for s in stacks.iter().copied().chain(self.sni_routes.iter().map(|r| &r.1)) {
+            let i = match s.overlays.iter().position(|x| x.cls.get_name() == "JsonRpcServerClass") {
+                Some(x) => x,
+                None => continue,
+            };
+            let inner = s.overlays.get(i + 1).unwrap_or(&s.addrtype);
+            if !["ExecClass", "CmdClass", "ShCClass"].contains(&inner.cls.get_name()) {
+                return Err("jsonrpc-server: must be used directly with exec:, cmd: or sh-c:")?;
+            }
+        }
         Ok(())
     }
 
@@ -587,17 +743,23 @@ impl WebsocatConfiguration2 {
         self.l_linemode()?;
         self.l_listener_on_the_right(&on_warning)?;
         self.l_reuser_for_append(multiconnect)?;
+        self.l_sni_routes(multiconnect)?;
         self.l_exec(&on_warning)?;
         self.l_uri_staticfiles(&on_warning)?;
         self.l_environ(&on_warning)?;
         self.l_closebug(&on_warning)?;
         self.l_socks5(&on_warning)?;
-        #[cfg(feature = "ssl")]
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
         self.l_ssl(&on_warning)?;
         self.l_ping(&on_warning)?;
         self.l_proto(&on_warning)?;
         self.l_eeof_unidir(&on_warning)?;
         self.l_udp(&on_warning)?;
+        self.l_resolve()?;
+        self.l_tcp()?;
+        self.l_lengthprefixed(&on_warning)?;
+        self.l_jsonrpc_server()?;
+        self.l_whole_messages();
 
         // TODO: UDP connect oneshot mode
         // TODO: tests for the linter
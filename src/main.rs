@@ -128,7 +128,7 @@ struct Opt {
 
     #[structopt(
         long = "udp-oneshot",
-        help = "[A] udp-listen: replies only one packet per client"
+        help = "[A] udp-listen:, udp-server: replies only one packet per client"
     )]
     udp_oneshot_mode: bool,
 
@@ -163,6 +163,15 @@ struct Opt {
     #[structopt(long="udp-reuseaddr")]
     udp_reuseaddr: bool,
 
+    /// [A] udp-server: close connection after this number of seconds without packets in either direction
+    #[structopt(long="udp-idle-timeout", default_value="60")]
+    udp_idle_timeout: u64,
+
+    /// [A] udp-server: maximum number of simultaneously tracked remote UDP sockets.
+    /// Packets from new addresses beyond this limit are dropped.
+    #[structopt(long="udp-max-peers", default_value="1024")]
+    udp_max_peers: usize,
+
     #[structopt(
         long = "unlink",
         help = "[A] Unlink listening UNIX socket before binding to it"
@@ -259,11 +268,16 @@ struct Opt {
     #[structopt(
         short = "B",
         long = "buffer-size",
-        help = "Maximum message size, in bytes",
+        help = "Buffer size, in bytes. Also maximum message size, unless both sides are message-oriented (see --max-message-size)",
         default_value = "65536"
     )]
     buffer_size: usize,
 
+    /// [A] Maximum size of a message passed whole between message-oriented peers (e.g. WebSockets).
+    /// Buffer grows from -B size as needed. Longer messages are split, or dropped with -S.
+    #[structopt(long = "max-message-size", default_value = "16777216")]
+    max_message_size: usize,
+
     #[structopt(
         short = "v",
         parse(from_occurrences),
@@ -287,7 +301,7 @@ struct Opt {
     #[structopt(
         short = "S",
         long = "strict",
-        help = "strict line/message mode: drop too long messages instead of splitting them, drop incomplete lines."
+        help = "strict line/message mode: drop too long messages instead of splitting them, drop incomplete lines, abort on malformed netstring:, cobs: or slip: frames."
     )]
     strict_mode: bool,
 
@@ -298,6 +312,22 @@ struct Opt {
     )]
     linemode_zero_terminated: bool,
 
+    /// [A] Size of length prefix for `lengthprefixed:`: 1, 2, 4 or 8 bytes
+    #[structopt(
+        long = "lengthprefixed-nbytes",
+        default_value = "4",
+        parse(try_from_str = "interpret_lengthprefixed_nbytes")
+    )]
+    lengthprefixed_nbytes: usize,
+
+    /// [A] Use little-endian length prefix for `lengthprefixed:` instead of big-endian
+    #[structopt(long = "lengthprefixed-little-endian")]
+    lengthprefixed_little_endian: bool,
+
+    /// [A] Use variable-length LEB128 length prefix for `lengthprefixed:`, like Protocol Buffers
+    #[structopt(long = "lengthprefixed-varint")]
+    lengthprefixed_varint: bool,
+
     #[structopt(
         long = "restrict-uri",
         help = "When serving a websocket, only accept the given URI, like `/ws`\nThis liberates other URIs for things like serving static files or proxying."
@@ -315,13 +345,13 @@ struct Opt {
     #[structopt(
         short = "e",
         long = "set-environment",
-        help = "Set WEBSOCAT_* environment variables when doing exec:/cmd:/sh-c:\nCurrently it's WEBSOCAT_URI and WEBSOCAT_CLIENT for\nrequest URI and client address (if TCP)\nBeware of ShellShock or similar security problems."
+        help = "Set WEBSOCAT_* environment variables when doing exec:/cmd:/sh-c:\nCurrently it's WEBSOCAT_URI, WEBSOCAT_CLIENT and WEBSOCAT_TLS_SNI for\nrequest URI, client address (if TCP) and TLS server name\nBeware of ShellShock or similar security problems."
     )]
     exec_set_env: bool,
 
     #[structopt(
         long = "reuser-send-zero-msg-on-disconnect",
-        help = "[A] Make reuse-raw: send a zero-length message to the peer when some clients disconnects."
+        help = "[A] Make reuse-raw: notify the peer when some client disconnects. It is a separate event, not a message: exec:, sh-c: and cmd: processes get SIGHUP, other peers don't support it."
     )]
     reuser_send_zero_msg_on_disconnect: bool,
 
@@ -337,12 +367,166 @@ struct Opt {
     )]
     process_exit_sighup: bool,
 
+    #[structopt(
+        long = "exec-per-message",
+        help = "[A] Make exec: or sh-c: or cmd: start a new process for each incoming message.\nThe message is written to process's stdin, then stdin is closed.\nEntire stdout of the process becomes one reply message, possibly empty."
+    )]
+    exec_per_message: bool,
+
+    #[structopt(
+        long = "exec-max-parallel",
+        default_value = "1",
+        help = "[A] Maximum number of processes running simultaneously in --exec-per-message mode.\nFurther messages wait for a process to finish.\nReplies are sent in the order of requests even if processes finish in a different order."
+    )]
+    exec_max_parallel: usize,
+
+    #[structopt(
+        long = "exec-timeout",
+        help = "[A] Kill processes started in --exec-per-message mode after this number of seconds"
+    )]
+    exec_timeout: Option<u64>,
+
+    #[structopt(
+        long = "exec-per-message-report",
+        help = "[A] In --exec-per-message mode, reply with JSON object with `stdout`, `stderr`,\n`exit_code` and `timed_out` fields instead of just process output"
+    )]
+    exec_per_message_report: bool,
+
+    #[structopt(
+        long = "exec-stderr",
+        default_value = "inherit",
+        help = "[A] What to do with stderr of exec: or sh-c: or cmd: processes:\n`inherit` - use websocat's stderr, `merge` - mix into stdout,\n`message` - send as separate messages with --exec-stderr-prefix (logged in --exec-per-message mode),\n`file:<path>` - append to a file, `log` - log as warnings"
+    )]
+    exec_stderr: websocat::options::ExecStderr,
+
+    #[structopt(
+        long = "exec-stderr-prefix",
+        default_value = "stderr: ",
+        help = "[A] Prefix for each line of process stderr in `--exec-stderr message` mode"
+    )]
+    exec_stderr_prefix: String,
+
+    #[structopt(
+        long = "exec-restart",
+        default_value = "never",
+        help = "[A] Start exec: or sh-c: or cmd: process again when it exits: `never`, `on-failure` or `always`.\nThe connection stays open while the process restarts. Not applied after input is closed."
+    )]
+    exec_restart: websocat::options::ExecRestart,
+
+    #[structopt(
+        long = "exec-restart-delay-millis",
+        default_value = "1000",
+        help = "[A] Delay before restarting exited process for --exec-restart"
+    )]
+    exec_restart_delay_millis: u64,
+
+    #[structopt(
+        long = "exec-restart-max",
+        help = "[A] Maximum number of restarts for --exec-restart (per connection)"
+    )]
+    exec_restart_max: Option<usize>,
+
+    #[structopt(
+        long = "exec-send-exit-status",
+        help = "[A] Send a message like {\"exit_code\":0} when exec: or sh-c: or cmd: process exits.\nProcess killed by a signal gets 128 + signal number, like in shells."
+    )]
+    exec_send_exit_status: bool,
+
+    #[cfg(all(unix, feature = "pty"))]
+    #[structopt(
+        long = "exec-pty",
+        help = "[A] Run exec: or sh-c: or cmd: process in a pseudo-terminal, for interactive programs.\nMessages starting with --exec-pty-control-prefix followed by JSON object like\n{\"cols\":80,\"rows\":24} or {\"signal\":\"INT\"} resize the terminal or send a signal."
+    )]
+    exec_pty: bool,
+
+    #[cfg(all(unix, feature = "pty"))]
+    #[structopt(
+        long = "exec-pty-control-prefix",
+        default_value = "\0",
+        help = "[A] Prefix of --exec-pty control messages. Default is a zero byte.\nEmpty value disables control messages."
+    )]
+    exec_pty_control_prefix: String,
+
     #[structopt(
         long = "jsonrpc",
         help = "Format messages you type as JSON RPC 2.0 method calls. First word becomes method name, the rest becomes parameters, possibly automatically wrapped in []."
     )]
     jsonrpc: bool,
 
+    /// [A] Show replies to `jsonrpc:` requests as is instead of readable form
+    #[structopt(long = "jsonrpc-raw")]
+    jsonrpc_raw: bool,
+
+    /// [A] Report `jsonrpc:` requests without a reply after this number of milliseconds. 0 disables.
+    #[structopt(long = "jsonrpc-timeout", default_value = "10000")]
+    jsonrpc_timeout_millis: u64,
+
+    #[cfg(feature = "jq")]
+    #[structopt(
+        long = "jq",
+        help = "[A] Filter for `jq:` overlay, in jq language. Example: '.items[] | select(.price > 10) | {name, price}'",
+        parse(try_from_str = "interpret_jq_filter")
+    )]
+    jq_filter: Option<::std::rc::Rc<websocat::jq::Filter>>,
+
+    #[cfg(feature = "jq")]
+    /// [A] Which messages `jq:` overlay processes: `read` (from the underlying specifier), `write` or `both`
+    #[structopt(long = "jq-direction", default_value = "read")]
+    jq_direction: websocat::options::MessageDirection,
+
+    #[cfg(feature = "jq")]
+    /// [A] Make `jq:` output strings as is instead of JSON string literals, like `jq -r`
+    #[structopt(long = "jq-raw-output")]
+    jq_raw_output: bool,
+
+    #[cfg(feature = "jq")]
+    /// [A] Make `jq:` drop messages which are not JSON instead of passing them unchanged
+    #[structopt(long = "jq-drop-non-json")]
+    jq_drop_non_json: bool,
+
+    #[cfg(feature = "filter")]
+    #[structopt(
+        long = "filter-regex",
+        help = "[A] `filter:` passes only messages matching this regular expression (`regex` crate syntax, matched on bytes)",
+        parse(try_from_str = "interpret_filter_regex")
+    )]
+    filter_regex: Option<::std::rc::Rc<regex::bytes::Regex>>,
+
+    #[cfg(feature = "filter")]
+    /// [A] `filter:` passes only messages starting with these bytes
+    #[structopt(long = "filter-prefix", parse(from_str = "interpret_filter_prefix"))]
+    filter_prefix: Option<Vec<u8>>,
+
+    #[cfg(feature = "filter")]
+    /// [A] `filter:` passes only `text` or only `binary` messages. WebSocket message type is not passed to `filter:`, so text means valid UTF-8
+    #[structopt(long = "filter-type")]
+    filter_type: Option<websocat::options::FilterMessageType>,
+
+    #[cfg(feature = "filter")]
+    /// [A] `filter:` passes only messages of at least this number of bytes
+    #[structopt(long = "filter-min-size")]
+    filter_min_size: Option<usize>,
+
+    #[cfg(feature = "filter")]
+    /// [A] `filter:` passes only messages of at most this number of bytes
+    #[structopt(long = "filter-max-size")]
+    filter_max_size: Option<usize>,
+
+    #[cfg(feature = "filter")]
+    /// [A] Make `filter:` drop messages matching all the conditions instead of passing them
+    #[structopt(long = "filter-invert")]
+    filter_invert: bool,
+
+    #[cfg(feature = "filter")]
+    /// [A] Which messages `filter:` checks: `read` (from the underlying specifier), `write` or `both`
+    #[structopt(long = "filter-direction", default_value = "both")]
+    filter_direction: websocat::options::MessageDirection,
+
+    #[cfg(feature = "filter")]
+    /// [A] Send messages dropped by `filter:` to this specifier, e.g. `appendfile:dropped.txt`
+    #[structopt(long = "filter-tee")]
+    filter_tee: Option<String>,
+
     #[structopt(
         long = "socks5-destination",
         help = "[A] Examples: 1.2.3.4:5678  2600:::80  hostname:5678",
@@ -370,7 +554,7 @@ struct Opt {
     )]
     tls_domain: Option<String>,
 
-    #[cfg(feature = "ssl")]
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
     #[structopt(
         long = "pkcs12-der",
         help = "Pkcs12 archive needed to accept SSL connections, certificate and key.\nA command to output it: openssl pkcs12 -export -out output.pkcs12 -inkey key.pem -in cert.pem\nUse with -s (--server-mode) option or with manually specified TLS overlays.\nSee moreexamples.md for more info.",
@@ -378,14 +562,30 @@ struct Opt {
     )]
     pkcs12_der: Option<Vec<u8>>,
 
-    #[cfg(feature = "ssl")]
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
     #[structopt(
         long = "pkcs12-passwd",
         help = "Password for --pkcs12-der pkcs12 archive. Required on Mac."
     )]
     pkcs12_passwd: Option<String>,
 
-    #[cfg(feature = "ssl")]
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "pem-cert",
+        help = "PEM file with certificate chain to accept SSL connections, as an alternative to --pkcs12-der.\nUse together with --pem-key. Files are re-read when they change on disk.",
+        parse(from_os_str)
+    )]
+    pem_cert: Option<std::path::PathBuf>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "pem-key",
+        help = "PEM file with PKCS#8 private key for --pem-cert.",
+        parse(from_os_str)
+    )]
+    pem_key: Option<std::path::PathBuf>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
     #[structopt(
         long = "insecure",
         short = "k",
@@ -393,6 +593,82 @@ struct Opt {
     )]
     tls_insecure: bool,
 
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "tls-ca",
+        help = "[A] Trust CA certificates from this PEM file when connecting to TLS, in addition to system roots.\nCan be specified multiple times.",
+        parse(from_os_str)
+    )]
+    tls_ca_files: Vec<std::path::PathBuf>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "tls-no-system-roots",
+        help = "[A] Don't trust system root certificates when connecting to TLS, only those from --tls-ca"
+    )]
+    tls_no_system_roots: bool,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "tls-pin-cert-sha256",
+        help = "[A] Require SHA-256 fingerprint of TLS server certificate to be this hex value.\nCan be specified multiple times. Combine with --insecure to accept pinned self-signed certificates.",
+        parse(try_from_str = "websocat::ssl_peer::interpret_sha256")
+    )]
+    tls_pin_cert_sha256: Vec<[u8; 32]>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "tls-pin-pubkey-sha256",
+        help = "[A] Require SHA-256 hash of TLS server's public key (DER SubjectPublicKeyInfo) to be this hex value.\nCan be specified multiple times, also together with --tls-pin-cert-sha256.",
+        parse(try_from_str = "websocat::ssl_peer::interpret_sha256")
+    )]
+    tls_pin_pubkey_sha256: Vec<[u8; 32]>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "tls-alpn",
+        help = "[A] ALPN protocol to offer (for TLS clients) or accept (for TLS listeners), e.g. `h2` or `http/1.1`.\nCan be specified multiple times, in order of preference."
+    )]
+    tls_alpn: Vec<String>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "tls-min-version",
+        help = "[A] Minimum TLS protocol version to allow: 1.0, 1.1, 1.2 or 1.3"
+    )]
+    tls_min_version: Option<websocat::options::TlsVersion>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "tls-max-version",
+        help = "[A] Maximum TLS protocol version to allow: 1.0, 1.1, 1.2 or 1.3"
+    )]
+    tls_max_version: Option<websocat::options::TlsVersion>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "sni-pem-cert",
+        help = "[A] PEM certificate chain to use for TLS clients requesting specific server name, in form `<hostname>=<file>`.\nHostname may start with `*.` to match any subdomain. Can be specified multiple times, each with a matching --sni-pem-key.\n--pem-cert or --pkcs12-der, if specified, is used for other server names.",
+        parse(try_from_str = "websocat::ssl_peer::interpret_sni_path")
+    )]
+    tls_sni_certs: Vec<(String, std::path::PathBuf)>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "sni-pem-key",
+        help = "[A] PEM private key for --sni-pem-cert with the same hostname, in form `<hostname>=<file>`.",
+        parse(try_from_str = "websocat::ssl_peer::interpret_sni_path")
+    )]
+    tls_sni_keys: Vec<(String, std::path::PathBuf)>,
+
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    #[structopt(
+        long = "sni-route",
+        help = "[A] Use another right side specifier for incoming TLS connections requesting this server name, in form `<hostname>=<specifier>`.\nHostname may start with `*.` to match any subdomain. Can be specified multiple times.\nConnections with unmatched or missing server name go to the usual right side specifier.",
+        parse(try_from_str = "websocat::ssl_peer::interpret_sni_route")
+    )]
+    sni_routes: Vec<(String, String)>,
+
     /// Maximum number of simultaneous connections for listening mode
     #[structopt(long = "conncap")]
     max_parallel_conns: Option<usize>,
@@ -429,15 +705,14 @@ struct Opt {
     )]
     request_headers: Vec<(http::header::HeaderName, http::header::HeaderValue)>,
 
-    /// [A] Don't exit when encountered a zero message.
-    /// Zero messages are used internally in Websocat,
-    /// so it may fail to close connection at all.
+    /// [A] Don't stop copying data in a direction when its reader signals end of stream.
+    /// Zero-length messages are distinct from end of stream and don't need this option.
+    /// It may fail to close connection at all.
     #[structopt(long = "no-exit-on-zeromsg")]
     no_exit_on_zeromsg: bool,
 
-    /// [A] Silently drop incoming zero-length WebSocket messages.
-    /// They may cause connection close due to
-    /// usage of zero-len message as EOF flag inside Websocat.
+    /// [A] Silently drop incoming zero-length WebSocket messages
+    /// instead of passing them on as empty messages (or empty lines in line mode).
     #[structopt(long = "websocket-ignore-zeromsg")]
     websocket_ignore_zeromsg: bool,
     
@@ -453,6 +728,83 @@ struct Opt {
     #[structopt(long = "--autoreconnect-delay-millis", default_value="20")]
     autoreconnect_delay_millis: u64,
 
+    /// [A] Give up connecting TCP (to all resolved addresses) after this number of milliseconds
+    #[structopt(long = "--tcp-connect-timeout-millis")]
+    tcp_connect_timeout_millis: Option<u64>,
+
+    /// [A] Give up on each individual TCP connection attempt after this number of milliseconds
+    #[structopt(long = "--tcp-attempt-timeout-millis")]
+    tcp_attempt_timeout_millis: Option<u64>,
+
+    /// [A] Delay between starting TCP connection attempts to subsequent resolved addresses (Happy Eyeballs)
+    #[structopt(long = "--tcp-happy-eyeballs-delay-millis", default_value="250")]
+    tcp_happy_eyeballs_delay_millis: u64,
+
+    /// [A] Try IPv4 addresses of resolved hostnames first
+    #[structopt(long = "--prefer-ipv4")]
+    prefer_ipv4: bool,
+
+    /// [A] Try IPv6 addresses of resolved hostnames first
+    #[structopt(long = "--prefer-ipv6")]
+    prefer_ipv6: bool,
+
+    #[structopt(
+        long = "resolve",
+        help = "[A] Use specified IP addresses for a hostname instead of resolving it, in form `<host>:<port>:<address>[,<address>...]`.\nPort may be `*` to match any port. Can be specified multiple times.",
+        parse(try_from_str = "websocat::resolve::interpret_resolve_override")
+    )]
+    resolve_overrides: Vec<websocat::resolve::ResolveOverride>,
+
+    /// [A] Set TCP_NODELAY for TCP sockets
+    #[structopt(long = "tcp-nodelay")]
+    tcp_nodelay: bool,
+
+    /// [A] Enable TCP keepalive, sending probes after this number of idle seconds
+    #[structopt(long = "tcp-keepalive")]
+    tcp_keepalive: Option<u64>,
+
+    /// [A] Number of seconds between TCP keepalive probes, used with --tcp-keepalive
+    #[cfg(target_os = "linux")]
+    #[structopt(long = "tcp-keepalive-interval")]
+    tcp_keepalive_interval: Option<u64>,
+
+    /// [A] Number of unanswered TCP keepalive probes before dropping the connection, used with --tcp-keepalive
+    #[cfg(target_os = "linux")]
+    #[structopt(long = "tcp-keepalive-count")]
+    tcp_keepalive_count: Option<u32>,
+
+    /// [A] Bind outgoing TCP connections to this local address (and port, if non-zero)
+    #[structopt(long = "tcp-bind-address")]
+    tcp_bind_address: Option<std::net::SocketAddr>,
+
+    /// [A] Listen backlog for listening TCP sockets
+    #[structopt(long = "tcp-backlog", default_value = "1024")]
+    tcp_listen_backlog: i32,
+
+    /// [A] Set SO_REUSEPORT for listening TCP sockets
+    #[cfg(unix)]
+    #[structopt(long = "tcp-reuseport")]
+    tcp_reuseport: bool,
+
+    /// [A] Set SO_MARK for TCP sockets. Requires CAP_NET_ADMIN
+    #[cfg(target_os = "linux")]
+    #[structopt(long = "so-mark")]
+    tcp_so_mark: Option<u32>,
+
+    /// [A] Set SO_BINDTODEVICE for TCP sockets: only use this network interface
+    #[cfg(target_os = "linux")]
+    #[structopt(long = "so-bindtodevice")]
+    tcp_bind_device: Option<String>,
+
+    /// [A] Notify systemd (`NOTIFY_SOCKET`) when listening sockets are ready and when exiting
+    #[cfg(unix)]
+    #[structopt(long = "sd-notify")]
+    sd_notify: bool,
+
+    /// [A] Use binary PROXY protocol v2 instead of v1 for `proxy-protocol-send:`
+    #[structopt(long = "proxy-protocol-v2")]
+    proxy_protocol_v2: bool,
+
 
     /// [A] Prepend specified text to each received WebSocket text message.
     /// Also strip this prefix from outgoing messages, explicitly marking
@@ -544,6 +896,30 @@ fn interpret_static_file(x: &str) -> Result<StaticFile> {
     })
 }
 
+fn interpret_lengthprefixed_nbytes(x: &str) -> Result<usize> {
+    match x.parse() {
+        Ok(n @ (1 | 2 | 4 | 8)) => Ok(n),
+        _ => Err("Must be 1, 2, 4 or 8")?,
+    }
+}
+
+#[cfg(feature = "jq")]
+fn interpret_jq_filter(x: &str) -> Result<::std::rc::Rc<websocat::jq::Filter>> {
+    Ok(::std::rc::Rc::new(x.parse().map_err(|e| format!("Invalid --jq filter: {}", e))?))
+}
+
+#[cfg(feature = "filter")]
+fn interpret_filter_regex(x: &str) -> Result<::std::rc::Rc<regex::bytes::Regex>> {
+    Ok(::std::rc::Rc::new(
+        regex::bytes::Regex::new(x).map_err(|e| format!("Invalid --filter-regex: {}", e))?,
+    ))
+}
+
+#[cfg(feature = "filter")]
+fn interpret_filter_prefix(x: &str) -> Vec<u8> {
+    x.as_bytes().to_vec()
+}
+
 fn interpret_socks_destination(x: &str) -> Result<SocksSocketAddr> {
     let colon = x.rfind(':');
     let colon = if let Some(colon) = colon {
@@ -698,6 +1074,8 @@ fn run() -> Result<()> {
             udp_join_multicast_iface_v4
             udp_join_multicast_iface_v6
             udp_reuseaddr
+            udp_idle_timeout
+            udp_max_peers
             unidirectional
             unidirectional_reverse
             exit_on_eof
@@ -715,13 +1093,29 @@ fn run() -> Result<()> {
             one_message
             no_auto_linemode
             buffer_size
+            max_message_size
             linemode_zero_terminated
+            lengthprefixed_nbytes
+            lengthprefixed_little_endian
+            lengthprefixed_varint
+            jsonrpc_raw
+            jsonrpc_timeout_millis
             restrict_uri
             serve_static_files
             exec_set_env
             reuser_send_zero_msg_on_disconnect
             process_zero_sighup
             process_exit_sighup
+            exec_per_message
+            exec_max_parallel
+            exec_timeout
+            exec_per_message_report
+            exec_stderr
+            exec_stderr_prefix
+            exec_restart
+            exec_restart_delay_millis
+            exec_restart_max
+            exec_send_exit_status
             socks_destination
             auto_socks5
             socks5_bind_script
@@ -737,21 +1131,94 @@ fn run() -> Result<()> {
             max_messages
             max_messages_rev
             autoreconnect_delay_millis
+            tcp_connect_timeout_millis
+            tcp_attempt_timeout_millis
+            tcp_happy_eyeballs_delay_millis
+            prefer_ipv4
+            prefer_ipv6
+            resolve_overrides
+            tcp_nodelay
+            tcp_keepalive
+            tcp_bind_address
+            tcp_listen_backlog
+            proxy_protocol_v2
             ws_text_prefix
             ws_binary_prefix
             ws_binary_base64
             ws_text_base64
             asyncstdio
         );
-        #[cfg(feature = "ssl")]
+        #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
         {
             opts! {
                 pkcs12_der
                 pkcs12_passwd
+                pem_cert
+                pem_key
                 tls_insecure
+                tls_ca_files
+                tls_no_system_roots
+                tls_pin_cert_sha256
+                tls_pin_pubkey_sha256
+                tls_alpn
+                tls_min_version
+                tls_max_version
+                tls_sni_certs
+                tls_sni_keys
+                sni_routes
+            }
+        }
+        #[cfg(unix)]
+        {
+            opts! {
+                tcp_reuseport
+                sd_notify
+            }
+        }
+        #[cfg(all(unix, feature = "pty"))]
+        {
+            opts! {
+                exec_pty
+                exec_pty_control_prefix
+            }
+        }
+        #[cfg(feature = "jq")]
+        {
+            opts! {
+                jq_filter
+                jq_direction
+                jq_raw_output
+                jq_drop_non_json
+            }
+        }
+        #[cfg(feature = "filter")]
+        {
+            opts! {
+                filter_regex
+                filter_prefix
+                filter_type
+                filter_min_size
+                filter_max_size
+                filter_invert
+                filter_direction
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            opts! {
+                tcp_keepalive_interval
+                tcp_keepalive_count
+                tcp_so_mark
+                tcp_bind_device
             }
         }
     };
+    #[cfg(feature = "filter")]
+    {
+        if let Some(ref x) = cmd.filter_tee {
+            opts.filter_tee = Some(websocat::spec(x)?);
+        }
+    }
 
     let (s1, s2): (String, String) = match (cmd.addr1, cmd.addr2) {
         (None, None) => {
@@ -774,9 +1241,9 @@ fn run() -> Result<()> {
             if cmd.server_mode {
                 #[allow(unused)]
                 let mut secure = false;
-                #[cfg(feature = "ssl")]
+                #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
                 {
-                    if opts.pkcs12_der.is_some() {
+                    if opts.pkcs12_der.is_some() || opts.pem_cert.is_some() || !opts.tls_sni_certs.is_empty() {
                         secure = true;
                     }
                 }
@@ -872,15 +1339,31 @@ fn run() -> Result<()> {
 
     let mut core = tokio::runtime::current_thread::Runtime::new()?;
 
+    #[cfg(unix)]
+    let sd_notify = websocat.opts.sd_notify;
+
     let error_handler = std::rc::Rc::new(move |e| {
         if !quiet {
             eprintln!("websocat: {}", e);
         }
     });
-    let prog = websocat.serve(error_handler);
+    let prog = websocat.serve_with_exit_code(error_handler);
     debug!("Preparation done. Now actually starting.");
-    core.block_on(prog)
-        .map_err(|()| "error running".to_string())?;
+    let ret = core.block_on(prog);
+    #[cfg(unix)]
+    {
+        if sd_notify {
+            websocat::sd_listen_peer::sd_notify("STOPPING=1");
+        }
+    }
+    let code = ret.map_err(|()| "error running".to_string())?;
+    // Reflect exit code of exec: process, like `ssh` does
+    if let Some(code) = code {
+        if code != 0 {
+            drop(core);
+            ::std::process::exit(code);
+        }
+    }
     Ok(())
 }
 
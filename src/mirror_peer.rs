@@ -22,7 +22,13 @@ use super::{once, ConstructParams, PeerConstructor, Specifier};
 pub struct Mirror;
 impl Specifier for Mirror {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
-        once(get_mirror_peer(cp.program_options.read_debt_handling))
+        let opts = &cp.program_options;
+        let grow_limit = if opts.whole_messages {
+            Some(opts.max_message_size)
+        } else {
+            None
+        };
+        once(get_mirror_peer(opts.read_debt_handling, grow_limit))
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec);
 }
@@ -80,10 +86,15 @@ struct MirrorRead {
     ch: mpsc::Receiver<Vec<u8>>,
 }
 
-pub fn get_mirror_peer(debt_handling: DebtHandling) -> BoxedNewPeerFuture {
+pub fn get_mirror_peer(debt_handling: DebtHandling, grow_limit: Option<usize>) -> BoxedNewPeerFuture {
     let (sender, receiver) = mpsc::channel::<Vec<u8>>(0);
     let r = MirrorRead {
-        debt: ReadDebt(Default::default(), debt_handling, ZeroMessagesHandling::Deliver),
+        debt: ReadDebt(
+            Default::default(),
+            debt_handling,
+            ZeroMessagesHandling::Deliver,
+            grow_limit,
+        ),
         ch: receiver,
     };
     let w = MirrorWrite(sender);
@@ -93,7 +104,12 @@ pub fn get_mirror_peer(debt_handling: DebtHandling) -> BoxedNewPeerFuture {
 pub fn get_literal_reply_peer(content: Vec<u8>) -> BoxedNewPeerFuture {
     let (sender, receiver) = mpsc::channel::<()>(0);
     let r = LiteralReplyRead {
-        debt: ReadDebt(Default::default(), DebtHandling::Silent, ZeroMessagesHandling::Deliver),
+        debt: ReadDebt(
+            Default::default(),
+            DebtHandling::Silent,
+            ZeroMessagesHandling::Deliver,
+            None,
+        ),
         ch: receiver,
         content,
     };
@@ -149,8 +165,7 @@ impl Write for MirrorWrite {
 impl Drop for MirrorWrite {
     fn drop(&mut self) {
         info!("MirrorWrite drop");
-        let _ = self.0.start_send(vec![]).map_err(|_| ()).map(|_| ());
-        let _ = self.0.poll_complete().map_err(|_| ()).map(|_| ());
+        // Closing the channel makes the reader side finish
     }
 }
 
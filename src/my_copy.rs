@@ -9,6 +9,8 @@ pub struct CopyOptions {
     pub stop_on_reader_zero_read: bool,
     pub once: bool,
     pub buffer_size: usize,
+    /// Buffer may grow up to this size if reader asks for it instead of splitting a message
+    pub max_buffer_size: usize,
     /// Because of -u or -U
     pub skip: bool,
     pub max_ops: Option<usize>,
@@ -33,6 +35,10 @@ pub struct Copy<R, W> {
     opts: CopyOptions,
     read_occurred: bool,
     remaining_ops: Option<usize>,
+    /// Zero-length message was read, but not yet written
+    empty_message_pending: bool,
+    /// Data was written, but flush has not succeeded yet
+    flush_pending: bool,
 }
 
 /// Creates a future which represents copying all the bytes from one object to
@@ -48,7 +54,8 @@ pub struct Copy<R, W> {
 /// well.
 ///
 /// Unlike original tokio_io::copy::copy, it does not always stop on zero length reads
-/// , handles BrokenPipe error kind as EOF and flushes after every write
+/// , handles BrokenPipe error kind as EOF and flushes after every write.
+/// `EmptyMessage` read errors are forwarded as zero-length writes.
 pub fn copy<R, W>(reader: R, writer: W, opts: CopyOptions) -> Copy<R, W>
 where
     R: AsyncRead,
@@ -66,6 +73,28 @@ where
         opts,
         read_occurred: false,
         remaining_ops: opts.max_ops,
+        empty_message_pending: false,
+        flush_pending: false,
+    }
+}
+
+impl<R, W> Copy<R, W> {
+    fn buf_filled(&mut self, n: usize) {
+        trace!("read {}", n);
+        if let Some(ref mut maxops) = self.remaining_ops {
+            *maxops -= 1;
+        }
+        if n == 0 {
+            debug!("zero len");
+            if self.opts.stop_on_reader_zero_read {
+                debug!("read_done");
+                self.read_done = true;
+            }
+        } else {
+            self.pos = 0;
+            self.cap = n;
+            self.read_occurred = true;
+        }
     }
 }
 
@@ -88,7 +117,13 @@ where
             // If our buffer is empty, then we need to read some data to
             // continue.
             trace!("poll");
-            if self.pos == self.cap && !self.read_done {
+            // Writer may accept data, but not be able to flush it right away
+            if self.flush_pending {
+                try_nb!(self.writer.as_mut().unwrap().flush());
+                self.flush_pending = false;
+            }
+
+            if self.pos == self.cap && !self.read_done && !self.empty_message_pending {
                 if self.read_occurred && self.opts.once {
                     debug!("Once mode requested, so aborting copy");
                     self.read_done = true;
@@ -99,6 +134,10 @@ where
                     self.read_done = true;
                     continue;
                 }
+                if self.buf.len() > self.opts.buffer_size {
+                    // Don't keep huge buffer after a huge message
+                    self.buf = vec![0; self.opts.buffer_size].into_boxed_slice();
+                }
                 let reader = self.reader.as_mut().unwrap();
                 let rr = reader.read(&mut self.buf);
                 if let Err(ref e) = rr {
@@ -107,26 +146,45 @@ where
                         self.read_done = true;
                         continue;
                     }
+                    if crate::readdebt::is_empty_message(e) {
+                        debug!("zero-length message");
+                        if let Some(ref mut maxops) = self.remaining_ops {
+                            *maxops -= 1;
+                        }
+                        self.read_occurred = true;
+                        self.empty_message_pending = true;
+                    }
+                    match crate::readdebt::requested_buffer_size(e) {
+                        Some(n) if n <= self.opts.max_buffer_size && n > self.buf.len() => {
+                            debug!("Growing buffer to {} bytes", n);
+                            self.buf = vec![0; n].into_boxed_slice();
+                            let reader = self.reader.as_mut().unwrap();
+                            let n = try_nb!(reader.read(&mut self.buf));
+                            self.buf_filled(n);
+                            continue;
+                        }
+                        _ => (),
+                    }
                 }
-                let n = try_nb!(rr);
-                trace!("read {}", n);
-                if let Some(ref mut maxops) = self.remaining_ops {
-                    *maxops -= 1;
-                }
-                if n == 0 {
-                    debug!("zero len");
-                    if self.opts.stop_on_reader_zero_read {
-                        debug!("read_done");
-                        self.read_done = true;
+                if !self.empty_message_pending {
+                    let n = try_nb!(rr);
+                    self.buf_filled(n);
+                    if n == 0 {
+                        continue;
                     }
-                    continue;
-                } else {
-                    self.pos = 0;
-                    self.cap = n;
-                    self.read_occurred = true;
                 }
             }
 
+            // Zero-length write means zero-length message for message-oriented writers
+            if self.empty_message_pending {
+                let writer = self.writer.as_mut().unwrap();
+                try_nb!(writer.write(&[]));
+                self.empty_message_pending = false;
+                self.flush_pending = true;
+                try_nb!(writer.flush());
+                self.flush_pending = false;
+            }
+
             // If our buffer has some data, let's write it out!
             while self.pos < self.cap {
                 let writer = self.writer.as_mut().unwrap();
@@ -141,7 +199,9 @@ where
                     self.pos += i;
                     self.amt += i as u64;
                 }
+                self.flush_pending = true;
                 try_nb!(writer.flush());
+                self.flush_pending = false;
             }
 
             // If we've written al the data and we've seen EOF, flush out the
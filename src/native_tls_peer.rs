@@ -0,0 +1,299 @@
+use futures::future::{ok, Future};
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+use super::ssl_peer::{check_pins, log_handshake};
+use super::{box_up_err, BoxedNewPeerFuture, Peer};
+use super::{L2rUser, Options};
+use crate::util::peer_err2;
+
+pub extern crate native_tls;
+extern crate readwrite;
+extern crate tokio_tls;
+
+use self::native_tls::{Certificate, Identity, Protocol, TlsAcceptor, TlsConnector};
+pub use self::tokio_tls::TlsAcceptor as TlsAcceptorExt;
+use self::tokio_tls::TlsConnector as TlsConnectorExt;
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+fn protocol(v: crate::options::TlsVersion) -> Protocol {
+    use crate::options::TlsVersion::*;
+    match v {
+        Tls10 => Protocol::Tlsv10,
+        Tls11 => Protocol::Tlsv11,
+        Tls12 => Protocol::Tlsv12,
+        Tls13 => Protocol::Tlsv13,
+    }
+}
+
+pub fn ssl_connect(
+    inner_peer: Peer,
+    _l2r: L2rUser,
+    dom: Option<String>,
+    opts: Rc<Options>,
+) -> BoxedNewPeerFuture {
+    let hup = inner_peer.2;
+    let sniffer = Rc::new(RefCell::new(HandshakeSniffer::default()));
+    let r: Box<dyn AsyncRead> = if log_enabled!(log::Level::Info) {
+        Box::new(SniffingReader(inner_peer.0, sniffer.clone()))
+    } else {
+        inner_peer.0
+    };
+    let squashed_peer = readwrite::ReadWriteAsync::new(r, inner_peer.1);
+
+    fn gettlsc(nohost: bool, opts: &Options) -> crate::Result<TlsConnectorExt> {
+        let mut b = TlsConnector::builder();
+        if nohost {
+            b.danger_accept_invalid_hostnames(true);
+        }
+        if opts.tls_insecure {
+            b.danger_accept_invalid_hostnames(true);
+            b.danger_accept_invalid_certs(true);
+        }
+        for ca in &opts.tls_ca_files {
+            let pem = ::std::fs::read(ca).map_err(|e| format!("Failed to read {:?}: {}", ca, e))?;
+            let certs = Certificate::stack_from_pem(&pem[..])?;
+            if certs.is_empty() {
+                Err(format!("No certificates found in {:?}", ca))?;
+            }
+            debug!("Loaded {} CA certificates from {:?}", certs.len(), ca);
+            for cert in certs {
+                b.add_root_certificate(cert);
+            }
+        }
+        if opts.tls_no_system_roots {
+            b.disable_built_in_roots(true);
+        }
+        if !opts.tls_alpn.is_empty() {
+            let alpns: Vec<&str> = opts.tls_alpn.iter().map(|x| x.as_str()).collect();
+            b.request_alpns(&alpns[..]);
+        }
+        b.min_protocol_version(opts.tls_min_version.map(protocol));
+        b.max_protocol_version(opts.tls_max_version.map(protocol));
+        let tlsc: TlsConnector = b.build()?;
+        Ok(TlsConnectorExt::from(tlsc))
+    }
+
+    let tls = match gettlsc(dom.is_none(), &opts) {
+        Ok(x) => x,
+        Err(e) => return peer_err2(e),
+    };
+
+    info!("Connecting to TLS");
+    let verified = dom.is_some();
+    let dom = dom.unwrap_or_else(|| "domainverificationdisabled".to_string());
+    Box::new(
+        tls.connect(dom.as_str(), squashed_peer)
+            .map_err(box_up_err)
+            .and_then(move |tls_stream| {
+                let cert = peer_cert_der(tls_stream.get_ref());
+                log_native_handshake(tls_stream.get_ref(), &sniffer.borrow(), true);
+                if let Err(e) = check_pins(cert.as_ref().map(|x| &x[..]), &opts) {
+                    return futures::future::err(e);
+                }
+                if verified {
+                    info!("Connected to TLS");
+                } else {
+                    warn!("Connected to TLS without proper verification of certificate. Use --tls-domain option.");
+                }
+                let (r, w) = tls_stream.split();
+                ok(Peer::new(r, w, hup))
+            }),
+    )
+}
+
+/// Observes plaintext part of TLS handshake as sent by the server to find out
+/// negotiated version, cipher suite and certificate chain, which are not exposed by native-tls.
+/// Certificates are only visible for TLS 1.2 and earlier.
+#[derive(Default)]
+struct HandshakeSniffer {
+    records: Vec<u8>,
+    handshake: Vec<u8>,
+    done: bool,
+    version: Option<u16>,
+    cipher: Option<u16>,
+    chain: Vec<Vec<u8>>,
+}
+
+impl HandshakeSniffer {
+    fn feed(&mut self, data: &[u8]) {
+        if self.done {
+            return;
+        }
+        self.records.extend_from_slice(data);
+        while !self.done && self.records.len() >= 5 {
+            let len = ((self.records[3] as usize) << 8) | self.records[4] as usize;
+            if self.records.len() < 5 + len {
+                break;
+            }
+            if self.records[0] != 22 {
+                // ChangeCipherSpec, alert or application data: the rest is encrypted
+                self.done = true;
+                break;
+            }
+            self.handshake.extend_from_slice(&self.records[5..5 + len]);
+            self.records.drain(..5 + len);
+            self.parse_handshake();
+        }
+        if self.records.len() + self.handshake.len() > 256 * 1024 {
+            self.done = true;
+        }
+        if self.done {
+            self.records = vec![];
+            self.handshake = vec![];
+        }
+    }
+
+    fn parse_handshake(&mut self) {
+        while self.handshake.len() >= 4 {
+            let h = &self.handshake;
+            let len = ((h[1] as usize) << 16) | ((h[2] as usize) << 8) | h[3] as usize;
+            if h.len() < 4 + len {
+                return;
+            }
+            let typ = h[0];
+            let body = h[4..4 + len].to_vec();
+            self.handshake.drain(..4 + len);
+            match typ {
+                2 => self.server_hello(&body),
+                11 => self.certificate(&body),
+                // ServerHelloDone
+                14 => self.done = true,
+                _ => (),
+            }
+        }
+    }
+
+    fn server_hello(&mut self, b: &[u8]) {
+        let u16_at = |i: usize| b.get(i..i + 2).map(|x| ((x[0] as u16) << 8) | x[1] as u16);
+        self.version = u16_at(0);
+        let sid_len = match b.get(34) {
+            Some(&x) => x as usize,
+            None => return,
+        };
+        let mut i = 35 + sid_len;
+        self.cipher = u16_at(i);
+        // cipher suite, compression method, extensions length
+        i += 5;
+        while let (Some(typ), Some(len)) = (u16_at(i), u16_at(i + 2)) {
+            // supported_versions carries the real version in TLS 1.3
+            if typ == 43 && len == 2 {
+                self.version = u16_at(i + 4);
+            }
+            i += 4 + len as usize;
+        }
+    }
+
+    fn certificate(&mut self, b: &[u8]) {
+        let u24_at = |i: usize| {
+            b.get(i..i + 3)
+                .map(|x| ((x[0] as usize) << 16) | ((x[1] as usize) << 8) | x[2] as usize)
+        };
+        let mut i = 3;
+        while let Some(len) = u24_at(i) {
+            match b.get(i + 3..i + 3 + len) {
+                Some(cert) => self.chain.push(cert.to_vec()),
+                None => break,
+            }
+            i += 3 + len;
+        }
+    }
+}
+
+struct SniffingReader<R>(R, Rc<RefCell<HandshakeSniffer>>);
+impl<R: Read> Read for SniffingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        let n = self.0.read(buf)?;
+        self.1.borrow_mut().feed(&buf[..n]);
+        Ok(n)
+    }
+}
+impl<R: AsyncRead> AsyncRead for SniffingReader<R> {}
+
+struct SniffingWriter<W>(W, Rc<RefCell<HandshakeSniffer>>);
+impl<W: Write> Write for SniffingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.1.borrow_mut().feed(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.0.flush()
+    }
+}
+impl<W: AsyncWrite> AsyncWrite for SniffingWriter<W> {
+    fn shutdown(&mut self) -> futures::Poll<(), ::std::io::Error> {
+        self.0.shutdown()
+    }
+}
+
+fn peer_cert_der<S: Read + Write>(s: &native_tls::TlsStream<S>) -> Option<Vec<u8>> {
+    s.peer_certificate().ok()?.and_then(|x| x.to_der().ok())
+}
+
+/// Log negotiated TLS parameters and, if `log_certs`, the certificate chain presented by the server.
+fn log_native_handshake<S: Read + Write>(
+    s: &native_tls::TlsStream<S>,
+    sniffer: &HandshakeSniffer,
+    log_certs: bool,
+) {
+    if !log_enabled!(log::Level::Info) {
+        return;
+    }
+    let alpn = s.negotiated_alpn().ok().and_then(|x| x);
+    let mut chain = vec![];
+    if log_certs {
+        chain = sniffer.chain.clone();
+        if chain.is_empty() {
+            // TLS 1.3 encrypts certificates, so only the leaf is available
+            chain.extend(peer_cert_der(s));
+        }
+    }
+    log_handshake(sniffer.version, sniffer.cipher, alpn.as_ref().map(|x| &x[..]), &chain[..]);
+}
+
+fn build_acceptor(identity: Identity, opts: &Options) -> native_tls::Result<TlsAcceptorExt> {
+    let mut b = TlsAcceptor::builder(identity);
+    if !opts.tls_alpn.is_empty() {
+        b.accept_alpn(&opts.tls_alpn[..]);
+    }
+    b.min_protocol_version(opts.tls_min_version.map(protocol));
+    b.max_protocol_version(opts.tls_max_version.map(protocol));
+    Ok(TlsAcceptorExt::from(b.build()?))
+}
+
+pub fn acceptor_from_pem(cert: &[u8], key: &[u8], opts: &Options) -> crate::Result<TlsAcceptorExt> {
+    let identity = Identity::from_pkcs8(cert, key)?;
+    Ok(build_acceptor(identity, opts)?)
+}
+
+pub fn acceptor_from_pkcs12(der: &[u8], passwd: &str, opts: &Options) -> crate::Result<TlsAcceptorExt> {
+    let identity = Identity::from_pkcs12(der, passwd)?;
+    Ok(build_acceptor(identity, opts)?)
+}
+
+pub fn ssl_accept(inner_peer: Peer, _l2r: L2rUser, tls: TlsAcceptorExt) -> BoxedNewPeerFuture {
+    let hup = inner_peer.2;
+    let sniffer = Rc::new(RefCell::new(HandshakeSniffer::default()));
+    let w: Box<dyn AsyncWrite> = if log_enabled!(log::Level::Info) {
+        Box::new(SniffingWriter(inner_peer.1, sniffer.clone()))
+    } else {
+        inner_peer.1
+    };
+    let squashed_peer = readwrite::ReadWriteAsync::new(inner_peer.0, w);
+
+    info!("Accepting a TLS connection");
+    Box::new(
+        tls.accept(squashed_peer)
+            .map_err(box_up_err)
+            .and_then(move |tls_stream| {
+                info!("Connected to TLS");
+                log_native_handshake(tls_stream.get_ref(), &sniffer.borrow(), false);
+                let (r, w) = tls_stream.split();
+                ok(Peer::new(r, w, hup))
+            }),
+    )
+}
@@ -7,25 +7,30 @@ use futures::unsync::oneshot::{channel, Receiver, Sender};
 use std;
 use std::io::Result as IoResult;
 use std::io::{Read, Write};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 use tokio_io::{AsyncRead, AsyncWrite};
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use tokio_tcp::{TcpListener, TcpStream};
+use tokio_tcp::{ConnectFuture, TcpListener, TcpStream};
 use tokio_udp::UdpSocket;
 
+use super::resolve::{resolve, HostPort};
 use super::L2rUser;
 use super::{box_up_err, peer_err_s, wouldblock, BoxedNewPeerFuture, BoxedNewPeerStream, Peer};
 use super::{multi, once, ConstructParams, Options, PeerConstructor, Specifier};
 
 #[derive(Debug, Clone)]
-pub struct TcpConnect(pub Vec<SocketAddr>);
+pub struct TcpConnect(pub HostPort);
 impl Specifier for TcpConnect {
-    fn construct(&self, _: ConstructParams) -> PeerConstructor {
-        // FIXME: connect to multiple things
-        once(tcp_connect_peer(&self.0[..]))
+    fn construct(&self, p: ConstructParams) -> PeerConstructor {
+        let opts = p.program_options;
+        once(Box::new(
+            resolve(&self.0, &opts).and_then(move |addrs| tcp_connect_peer(&addrs[..], &opts)),
+        ) as BoxedNewPeerFuture)
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec );
 }
@@ -33,12 +38,22 @@ specifier_class!(
     name = TcpConnectClass,
     target = TcpConnect,
     prefixes = ["tcp:", "tcp-connect:", "connect-tcp:", "tcp-c:", "c-tcp:"],
-    arg_handling = parseresolve,
+    arg_handling = parse,
     overlay = false,
     StreamOriented,
     SingleConnect,
     help = r#"
-Connect to specified TCP host and port. Argument is a socket address.
+Connect to specified TCP host and port. Argument is a socket address
+or a hostname with port. Hostname is resolved on each connection attempt.
+
+If the host resolves to multiple addresses, they are tried Happy Eyeballs-style
+(RFC 8305): IPv6 and IPv4 addresses are interleaved, a next attempt starts each
+`--tcp-happy-eyeballs-delay-millis` or as soon as previous attempt fails,
+first successful connection wins. See also `--tcp-connect-timeout-millis`
+and `--tcp-attempt-timeout-millis`.
+
+Socket can be tuned with `--tcp-nodelay`, `--tcp-keepalive`, `--tcp-bind-address`
+and (on Linux) `--so-mark`, `--so-bindtodevice`.
 
 Example: simulate netcat netcat
 
@@ -54,7 +69,7 @@ Example: redirect websocket connections to local SSH server over IPv6
 pub struct TcpListen(pub SocketAddr);
 impl Specifier for TcpListen {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
-        multi(tcp_listen_peer(&self.0, p.left_to_right))
+        multi(tcp_listen_peer(&self.0, p.left_to_right, &p.program_options))
     }
     specifier_boilerplate!(noglobalstate multiconnect no_subspec );
 }
@@ -68,6 +83,9 @@ specifier_class!(
     MultiConnect,
     help = r#"
 Listen TCP port on specified address.
+
+Socket can be tuned with `--tcp-nodelay`, `--tcp-keepalive`, `--tcp-backlog`,
+`--tcp-reuseport` and (on Linux) `--so-mark`, `--so-bindtodevice`.
     
 Example: echo server
 
@@ -80,10 +98,13 @@ Example: redirect TCP to a websocket
 );
 
 #[derive(Debug, Clone)]
-pub struct UdpConnect(pub SocketAddr);
+pub struct UdpConnect(pub HostPort);
 impl Specifier for UdpConnect {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
-        once(udp_connect_peer(&self.0, &p.program_options))
+        let opts = p.program_options;
+        once(Box::new(
+            resolve(&self.0, &opts).and_then(move |addrs| udp_connect_peer(&addrs[0], &opts)),
+        ) as BoxedNewPeerFuture)
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec );
 }
@@ -96,7 +117,9 @@ specifier_class!(
     MessageOriented,
     SingleConnect,
     help = r#"
-Send and receive packets to specified UDP socket, from random UDP port  
+Send and receive packets to specified UDP socket, from random UDP port.
+Argument is a socket address or a hostname with port.
+Hostname is resolved on each connection attempt, first address is used.
 "#
 );
 
@@ -124,7 +147,45 @@ remote UDP socket.
 Note that it is not a multiconnect specifier like e.g. `tcp-listen`:
 entire lifecycle of the UDP socket is the same connection.
 
-File a feature request on Github if you want proper DNS-like request-reply UDP mode here.
+See `udp-server:` for a mode where each remote UDP socket gets a separate connection.
+"#
+);
+
+#[derive(Debug, Clone)]
+pub struct UdpServer(pub SocketAddr);
+impl Specifier for UdpServer {
+    fn construct(&self, p: ConstructParams) -> PeerConstructor {
+        multi(udp_server_peer(&self.0, &p.program_options, p.left_to_right))
+    }
+    specifier_boilerplate!(noglobalstate multiconnect no_subspec );
+}
+specifier_class!(
+    name = UdpServerClass,
+    target = UdpServer,
+    prefixes = ["udp-server:", "server-udp:", "udp-s:", "s-udp:"],
+    arg_handling = parse,
+    overlay = false,
+    MessageOriented,
+    MultiConnect,
+    help = r#"
+Bind an UDP socket to specified host:port and treat each remote
+UDP socket (source address) as a separate connection.
+Packets from a new source address start a new connection,
+replies are sent back to that source address.
+
+A connection is closed when there are no packets in either direction
+for `--udp-idle-timeout` seconds (default 60).
+At most `--udp-max-peers` (default 1024) connections are tracked;
+packets from new source addresses beyond that are dropped.
+With `--udp-oneshot`, a connection is closed after the first reply.
+
+Example: give each UDP client its own WebSocket connection
+
+    websocat -b udp-server:0.0.0.0:5353 ws://127.0.0.1:8080/
+
+Example: UDP echo server
+
+    websocat -b udp-server:127.0.0.1:1234 mirror:
 "#
 );
 
@@ -193,42 +254,249 @@ impl Drop for MyTcpStream {
     }
 }
 
-pub fn tcp_connect_peer(addrs: &[SocketAddr]) -> BoxedNewPeerFuture {
-    // Apply Happy Eyeballs in case of multiple proposed addresses.
+/// Create TCP socket with options that need to be set before `bind` or `connect`
+fn new_tcp_socket(addr: &SocketAddr, opts: &Options, listening: bool) -> IoResult<socket2::Socket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let s = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+    if listening {
+        #[cfg(unix)]
+        {
+            s.set_reuse_address(true)?;
+            if opts.tcp_reuseport {
+                s.set_reuse_port(true)?;
+            }
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(mark) = opts.tcp_so_mark {
+            s.set_mark(mark)?;
+        }
+        if let Some(ref dev) = opts.tcp_bind_device {
+            s.bind_device(Some(dev.as_bytes()))?;
+        }
+    }
+    Ok(s)
+}
+
+fn tcp_connect_socket(addr: &SocketAddr, opts: &Options) -> IoResult<ConnectFuture> {
+    let s = new_tcp_socket(addr, opts, false)?;
+    if let Some(ref b) = opts.tcp_bind_address {
+        s.bind(&(*b).into())?;
+    } else if cfg!(windows) {
+        // Windows requires the socket to be bound before `connect_std`
+        s.bind(&get_zero_address(addr).into())?;
+    }
+    Ok(TcpStream::connect_std(s.into(), addr, &tokio_reactor::Handle::default()))
+}
+
+fn tcp_listen_socket(addr: &SocketAddr, opts: &Options) -> IoResult<TcpListener> {
+    let s = new_tcp_socket(addr, opts, true)?;
+    s.bind(&(*addr).into())?;
+    s.listen(opts.tcp_listen_backlog)?;
+    TcpListener::from_std(s.into(), &tokio_reactor::Handle::default())
+}
+
+/// Options for both connected and accepted TCP sockets
+fn apply_tcp_stream_options(s: &TcpStream, opts: &Options) -> IoResult<()> {
+    if opts.tcp_nodelay {
+        s.set_nodelay(true)?;
+    }
+    if let Some(idle) = opts.tcp_keepalive {
+        s.set_keepalive(Some(Duration::from_secs(idle)))?;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let tuned = opts.tcp_keepalive_interval.is_some() || opts.tcp_keepalive_count.is_some();
+        if let (Some(idle), true) = (opts.tcp_keepalive, tuned) {
+            let mut ka = socket2::TcpKeepalive::new().with_time(Duration::from_secs(idle));
+            if let Some(i) = opts.tcp_keepalive_interval {
+                ka = ka.with_interval(Duration::from_secs(i));
+            }
+            if let Some(c) = opts.tcp_keepalive_count {
+                ka = ka.with_retries(c);
+            }
+            socket2::SockRef::from(s).set_tcp_keepalive(&ka)?;
+        }
+    }
+    Ok(())
+}
+
+/// Order addresses as suggested by RFC 8305: alternate between address families,
+/// starting with the family of the first resolved address.
+fn interleave_address_families(addrs: &[SocketAddr]) -> VecDeque<SocketAddr> {
+    let first_is_v6 = addrs.first().map(|a| a.is_ipv6()).unwrap_or(false);
+    let (mut preferred, mut other): (VecDeque<SocketAddr>, VecDeque<SocketAddr>) =
+        addrs.iter().partition(|a| a.is_ipv6() == first_is_v6);
+    let mut ret = VecDeque::with_capacity(addrs.len());
+    loop {
+        match (preferred.pop_front(), other.pop_front()) {
+            (None, None) => break,
+            (a, b) => ret.extend(a.into_iter().chain(b)),
+        }
+    }
+    ret
+}
+
+struct ConnectAttempt {
+    addr: SocketAddr,
+    conn: Box<dyn Future<Item = TcpStream, Error = std::io::Error>>,
+    timeout: Option<::tokio_timer::Delay>,
+}
+
+/// Happy Eyeballs (RFC 8305) style connection race.
+/// A new attempt is started each `delay` or as soon as a previous attempt fails.
+struct HappyEyeballs {
+    pending: VecDeque<SocketAddr>,
+    attempts: Vec<ConnectAttempt>,
+    next_attempt: ::tokio_timer::Delay,
+    delay: Duration,
+    attempt_timeout: Option<Duration>,
+    overall_timeout: Option<::tokio_timer::Delay>,
+    failures: Vec<String>,
+    opts: Rc<Options>,
+}
+
+impl HappyEyeballs {
+    fn start_attempt(&mut self, addr: SocketAddr) {
+        debug!("Trying to connect to TCP {}", addr);
+        let now = Instant::now();
+        self.attempts.push(ConnectAttempt {
+            addr,
+            conn: match tcp_connect_socket(&addr, &self.opts) {
+                Ok(x) => Box::new(x),
+                Err(e) => Box::new(futures::future::err(e)),
+            },
+            timeout: self.attempt_timeout.map(|t| ::tokio_timer::Delay::new(now + t)),
+        });
+        self.next_attempt.reset(now + self.delay);
+    }
+
+    fn error(&mut self) -> Box<dyn std::error::Error> {
+        for a in self.attempts.drain(..) {
+            self.failures.push(format!("{}: timed out", a.addr));
+        }
+        for a in self.pending.drain(..) {
+            self.failures.push(format!("{}: not tried", a));
+        }
+        format!("Failed to connect to TCP: {}", self.failures.join(", ")).into()
+    }
+}
+
+impl Future for HappyEyeballs {
+    type Item = TcpStream;
+    type Error = Box<dyn std::error::Error>;
+
+    fn poll(&mut self) -> futures::Poll<TcpStream, Self::Error> {
+        if let Some(ref mut t) = self.overall_timeout {
+            match t.poll() {
+                Ok(futures::Async::NotReady) => (),
+                Ok(futures::Async::Ready(())) => return Err(self.error()),
+                Err(e) => error!("tokio-timer's Delay: {}", e),
+            }
+        }
+        let mut previous_attempt_ended = false;
+        loop {
+            if !self.pending.is_empty() {
+                let time_for_next = match self.next_attempt.poll() {
+                    Ok(futures::Async::NotReady) => false,
+                    Ok(futures::Async::Ready(())) => true,
+                    Err(e) => {
+                        error!("tokio-timer's Delay: {}", e);
+                        false
+                    }
+                };
+                if time_for_next || previous_attempt_ended || self.attempts.is_empty() {
+                    previous_attempt_ended = false;
+                    let addr = self.pending.pop_front().unwrap();
+                    self.start_attempt(addr);
+                    continue;
+                }
+            }
+
+            let mut some_attempt_ended = false;
+            let mut i = 0;
+            while i < self.attempts.len() {
+                let a = &mut self.attempts[i];
+                let outcome = match a.conn.poll() {
+                    Ok(futures::Async::Ready(x)) => {
+                        info!("Connected to TCP {}", a.addr);
+                        return Ok(futures::Async::Ready(x));
+                    }
+                    Err(e) => Some(format!("{}: {}", a.addr, e)),
+                    Ok(futures::Async::NotReady) => match a.timeout.as_mut().map(|t| t.poll()) {
+                        Some(Ok(futures::Async::Ready(()))) => Some(format!("{}: timed out", a.addr)),
+                        Some(Err(e)) => {
+                            error!("tokio-timer's Delay: {}", e);
+                            None
+                        }
+                        _ => None,
+                    },
+                };
+                if let Some(f) = outcome {
+                    info!("Failed to connect to TCP {}", f);
+                    self.failures.push(f);
+                    self.attempts.remove(i);
+                    some_attempt_ended = true;
+                } else {
+                    i += 1;
+                }
+            }
+
+            if self.attempts.is_empty() && self.pending.is_empty() {
+                return Err(self.error());
+            }
+            if !some_attempt_ended {
+                return Ok(futures::Async::NotReady);
+            }
+            previous_attempt_ended = true;
+        }
+    }
+}
+
+pub fn tcp_connect_peer(addrs: &[SocketAddr], opts: &Rc<Options>) -> BoxedNewPeerFuture {
+    if addrs.is_empty() {
+        return Box::new(futures::future::err("No addresses to connect to".into())) as BoxedNewPeerFuture;
+    }
     if addrs.len() > 1 {
         debug!("Setting up a race between multiple TCP client sockets. Who connects the first?");
     }
-    use futures::stream::futures_unordered::FuturesUnordered;
-    let mut fu = FuturesUnordered::new();
-    for addr in addrs {
-        let addr = addr.clone();
-        fu.push(
-            TcpStream::connect(&addr)
-            .map(move |x| {
-                info!("Connected to TCP {}", addr);
-                let x = Rc::new(x);
-                Peer::new(
-                    MyTcpStream(x.clone(), true),
-                    MyTcpStream(x.clone(), false),
-                    None /* TODO */
-                )
-            })
-            .map_err(box_up_err)
-        );
-    }
-    let p = fu.into_future().and_then(|(x, _losers)| {
-        let peer = x.unwrap();
-        debug!("We have a winner. Disconnecting losers.");
-        futures::future::ok(peer)       
-    });
-    Box::new(p.map_err(|(e,_)|e)) as BoxedNewPeerFuture
+    let now = Instant::now();
+    let he = HappyEyeballs {
+        pending: interleave_address_families(addrs),
+        attempts: Vec::new(),
+        next_attempt: ::tokio_timer::Delay::new(now),
+        delay: Duration::from_millis(opts.tcp_happy_eyeballs_delay_millis),
+        attempt_timeout: opts.tcp_attempt_timeout_millis.map(Duration::from_millis),
+        overall_timeout: opts
+            .tcp_connect_timeout_millis
+            .map(|t| ::tokio_timer::Delay::new(now + Duration::from_millis(t))),
+        failures: Vec::new(),
+        opts: opts.clone(),
+    };
+    let opts = opts.clone();
+    Box::new(he.and_then(move |x| {
+        apply_tcp_stream_options(&x, &opts)?;
+        let x = Rc::new(x);
+        Ok(Peer::new(
+            MyTcpStream(x.clone(), true),
+            MyTcpStream(x.clone(), false),
+            None, /* TODO */
+        ))
+    })) as BoxedNewPeerFuture
 }
 
-pub fn tcp_listen_peer(addr: &SocketAddr, l2r: L2rUser) -> BoxedNewPeerStream {
-    let bound = match TcpListener::bind(&addr) {
+pub fn tcp_listen_peer(addr: &SocketAddr, l2r: L2rUser, opts: &Rc<Options>) -> BoxedNewPeerStream {
+    let bound = match tcp_listen_socket(addr, opts) {
         Ok(x) => x,
         Err(e) => return peer_err_s(e),
     };
+    tcp_serve_listener(bound, l2r, opts)
+}
+
+/// Accept connections from already bound TCP listener
+pub fn tcp_serve_listener(bound: TcpListener, l2r: L2rUser, opts: &Rc<Options>) -> BoxedNewPeerStream {
+    let opts = opts.clone();
     use tk_listen::ListenExt;
     Box::new(
         bound
@@ -237,11 +505,15 @@ pub fn tcp_listen_peer(addr: &SocketAddr, l2r: L2rUser) -> BoxedNewPeerStream {
             .map(move |x| {
                 let addr = x.peer_addr().ok();
                 info!("Incoming TCP connection from {:?}", addr);
+                if let Err(e) = apply_tcp_stream_options(&x, &opts) {
+                    warn!("Failed to set TCP socket options: {}", e);
+                }
 
                 match l2r {
                     L2rUser::FillIn(ref y) => {
                         let mut z = y.borrow_mut();
                         z.client_addr = addr.map(|a| format!("{}", a));
+                        z.server_addr = x.local_addr().ok().map(|a| format!("{}", a));
                     }
                     L2rUser::ReadFrom(_) => {}
                 }
@@ -385,6 +657,307 @@ pub fn udp_listen_peer(addr: &SocketAddr, opts: &Rc<Options>) -> BoxedNewPeerFut
     )) as BoxedNewPeerFuture
 }
 
+/// Maximum number of not yet read packets per `udp-server:` connection
+const UDP_SERVER_QUEUE_LEN: usize = 64;
+
+struct UdpSession {
+    addr: SocketAddr,
+    queue: VecDeque<Vec<u8>>,
+    reader: Option<futures::task::Task>,
+    last_activity: Instant,
+    finished: bool,
+}
+
+struct UdpServerState {
+    s: UdpSocket,
+    sessions: HashMap<SocketAddr, Rc<RefCell<UdpSession>>>,
+    /// Sessions created by the receiver task, not yet yielded by the stream
+    new_sessions: VecDeque<Rc<RefCell<UdpSession>>>,
+    /// `UdpServerStream` is still alive and accepts new sessions
+    listening: bool,
+    listener: Option<futures::task::Task>,
+    receiver: Option<futures::task::Task>,
+}
+
+impl UdpServerState {
+    fn wake_receiver(&mut self) {
+        if let Some(t) = self.receiver.take() {
+            t.notify();
+        }
+    }
+}
+
+struct UdpServerStream {
+    srv: Rc<RefCell<UdpServerState>>,
+    receiver: Option<UdpServerReceiver>,
+    opts: Rc<Options>,
+    l2r: L2rUser,
+}
+
+/// Task reading the socket and distributing packets to sessions.
+/// Runs separately from the stream, so existing sessions keep receiving
+/// when new connections are not accepted (e.g. because of `--conncap`).
+struct UdpServerReceiver {
+    srv: Rc<RefCell<UdpServerState>>,
+    buf: Vec<u8>,
+    max_peers: usize,
+}
+
+struct UdpSessionReader {
+    srv: Rc<RefCell<UdpServerState>>,
+    sess: Rc<RefCell<UdpSession>>,
+    timer: ::tokio_timer::Delay,
+    idle_timeout: Duration,
+}
+
+struct UdpSessionWriter {
+    srv: Rc<RefCell<UdpServerState>>,
+    sess: Rc<RefCell<UdpSession>>,
+    oneshot_mode: bool,
+}
+
+pub fn udp_server_peer(addr: &SocketAddr, opts: &Rc<Options>, l2r: L2rUser) -> BoxedNewPeerStream {
+    let s = match get_udp(addr, opts).and_then(|x| apply_udp_options(&x, opts).map(|()| x)) {
+        Ok(x) => x,
+        Err(e) => return peer_err_s(e),
+    };
+    let srv = Rc::new(RefCell::new(UdpServerState {
+        s,
+        sessions: HashMap::new(),
+        new_sessions: VecDeque::new(),
+        listening: true,
+        listener: None,
+        receiver: None,
+    }));
+    Box::new(UdpServerStream {
+        receiver: Some(UdpServerReceiver {
+            srv: srv.clone(),
+            buf: vec![0; opts.buffer_size],
+            max_peers: opts.udp_max_peers,
+        }),
+        srv,
+        opts: opts.clone(),
+        l2r,
+    }) as BoxedNewPeerStream
+}
+
+impl Future for UdpServerReceiver {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        loop {
+            // Other references are held by the stream and the sessions
+            if Rc::strong_count(&self.srv) == 1 {
+                debug!("UDP server socket is not used anymore");
+                return Ok(futures::Async::Ready(()));
+            }
+            let mut srv = self.srv.borrow_mut();
+            srv.receiver = Some(futures::task::current());
+            let (n, addr) = match srv.s.poll_recv_from(&mut self.buf) {
+                Ok(futures::Async::Ready(x)) => x,
+                Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                Err(e) => {
+                    warn!("Failed to receive UDP packet: {}", e);
+                    continue;
+                }
+            };
+            let data = self.buf[..n].to_vec();
+
+            if let Some(sess) = srv.sessions.get(&addr) {
+                let mut sess = sess.borrow_mut();
+                sess.last_activity = Instant::now();
+                if n == 0 {
+                    debug!("Ignoring empty UDP packet from {}", addr);
+                } else if sess.queue.len() >= UDP_SERVER_QUEUE_LEN {
+                    debug!("Dropping UDP packet from {}: queue is full", addr);
+                } else {
+                    sess.queue.push_back(data);
+                }
+                if let Some(t) = sess.reader.take() {
+                    t.notify();
+                }
+                continue;
+            }
+
+            if !srv.listening {
+                debug!("Dropping UDP packet from {}: not accepting new peers", addr);
+                continue;
+            }
+            if srv.sessions.len() >= self.max_peers {
+                warn!("Dropping UDP packet from {}: too many peers", addr);
+                continue;
+            }
+
+            info!("New UDP peer {}", addr);
+            let mut queue = VecDeque::new();
+            if n > 0 {
+                queue.push_back(data);
+            }
+            let sess = Rc::new(RefCell::new(UdpSession {
+                addr,
+                queue,
+                reader: None,
+                last_activity: Instant::now(),
+                finished: false,
+            }));
+            srv.sessions.insert(addr, sess.clone());
+            srv.new_sessions.push_back(sess);
+            if let Some(t) = srv.listener.take() {
+                t.notify();
+            }
+        }
+    }
+}
+
+impl Stream for UdpServerStream {
+    type Item = Peer;
+    type Error = Box<dyn std::error::Error>;
+
+    fn poll(&mut self) -> futures::Poll<Option<Peer>, Self::Error> {
+        if let Some(r) = self.receiver.take() {
+            super::spawn_hack(r);
+        }
+        let mut srv = self.srv.borrow_mut();
+        let sess = match srv.new_sessions.pop_front() {
+            Some(x) => x,
+            None => {
+                srv.listener = Some(futures::task::current());
+                return Ok(futures::Async::NotReady);
+            }
+        };
+        drop(srv);
+
+        let addr = sess.borrow().addr;
+        if let L2rUser::FillIn(ref y) = self.l2r {
+            let mut z = y.borrow_mut();
+            z.client_addr = Some(format!("{}", addr));
+        }
+
+        let now = Instant::now();
+        let idle_timeout = Duration::from_secs(self.opts.udp_idle_timeout);
+        sess.borrow_mut().last_activity = now;
+        let r = UdpSessionReader {
+            srv: self.srv.clone(),
+            sess: sess.clone(),
+            timer: ::tokio_timer::Delay::new(now + idle_timeout),
+            idle_timeout,
+        };
+        let w = UdpSessionWriter {
+            srv: self.srv.clone(),
+            sess,
+            oneshot_mode: self.opts.udp_oneshot_mode,
+        };
+        Ok(futures::Async::Ready(Some(Peer::new(r, w, None))))
+    }
+}
+
+impl Drop for UdpServerStream {
+    fn drop(&mut self) {
+        let mut srv = self.srv.borrow_mut();
+        srv.listening = false;
+        // Sessions which were never yielded have no reader to clean them up
+        for sess in std::mem::take(&mut srv.new_sessions) {
+            let addr = sess.borrow().addr;
+            srv.sessions.remove(&addr);
+        }
+        srv.wake_receiver();
+    }
+}
+
+impl UdpSessionReader {
+    /// Stop routing packets to this connection.
+    /// Subsequent packets from the same address start a new one.
+    fn forget(&self) {
+        let mut sess = self.sess.borrow_mut();
+        sess.finished = true;
+        let mut srv = self.srv.borrow_mut();
+        if let Some(x) = srv.sessions.get(&sess.addr) {
+            if Rc::ptr_eq(x, &self.sess) {
+                srv.sessions.remove(&sess.addr);
+            }
+        }
+    }
+}
+
+impl Read for UdpSessionReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let deadline = {
+            let mut sess = self.sess.borrow_mut();
+            if let Some(data) = sess.queue.pop_front() {
+                let l = data.len().min(buf.len());
+                if l < data.len() {
+                    warn!("UDP packet from {} truncated to {} bytes", sess.addr, l);
+                }
+                buf[..l].copy_from_slice(&data[..l]);
+                return Ok(l);
+            }
+            if sess.finished {
+                drop(sess);
+                self.forget();
+                return Ok(0);
+            }
+            sess.reader = Some(futures::task::current());
+            sess.last_activity + self.idle_timeout
+        };
+        if self.timer.deadline() != deadline {
+            self.timer.reset(deadline);
+        }
+        match self.timer.poll() {
+            Ok(futures::Async::NotReady) => wouldblock(),
+            Ok(futures::Async::Ready(())) => {
+                info!("UDP peer {} timed out", self.sess.borrow().addr);
+                self.forget();
+                Ok(0)
+            }
+            Err(e) => {
+                error!("tokio-timer's Delay: {}", e);
+                wouldblock()
+            }
+        }
+    }
+}
+
+impl Drop for UdpSessionReader {
+    fn drop(&mut self) {
+        self.forget();
+        self.srv.borrow_mut().wake_receiver();
+    }
+}
+
+impl Drop for UdpSessionWriter {
+    fn drop(&mut self) {
+        self.srv.borrow_mut().wake_receiver();
+    }
+}
+
+impl Write for UdpSessionWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let mut sess = self.sess.borrow_mut();
+        let ret = self.srv.borrow_mut().s.send_to2(buf, &sess.addr)?;
+        sess.last_activity = Instant::now();
+        if self.oneshot_mode {
+            sess.finished = true;
+            if let Some(t) = sess.reader.take() {
+                t.notify();
+            }
+        }
+        Ok(ret)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for UdpSessionReader {}
+
+impl AsyncWrite for UdpSessionWriter {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        Ok(().into())
+    }
+}
+
 impl Read for UdpPeerHandle {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
         let mut p = self.0.borrow_mut();
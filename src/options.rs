@@ -12,10 +12,137 @@ pub struct StaticFile {
     pub content_type: String,
 }
 
+/// TLS protocol version for `--tls-min-version` and `--tls-max-version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+impl ::std::str::FromStr for TlsVersion {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim_start_matches("TLSv").trim_start_matches("tlsv");
+        Ok(match s {
+            "1.0" | "1" => TlsVersion::Tls10,
+            "1.1" => TlsVersion::Tls11,
+            "1.2" => TlsVersion::Tls12,
+            "1.3" => TlsVersion::Tls13,
+            _ => return Err(format!("Unknown TLS version {:?}, use 1.0, 1.1, 1.2 or 1.3", s)),
+        })
+    }
+}
+
+/// Where stderr of `exec:`, `sh-c:` and `cmd:` processes goes, for `--exec-stderr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecStderr {
+    /// Inherit websocat's own stderr
+    Inherit,
+    /// Mix with stdout
+    Merge,
+    /// Separate messages, each line prefixed with `--exec-stderr-prefix`
+    Message,
+    /// Append to a file
+    File(::std::path::PathBuf),
+    /// Log lines as warnings
+    Log,
+}
+
+impl ::std::str::FromStr for ExecStderr {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "inherit" => ExecStderr::Inherit,
+            "merge" => ExecStderr::Merge,
+            "message" => ExecStderr::Message,
+            "log" => ExecStderr::Log,
+            _ if s.starts_with("file:") && s.len() > 5 => ExecStderr::File(s[5..].into()),
+            _ => {
+                return Err(format!(
+                    "Unknown stderr mode {:?}, use inherit, merge, message, log or file:<path>",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+/// When to start exited `exec:`, `sh-c:` and `cmd:` processes again, for `--exec-restart`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecRestart {
+    Never,
+    /// Only if exit status is not successful
+    OnFailure,
+    Always,
+}
+
+impl ::std::str::FromStr for ExecRestart {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "never" | "no" => ExecRestart::Never,
+            "on-failure" => ExecRestart::OnFailure,
+            "always" => ExecRestart::Always,
+            _ => return Err(format!("Unknown restart policy {:?}, use never, on-failure or always", s)),
+        })
+    }
+}
+
+/// Which messages an overlay like `jq:` processes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageDirection {
+    /// Messages read from the underlying specifier
+    Read,
+    /// Messages written to the underlying specifier
+    Write,
+    Both,
+}
+
+impl MessageDirection {
+    pub fn reads(self) -> bool {
+        self != MessageDirection::Write
+    }
+    pub fn writes(self) -> bool {
+        self != MessageDirection::Read
+    }
+}
+
+impl ::std::str::FromStr for MessageDirection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "read" | "in" => MessageDirection::Read,
+            "write" | "out" => MessageDirection::Write,
+            "both" => MessageDirection::Both,
+            _ => return Err(format!("Unknown direction {:?}, use read, write or both", s)),
+        })
+    }
+}
+
+/// Message type for `--filter-type`. Text means valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMessageType {
+    Text,
+    Binary,
+}
+
+impl ::std::str::FromStr for FilterMessageType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "text" => FilterMessageType::Text,
+            "binary" => FilterMessageType::Binary,
+            _ => return Err(format!("Unknown message type {:?}, use text or binary", s)),
+        })
+    }
+}
+
 extern crate http_bytes;
 use http_bytes::http;
 
-#[derive(SmartDefault, Derivative)]
+#[derive(SmartDefault, Derivative, Clone)]
 #[derivative(Debug)]
 pub struct Options {
     pub websocket_text_mode: bool,
@@ -29,6 +156,10 @@ pub struct Options {
     pub udp_join_multicast_iface_v4: Vec<std::net::Ipv4Addr>,
     pub udp_join_multicast_iface_v6: Vec<u32>,
     pub udp_reuseaddr: bool,
+    #[default = 60]
+    pub udp_idle_timeout: u64,
+    #[default = 1024]
+    pub udp_max_peers: usize,
     pub unidirectional: bool,
     pub unidirectional_reverse: bool,
     pub max_messages: Option<usize>,
@@ -54,7 +185,35 @@ pub struct Options {
     pub broadcast_queue_len: usize,
     #[default(DebtHandling::Silent)]
     pub read_debt_handling: DebtHandling,
+    #[default = 16777216]
+    pub max_message_size: usize,
+    /// Set by linter when both sides are message-oriented:
+    /// messages longer than `buffer_size` are passed whole, up to `max_message_size`
+    pub whole_messages: bool,
     pub linemode_zero_terminated: bool,
+    #[default = 4]
+    pub lengthprefixed_nbytes: usize,
+    pub lengthprefixed_little_endian: bool,
+    pub lengthprefixed_varint: bool,
+    pub jsonrpc_raw: bool,
+    #[default = 10000]
+    pub jsonrpc_timeout_millis: u64,
+    #[cfg(feature = "jq")]
+    pub jq_filter: Option<std::rc::Rc<crate::jq::Filter>>,
+    #[default(MessageDirection::Read)]
+    pub jq_direction: MessageDirection,
+    pub jq_raw_output: bool,
+    pub jq_drop_non_json: bool,
+    #[cfg(feature = "filter")]
+    pub filter_regex: Option<std::rc::Rc<regex::bytes::Regex>>,
+    pub filter_prefix: Option<Vec<u8>>,
+    pub filter_type: Option<FilterMessageType>,
+    pub filter_min_size: Option<usize>,
+    pub filter_max_size: Option<usize>,
+    pub filter_invert: bool,
+    #[default(MessageDirection::Both)]
+    pub filter_direction: MessageDirection,
+    pub filter_tee: Option<std::rc::Rc<dyn crate::Specifier>>,
     pub restrict_uri: Option<String>,
     pub serve_static_files: Vec<StaticFile>,
     pub exec_set_env: bool,
@@ -62,6 +221,24 @@ pub struct Options {
     pub reuser_send_zero_msg_on_disconnect: bool,
     pub process_zero_sighup: bool,
     pub process_exit_sighup: bool,
+    pub exec_per_message: bool,
+    #[default = 1]
+    pub exec_max_parallel: usize,
+    pub exec_timeout: Option<u64>,
+    pub exec_per_message_report: bool,
+    #[default(ExecStderr::Inherit)]
+    pub exec_stderr: ExecStderr,
+    #[default("stderr: ".to_string())]
+    pub exec_stderr_prefix: String,
+    pub exec_pty: bool,
+    #[default(ExecRestart::Never)]
+    pub exec_restart: ExecRestart,
+    #[default = 1000]
+    pub exec_restart_delay_millis: u64,
+    pub exec_restart_max: Option<usize>,
+    pub exec_send_exit_status: bool,
+    #[default("\0".to_string())]
+    pub exec_pty_control_prefix: String,
     pub socks_destination: Option<SocksSocketAddr>,
     pub auto_socks5: Option<SocketAddr>,
     pub socks5_bind_script: Option<OsString>,
@@ -70,7 +247,19 @@ pub struct Options {
     pub pkcs12_der: Option<Vec<u8>>,
     #[derivative(Debug = "ignore")]
     pub pkcs12_passwd: Option<String>,
+    pub pem_cert: Option<::std::path::PathBuf>,
+    pub pem_key: Option<::std::path::PathBuf>,
     pub tls_insecure: bool,
+    pub tls_ca_files: Vec<::std::path::PathBuf>,
+    pub tls_no_system_roots: bool,
+    pub tls_pin_cert_sha256: Vec<[u8; 32]>,
+    pub tls_pin_pubkey_sha256: Vec<[u8; 32]>,
+    pub tls_alpn: Vec<String>,
+    pub tls_min_version: Option<TlsVersion>,
+    pub tls_max_version: Option<TlsVersion>,
+    pub tls_sni_certs: Vec<(String, ::std::path::PathBuf)>,
+    pub tls_sni_keys: Vec<(String, ::std::path::PathBuf)>,
+    pub sni_routes: Vec<(String, String)>,
 
     pub headers_to_env: Vec<String>,
 
@@ -83,6 +272,25 @@ pub struct Options {
     pub request_headers: Vec<(http::header::HeaderName, http::header::HeaderValue)>,
 
     pub autoreconnect_delay_millis: u64,
+    pub tcp_connect_timeout_millis: Option<u64>,
+    pub tcp_attempt_timeout_millis: Option<u64>,
+    #[default = 250]
+    pub tcp_happy_eyeballs_delay_millis: u64,
+    pub prefer_ipv4: bool,
+    pub prefer_ipv6: bool,
+    pub resolve_overrides: Vec<crate::resolve::ResolveOverride>,
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Option<u64>,
+    pub tcp_keepalive_interval: Option<u64>,
+    pub tcp_keepalive_count: Option<u32>,
+    pub tcp_bind_address: Option<std::net::SocketAddr>,
+    #[default = 1024]
+    pub tcp_listen_backlog: i32,
+    pub tcp_reuseport: bool,
+    pub tcp_so_mark: Option<u32>,
+    pub tcp_bind_device: Option<String>,
+    pub sd_notify: bool,
+    pub proxy_protocol_v2: bool,
 
     pub ws_text_prefix: Option<String>,
     pub ws_binary_prefix: Option<String>,
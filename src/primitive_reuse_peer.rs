@@ -5,7 +5,7 @@ use futures::future::ok;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use super::{BoxedNewPeerFuture, Peer};
+use super::{BoxedNewPeerFuture, Peer, PeerEvent};
 
 use std::io::{Error as IoError, Read, Write};
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -94,13 +94,17 @@ impl Write for PeerHandle {
 }
 impl AsyncWrite for PeerHandle {
     fn shutdown(&mut self) -> futures::Poll<(), IoError> {
-        if self.1 {
-            let _ = self.write(b"");
-        }
-        if let Some(ref mut _x) = *self.0.borrow_mut().deref_mut() {
+        if let Some(ref x) = *self.0.borrow() {
+            if self.1 {
+                self.1 = false;
+                match x.3 {
+                    Some(ref events) => events(PeerEvent::ClientDisconnected),
+                    None => warn!("The reused peer does not accept client disconnect notifications"),
+                }
+            }
             // Ignore shutdown attempts
             Ok(futures::Async::Ready(()))
-        //_x.1.shutdown()
+        //x.1.shutdown()
         } else {
             unreachable!()
         }
@@ -13,12 +13,25 @@ use std::rc::Rc;
 
 use std::process::Command;
 
-use self::tokio_process::{Child, CommandExt};
+use self::tokio_process::{Child, ChildStderr, ChildStdin, ChildStdout, CommandExt};
 
 use super::{once, ConstructParams, PeerConstructor, Specifier};
-use super::{BoxedNewPeerFuture, Peer};
+use super::{BoxedNewPeerFuture, Peer, PeerEvent};
 use std::process::Stdio;
 
+use futures::future::{Either, Future};
+use futures::task::Task;
+use futures::unsync::oneshot;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio_timer::Delay;
+
+use super::options::{ExecRestart, ExecStderr};
+use super::Options;
+use std::process::ExitStatus;
+use super::spawn_hack;
+use futures::Stream;
+
 fn needenv(p: &ConstructParams) -> Option<&LeftSpecToRightSpec> {
     match (p.program_options.exec_set_env, &p.left_to_right) {
         (true, &L2rUser::ReadFrom(ref x)) => Some(&**x),
@@ -30,24 +43,18 @@ fn needenv(p: &ConstructParams) -> Option<&LeftSpecToRightSpec> {
 pub struct Cmd(pub String);
 impl Specifier for Cmd {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
-        let zero_sighup = p.program_options.process_zero_sighup;
-        let exit_sighup = p.program_options.process_exit_sighup;
-        let args = if cfg!(target_os = "windows") {
-            let mut args = Command::new("cmd");
-            args.arg("/C").arg(self.0.clone());
-            args
-        } else {
-            let mut args = Command::new("sh");
-            args.arg("-c").arg(self.0.clone());
-            args
-        };
-        let env = needenv(&p);
-        once(Box::new(futures::future::result(process_connect_peer(
-            args,
-            env,
-            zero_sighup,
-            exit_sighup,
-        ))) as BoxedNewPeerFuture)
+        let cmdline = self.0.clone();
+        process_peer_constructor(&p, move || {
+            if cfg!(target_os = "windows") {
+                let mut args = Command::new("cmd");
+                args.arg("/C").arg(cmdline.clone());
+                args
+            } else {
+                let mut args = Command::new("sh");
+                args.arg("-c").arg(cmdline.clone());
+                args
+            }
+        })
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec );
 }
@@ -72,17 +79,12 @@ Otherwise should be the the same as `sh-c:` (see examples from there).
 pub struct ShC(pub String);
 impl Specifier for ShC {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
-        let zero_sighup = p.program_options.process_zero_sighup;
-        let exit_sighup = p.program_options.process_exit_sighup;
-        let mut args = Command::new("sh");
-        args.arg("-c").arg(self.0.clone());
-        let env = needenv(&p);
-        once(Box::new(futures::future::result(process_connect_peer(
-            args,
-            env,
-            zero_sighup,
-            exit_sighup,
-        ))) as BoxedNewPeerFuture)
+        let cmdline = self.0.clone();
+        process_peer_constructor(&p, move || {
+            let mut args = Command::new("sh");
+            args.arg("-c").arg(cmdline.clone());
+            args
+        })
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec );
 }
@@ -104,6 +106,15 @@ Example: serve a counter
 Example: unauthenticated shell
 
     websocat --exit-on-eof ws-l:127.0.0.1:5667 sh-c:'bash -i 2>&1'
+
+Example: the same, but deliver stderr as separate messages starting with `stderr: `
+
+    websocat --exit-on-eof ws-l:127.0.0.1:5667 sh-c:'bash -i' --exec-stderr message
+
+Example: backend for a browser terminal like xterm.js. Messages like
+`\0{"cols":120,"rows":40}` resize the terminal, `\0{"signal":"INT"}` send a signal.
+
+    websocat --exit-on-eof -b ws-l:127.0.0.1:5667 sh-c:'exec bash -l' --exec-pty
 "#
 );
 
@@ -111,17 +122,13 @@ Example: unauthenticated shell
 pub struct Exec(pub String);
 impl Specifier for Exec {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
-        let zero_sighup = p.program_options.process_zero_sighup;
-        let exit_sighup = p.program_options.process_exit_sighup;
-        let mut args = Command::new(self.0.clone());
-        args.args(p.program_options.exec_args.clone());
-        let env = needenv(&p);
-        once(Box::new(futures::future::result(process_connect_peer(
-            args,
-            env,
-            zero_sighup,
-            exit_sighup,
-        ))) as BoxedNewPeerFuture)
+        let program = self.0.clone();
+        let exec_args = p.program_options.exec_args.clone();
+        process_peer_constructor(&p, move || {
+            let mut args = Command::new(program.clone());
+            args.args(exec_args.clone());
+            args
+        })
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec );
 }
@@ -143,72 +150,568 @@ Example: Serve current date
 Example: pinger
 
   websocat -U ws-l:127.0.0.1:5667 exec:ping --exec-args 127.0.0.1 -c 1
+
+With --exec-per-message, `exec:`, `sh-c:` and `cmd:` start a new process for each
+incoming message instead of one process per connection. The message becomes
+process's stdin and its whole stdout is sent back as one message. Each request gets
+exactly one reply, in order of requests: empty if the process could not be started
+and without stderr, which is only available with --exec-per-message-report.
+See also --exec-max-parallel, --exec-timeout and --exec-per-message-report.
+
+Example: convert each message to uppercase, at most 4 processes at a time
+
+  websocat -E ws-l:127.0.0.1:5667 sh-c:'tr a-z A-Z' --exec-per-message --exec-max-parallel 4 --exec-timeout 10
+
+Exit code of the process becomes websocat's exit code when serving only one connection.
+Use --exec-send-exit-status to also report it to the peer and --exec-restart
+to keep a long-running backend process alive.
+
+Example: shared backend process, restarted if it crashes
+
+  websocat -E ws-l:127.0.0.1:5667 reuse-raw:exec:./backend --exec-restart on-failure
   
 "#
 );
 
+fn process_peer_constructor<F>(p: &ConstructParams, make_cmd: F) -> PeerConstructor
+where
+    F: Fn() -> Command + 'static,
+{
+    let opts = &p.program_options;
+    let env = needenv(p);
+    let mut prefix = opts.ws_text_prefix.clone().unwrap_or_default().into_bytes();
+    prefix.extend_from_slice(opts.exec_stderr_prefix.as_bytes());
+    let stderr = StderrConfig {
+        mode: opts.exec_stderr.clone(),
+        prefix,
+    };
+    if opts.exec_pty {
+        let mut cmd = make_cmd();
+        if let Some(x) = env {
+            set_process_env(&mut cmd, x);
+        }
+        #[cfg(all(unix, feature = "pty"))]
+        return once(Box::new(futures::future::result(
+            super::process_pty::pty_connect_peer(
+                cmd,
+                opts.exec_pty_control_prefix.clone().into_bytes(),
+                &p.global(SharedExitCodes::default),
+            ),
+        )) as BoxedNewPeerFuture);
+        #[cfg(not(all(unix, feature = "pty")))]
+        {
+            drop(cmd);
+            return PeerConstructor::Error("--exec-pty is not supported in this build".into());
+        }
+    }
+    if opts.exec_per_message {
+        let cfg = PerMessageConfig {
+            make_cmd: Box::new(make_cmd),
+            env: env.cloned(),
+            max_parallel: opts.exec_max_parallel,
+            timeout: opts.exec_timeout.map(Duration::from_secs),
+            report: opts.exec_per_message_report,
+            stderr,
+            grow_limit: if opts.whole_messages {
+                Some(opts.max_message_size)
+            } else {
+                None
+            },
+        };
+        return once(Box::new(futures::future::ok(per_message_peer(cfg))) as BoxedNewPeerFuture);
+    }
+    let spawner = Spawner {
+        make_cmd: Box::new(make_cmd),
+        env: env.cloned(),
+        stderr,
+        codes: p.global(SharedExitCodes::default).clone(),
+    };
+    once(Box::new(futures::future::result(process_connect_peer(spawner, opts))) as BoxedNewPeerFuture)
+}
+
+struct StderrConfig {
+    mode: ExecStderr,
+    /// For `message` mode, including `--text-prefix`
+    prefix: Vec<u8>,
+}
+
+impl StderrConfig {
+    /// Returns whether stderr is piped to us
+    fn setup(&self, cmd: &mut Command) -> IoResult<bool> {
+        match self.mode {
+            ExecStderr::Inherit => Ok(false),
+            ExecStderr::File(ref path) => {
+                let f = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?;
+                cmd.stderr(Stdio::from(f));
+                Ok(false)
+            }
+            ExecStderr::Merge | ExecStderr::Message | ExecStderr::Log => {
+                cmd.stderr(Stdio::piped());
+                Ok(true)
+            }
+        }
+    }
+
+}
+
+/// Insert the prefix at the beginning of each line
+fn prefix_lines(prefix: &[u8], data: &[u8], at_line_start: &mut bool) -> Vec<u8> {
+    let mut r = Vec::with_capacity(data.len() + prefix.len());
+    for &c in data {
+        if *at_line_start {
+            r.extend_from_slice(prefix);
+        }
+        r.push(c);
+        *at_line_start = c == b'\n';
+    }
+    r
+}
+
+fn log_stderr_lines(pid: u32, data: &[u8]) {
+    for l in String::from_utf8_lossy(data).lines() {
+        warn!("Process {} stderr: {}", pid, l);
+    }
+}
+
+fn set_process_env(cmd: &mut Command, x: &LeftSpecToRightSpec) {
+    if let Some(ref z) = x.client_addr {
+        cmd.env("WEBSOCAT_CLIENT", z);
+    };
+    if let Some(ref z) = x.uri {
+        cmd.env("WEBSOCAT_URI", z);
+    };
+    if let Some(ref z) = x.tls_server_name {
+        cmd.env("WEBSOCAT_TLS_SNI", z);
+    };
+    for (hn, hv) in &x.headers {
+        cmd.env(format!("H_{}", hn), hv);
+    }
+}
+
+/// How to start (and restart) the process
+struct Spawner {
+    make_cmd: Box<dyn Fn() -> Command>,
+    env: Option<LeftSpecToRightSpec>,
+    stderr: StderrConfig,
+    codes: SharedExitCodes,
+}
+
+/// Started process with its stdio
+struct Spawned {
+    child: ChildHandle,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    stderr: Option<StderrReader>,
+}
+
+impl Spawner {
+    fn spawn(&self) -> IoResult<Spawned> {
+        let mut cmd = (self.make_cmd)();
+        if let Some(ref x) = self.env {
+            set_process_env(&mut cmd, x);
+        }
+        cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+        self.stderr.setup(&mut cmd)?;
+        let mut child = cmd.spawn_async()?;
+        let stdin = child.stdin().take().expect("assertion failed 1425");
+        let stdout = child.stdout().take().expect("assertion failed 1425");
+        let stderr_reader = match self.stderr.mode {
+            ExecStderr::Merge | ExecStderr::Message => Some(StderrReader {
+                stream: child.stderr().take().expect("assertion failed 1427"),
+                message_prefix: if self.stderr.mode == ExecStderr::Message {
+                    Some(self.stderr.prefix.clone())
+                } else {
+                    None
+                },
+                pending: vec![],
+                at_line_start: true,
+                eof: false,
+            }),
+            ExecStderr::Log => {
+                let pid = child.id();
+                let se = child.stderr().take().expect("assertion failed 1427");
+                let lines = tokio_io::io::lines(std::io::BufReader::new(se));
+                spawn_hack(
+                    lines
+                        .for_each(move |l| {
+                            log_stderr_lines(pid, l.as_bytes());
+                            Ok(())
+                        })
+                        .map_err(|e| debug!("Failed to read process stderr: {}", e)),
+                );
+                None
+            }
+            _ => None,
+        };
+        Ok(Spawned {
+            child: ChildHandle::new(child, &self.codes),
+            stdin,
+            stdout,
+            stderr: stderr_reader,
+        })
+    }
+}
+
 fn process_connect_peer(
-    mut cmd: Command,
-    l2r: Option<&LeftSpecToRightSpec>,
+    spawner: Spawner,
+    opts: &Options,
+) -> Result<Peer, Box<dyn std::error::Error>> {
+    let p = spawner.spawn()?;
+    let ph = ProcessPeer(Rc::new(RefCell::new(ProcessState {
+        spawner,
+        child: p.child,
+        stdin: Some(p.stdin),
+        stdout: p.stdout,
+        stderr: p.stderr,
+        zero_sighup: opts.process_zero_sighup,
+        close_sighup: opts.process_exit_sighup,
+        restart: opts.exec_restart,
+        restart_delay: Duration::from_millis(opts.exec_restart_delay_millis),
+        restarts_left: opts.exec_restart_max,
+        send_exit_status: opts.exec_send_exit_status,
+        restart_timer: None,
+        exited: false,
+        finished: false,
+        stdin_closed: false,
+        status_message: vec![],
+        writer: None,
+    })));
+    let state = ph.0.clone();
+    let events = Rc::new(move |ev: PeerEvent| match ev {
+        PeerEvent::ClientDisconnected => state.borrow().sighup(),
+    });
+    Ok(Peer::new(ph.clone(), ph, None /* TODO */).with_events(events))
+}
+
+/// Stderr of the process read along with stdout
+struct StderrReader {
+    stream: ChildStderr,
+    /// `None` means merging stderr into stdout as is
+    message_prefix: Option<Vec<u8>>,
+    pending: Vec<u8>,
+    at_line_start: bool,
+    eof: bool,
+}
+
+impl StderrReader {
+    fn take_pending(&mut self, buf: &mut [u8]) -> usize {
+        let n = self.pending.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        n
+    }
+}
+
+/// Exit code of the most recently exited process started for this `serve`,
+/// for propagating it as websocat's own exit code.
+#[derive(Default)]
+pub struct ExitCodes {
+    last: Option<i32>,
+    /// Number of processes not yet reaped
+    running: usize,
+    waiter: Option<Task>,
+}
+
+pub type SharedExitCodes = Rc<RefCell<ExitCodes>>;
+
+/// Resolves to the last recorded exit code after all processes are reaped
+pub fn wait_exit_code(codes: SharedExitCodes) -> impl Future<Item = Option<i32>, Error = ()> {
+    futures::future::poll_fn(move || {
+        let mut c = codes.borrow_mut();
+        if c.running > 0 {
+            c.waiter = Some(futures::task::current());
+            return Ok(futures::Async::NotReady);
+        }
+        Ok(futures::Async::Ready(c.last))
+    })
+}
+
+/// Killed processes get 128 + signal number
+fn exit_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    let code = {
+        use std::os::unix::process::ExitStatusExt;
+        status.code().or_else(|| status.signal().map(|x| 128 + x))
+    };
+    #[cfg(not(unix))]
+    let code = status.code();
+    code.unwrap_or(1)
+}
+
+#[derive(Default)]
+struct ChildExit {
+    status: Option<IoResult<ExitStatus>>,
+    reader: Option<Task>,
+}
+
+/// Waits for the process to exit and records its exit code.
+/// Kills the process if the `ChildHandle` is dropped before that.
+struct ChildWait {
+    child: Child,
+    killer: Option<oneshot::Receiver<()>>,
+    exit: Rc<RefCell<ChildExit>>,
+    codes: SharedExitCodes,
+}
+
+impl Future for ChildWait {
+    type Item = ();
+    type Error = ();
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        loop {
+            let status = match self.child.poll() {
+                Ok(futures::Async::NotReady) => {
+                    if let Some(ref mut k) = self.killer {
+                        if let Ok(futures::Async::NotReady) = k.poll() {
+                            return Ok(futures::Async::NotReady);
+                        }
+                    } else {
+                        return Ok(futures::Async::NotReady);
+                    }
+                    self.killer = None;
+                    debug!("Killing process {}", self.child.id());
+                    if let Err(e) = self.child.kill() {
+                        debug!("Failed to kill process: {}", e);
+                    }
+                    continue;
+                }
+                Ok(futures::Async::Ready(x)) => Ok(x),
+                Err(e) => Err(e),
+            };
+            match status {
+                // Exit caused by our own kill is not the process's exit code
+                Ok(ref x) if self.killer.is_some() => {
+                    self.codes.borrow_mut().last = Some(exit_code(x));
+                }
+                Ok(_) => (),
+                Err(ref e) => error!("Failed to wait for process: {}", e),
+            }
+            let mut ex = self.exit.borrow_mut();
+            ex.status = Some(status);
+            if let Some(t) = ex.reader.take() {
+                t.notify();
+            }
+            return Ok(futures::Async::Ready(()));
+        }
+    }
+}
+
+impl Drop for ChildWait {
+    fn drop(&mut self) {
+        let mut c = self.codes.borrow_mut();
+        c.running -= 1;
+        if let Some(t) = c.waiter.take() {
+            t.notify();
+        }
+    }
+}
+
+/// Our side of a started process, with its stdio taken out
+pub(crate) struct ChildHandle {
+    pub pid: u32,
+    exit: Rc<RefCell<ChildExit>>,
+    _killer: oneshot::Sender<()>,
+}
+
+impl ChildHandle {
+    pub fn new(child: Child, codes: &SharedExitCodes) -> ChildHandle {
+        let exit: Rc<RefCell<ChildExit>> = Default::default();
+        let (tx, rx) = oneshot::channel();
+        codes.borrow_mut().running += 1;
+        let pid = child.id();
+        spawn_hack(ChildWait {
+            child,
+            killer: Some(rx),
+            exit: exit.clone(),
+            codes: codes.clone(),
+        });
+        ChildHandle {
+            pid,
+            exit,
+            _killer: tx,
+        }
+    }
+
+    /// Exit status of the process. Resolves only once.
+    pub fn poll_exit(&self) -> futures::Poll<ExitStatus, std::io::Error> {
+        let mut ex = self.exit.borrow_mut();
+        match ex.status.take() {
+            Some(x) => x.map(futures::Async::Ready),
+            None => {
+                ex.reader = Some(futures::task::current());
+                Ok(futures::Async::NotReady)
+            }
+        }
+    }
+}
+
+struct ProcessState {
+    spawner: Spawner,
+    child: ChildHandle,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+    stderr: Option<StderrReader>,
     zero_sighup: bool,
     close_sighup: bool,
-) -> Result<Peer, Box<dyn std::error::Error>> {
-    if let Some(x) = l2r {
-        if let Some(ref z) = x.client_addr {
-            cmd.env("WEBSOCAT_CLIENT", z);
-        };
-        if let Some(ref z) = x.uri {
-            cmd.env("WEBSOCAT_URI", z);
+    restart: ExecRestart,
+    restart_delay: Duration,
+    restarts_left: Option<usize>,
+    send_exit_status: bool,
+    /// Waiting before starting the process again
+    restart_timer: Option<Delay>,
+    exited: bool,
+    /// Process has exited and won't be restarted
+    finished: bool,
+    stdin_closed: bool,
+    status_message: Vec<u8>,
+    writer: Option<Task>,
+}
+
+impl ProcessState {
+    fn on_exit(&mut self, status: ExitStatus) {
+        self.exited = true;
+        info!("Process {} exited with {}", self.child.pid, status);
+        if self.send_exit_status {
+            self.status_message = format!("{{\"exit_code\":{}}}\n", exit_code(&status)).into_bytes();
+        }
+        let restart = match self.restart {
+            ExecRestart::Never => false,
+            ExecRestart::OnFailure => !status.success(),
+            ExecRestart::Always => true,
         };
-        for (hn, hv) in &x.headers {
-            cmd.env(format!("H_{}", hn), hv);
+        if restart && !self.stdin_closed && self.restarts_left != Some(0) {
+            if let Some(ref mut n) = self.restarts_left {
+                *n -= 1;
+            }
+            info!("Restarting the process in {:?}", self.restart_delay);
+            self.restart_timer = Some(Delay::new(Instant::now() + self.restart_delay));
+        } else {
+            self.finished = true;
+        }
+        if let Some(t) = self.writer.take() {
+            t.notify();
+        }
+    }
+
+    fn respawn(&mut self) -> IoResult<()> {
+        let p = self.spawner.spawn()?;
+        info!("Restarted the process, new pid {}", p.child.pid);
+        self.child = p.child;
+        self.stdin = Some(p.stdin);
+        self.stdout = p.stdout;
+        self.stderr = p.stderr;
+        self.exited = false;
+        if let Some(t) = self.writer.take() {
+            t.notify();
+        }
+        Ok(())
+    }
+
+    fn sighup(&self) {
+        #[cfg(all(unix, feature = "libc"))]
+        {
+            // TODO use nix crate?
+            let pid = self.child.pid;
+            unsafe {
+                extern crate libc;
+                libc::kill(pid as libc::pid_t, libc::SIGHUP);
+            }
         }
     }
-    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
-    let child = cmd.spawn_async()?;
-    let ph = ProcessPeer(Rc::new(RefCell::new(child)), zero_sighup, close_sighup);
-    Ok(Peer::new(ph.clone(), ph, None /* TODO */))
 }
 
 #[derive(Clone)]
-struct ProcessPeer(Rc<RefCell<Child>>, bool, bool);
+struct ProcessPeer(Rc<RefCell<ProcessState>>);
 
 impl Read for ProcessPeer {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        self.0
-            .borrow_mut()
-            .stdout()
-            .as_mut()
-            .expect("assertion failed 1425")
-            .read(buf)
+        let mut guard = self.0.borrow_mut();
+        let st = &mut *guard;
+        loop {
+            if !st.status_message.is_empty() {
+                let n = st.status_message.len().min(buf.len());
+                buf[..n].copy_from_slice(&st.status_message[..n]);
+                st.status_message.drain(..n);
+                return Ok(n);
+            }
+            if let Some(ref mut t) = st.restart_timer {
+                match t.poll() {
+                    Ok(futures::Async::NotReady) => return Err(std::io::ErrorKind::WouldBlock.into()),
+                    Ok(futures::Async::Ready(())) => (),
+                    Err(e) => return Err(crate::simple_err(e.to_string())),
+                }
+                st.restart_timer = None;
+                st.respawn()?;
+                continue;
+            }
+            if st.finished {
+                return Ok(0);
+            }
+
+            let mut stderr_open = false;
+            if let Some(ref mut se) = st.stderr {
+                if !se.pending.is_empty() {
+                    return Ok(se.take_pending(buf));
+                }
+                if !se.eof {
+                    match se.stream.read(buf) {
+                        Ok(0) => se.eof = true,
+                        Ok(n) => {
+                            if let Some(ref prefix) = se.message_prefix {
+                                se.pending = prefix_lines(prefix, &buf[..n], &mut se.at_line_start);
+                                return Ok(se.take_pending(buf));
+                            }
+                            return Ok(n);
+                        }
+                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => stderr_open = true,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            match st.stdout.read(buf) {
+                // Deliver the rest of stderr before EOF
+                Ok(0) if stderr_open => return Err(std::io::ErrorKind::WouldBlock.into()),
+                Ok(0) => {
+                    // Wait for exit status before reporting EOF
+                    match st.child.poll_exit()? {
+                        futures::Async::Ready(status) => st.on_exit(status),
+                        futures::Async::NotReady => return Err(std::io::ErrorKind::WouldBlock.into()),
+                    }
+                }
+                r => return r,
+            }
+        }
     }
 }
 
 impl Write for ProcessPeer {
     fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
-        #[cfg(all(unix, feature = "libc"))]
-        {
-            if self.1 && buf.is_empty() {
-                // TODO use nix crate?
-                let pid = self.0.borrow().id();
-                unsafe {
-                    extern crate libc;
-                    libc::kill(pid as libc::pid_t, libc::SIGHUP);
-                }
+        let mut st = self.0.borrow_mut();
+        if st.exited && !st.finished {
+            // Waiting for restart
+            st.writer = Some(futures::task::current());
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        if st.zero_sighup && buf.is_empty() {
+            st.sighup();
+        }
+        let restartable = st.restart != ExecRestart::Never && !st.finished;
+        match st.stdin.as_mut().expect("assertion failed 1425").write(buf) {
+            Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe && restartable => {
+                debug!("Process has closed its stdin, waiting for it to exit and restart");
+                st.writer = Some(futures::task::current());
+                Err(std::io::ErrorKind::WouldBlock.into())
             }
+            r => r,
         }
-        self.0
-            .borrow_mut()
-            .stdin()
-            .as_mut()
-            .expect("assertion failed 1425")
-            .write(buf)
     }
 
     fn flush(&mut self) -> IoResult<()> {
         self.0
             .borrow_mut()
-            .stdin()
+            .stdin
             .as_mut()
             .expect("assertion failed 1425")
             .flush()
@@ -219,23 +722,248 @@ impl AsyncRead for ProcessPeer {}
 
 impl AsyncWrite for ProcessPeer {
     fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
-        #[cfg(all(unix, feature = "libc"))]
+        let mut st = self.0.borrow_mut();
+        st.stdin_closed = true;
+        if st.close_sighup {
+            st.sighup();
+        }
+        match st.stdin.take() {
+            Some(mut c) => c.shutdown(),
+            None => Ok(futures::Async::Ready(())),
+        }
+    }
+}
+
+struct PerMessageConfig {
+    make_cmd: Box<dyn Fn() -> Command>,
+    env: Option<LeftSpecToRightSpec>,
+    max_parallel: usize,
+    timeout: Option<Duration>,
+    report: bool,
+    stderr: StderrConfig,
+    /// Ask reader for a bigger buffer instead of splitting replies up to this size
+    grow_limit: Option<usize>,
+}
+
+#[derive(Default)]
+struct PerMessageState {
+    /// A slot for each started process in order of requests, filled when it finishes
+    replies: VecDeque<Option<Vec<u8>>>,
+    /// Sequence number of the request in the front slot of `replies`
+    first_seq: u64,
+    running: usize,
+    shutdown: bool,
+    reader: Option<Task>,
+    writer: Option<Task>,
+}
+
+/// `--exec-per-message` mode: each written message is fed to a new process,
+/// its complete output is read back as one message.
+#[derive(Clone)]
+struct PerMessagePeer(Rc<PerMessageConfig>, Rc<RefCell<PerMessageState>>);
+
+fn per_message_peer(cfg: PerMessageConfig) -> Peer {
+    let ph = PerMessagePeer(Rc::new(cfg), Default::default());
+    Peer::new(ph.clone(), ph, None)
+}
+
+struct ProcessOutcome {
+    pid: u32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    status: Option<std::process::ExitStatus>,
+    timed_out: bool,
+}
+
+impl PerMessagePeer {
+    fn start_job(&self, msg: Vec<u8>) {
+        let cfg = &self.0;
+        let mut cmd = (cfg.make_cmd)();
+        if let Some(ref x) = cfg.env {
+            set_process_env(&mut cmd, x);
+        }
+        cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+
+        let state = self.1.clone();
+        let cfg2 = self.0.clone();
+        let seq = {
+            let mut st = self.1.borrow_mut();
+            st.running += 1;
+            st.replies.push_back(None);
+            st.first_seq + st.replies.len() as u64 - 1
+        };
+        let finish = move |outcome: Result<ProcessOutcome, String>| {
+            let mut st = state.borrow_mut();
+            st.running -= 1;
+            let slot = (seq - st.first_seq) as usize;
+            st.replies[slot] = Some(format_outcome(outcome, &cfg2));
+            if let Some(t) = st.reader.take() {
+                t.notify();
+            }
+            if let Some(t) = st.writer.take() {
+                t.notify();
+            }
+        };
+
+        if cfg.report {
+            cmd.stderr(Stdio::piped());
+        } else if let Err(e) = cfg.stderr.setup(&mut cmd) {
+            finish(Err(format!("Failed to set up process stderr: {}", e)));
+            return;
+        }
+        let mut child = match cmd.spawn_async() {
+            Ok(x) => x,
+            Err(e) => {
+                finish(Err(format!("Failed to start process: {}", e)));
+                return;
+            }
+        };
+        // Dropping stdin after the message is written closes it
+        let stdin = child.stdin().take().expect("assertion failed 1426");
+        let input = tokio_io::io::write_all(stdin, msg).then(|r| {
+            if let Err(e) = r {
+                debug!("Failed to write message to process stdin: {}", e);
+            }
+            Ok(())
+        });
+        let pid = child.id();
+        let work = input.join(child.wait_with_output()).map(move |((), o)| ProcessOutcome {
+            pid,
+            stdout: o.stdout,
+            stderr: o.stderr,
+            status: Some(o.status),
+            timed_out: false,
+        });
+        let work: Box<dyn Future<Item = ProcessOutcome, Error = std::io::Error>> = match cfg.timeout {
+            None => Box::new(work),
+            Some(t) => {
+                // Child process gets killed when `work` is dropped
+                let timer = Delay::new(Instant::now() + t);
+                Box::new(work.select2(timer).then(move |r| match r {
+                    Ok(Either::A((x, _))) => Ok(x),
+                    Err(Either::A((e, _))) => Err(e),
+                    Ok(Either::B(_)) | Err(Either::B(_)) => {
+                        warn!("Process timed out and was killed");
+                        Ok(ProcessOutcome {
+                            pid,
+                            stdout: vec![],
+                            stderr: vec![],
+                            status: None,
+                            timed_out: true,
+                        })
+                    }
+                }))
+            }
+        };
+        spawn_hack(work.then(move |r| {
+            finish(r.map_err(|e| format!("Failed to wait for process: {}", e)));
+            Ok(())
+        }));
+    }
+}
+
+/// Make exactly one reply message for a request, so replies stay in step with requests
+fn format_outcome(o: Result<ProcessOutcome, String>, cfg: &PerMessageConfig) -> Vec<u8> {
+    let mut o = match o {
+        Ok(x) => x,
+        Err(e) => {
+            error!("{}", e);
+            if !cfg.report {
+                return vec![];
+            }
+            ProcessOutcome {
+                pid: 0,
+                stdout: vec![],
+                stderr: e.into_bytes(),
+                status: None,
+                timed_out: false,
+            }
+        }
+    };
+    if let Some(ref s) = o.status {
+        if !s.success() {
+            info!("Process exited with {}", s);
+        }
+    }
+    if !cfg.report {
+        match cfg.stderr.mode {
+            ExecStderr::Merge => o.stdout.extend_from_slice(&o.stderr),
+            // A separate stderr message would break one reply per request
+            ExecStderr::Log | ExecStderr::Message => log_stderr_lines(o.pid, &o.stderr),
+            _ => (),
+        }
+        return o.stdout;
+    }
+    let report = serde_json::json!({
+        "stdout": String::from_utf8_lossy(&o.stdout),
+        "stderr": String::from_utf8_lossy(&o.stderr),
+        "exit_code": o.status.and_then(|s| s.code()),
+        "timed_out": o.timed_out,
+    });
+    format!("{}\n", report).into_bytes()
+}
+
+impl Read for PerMessagePeer {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let mut st = self.1.borrow_mut();
+        let ready = match st.replies.front() {
+            Some(Some(reply)) => Some(reply.len()),
+            _ => None,
+        };
+        if let Some(l) = ready {
+            if l > buf.len() && self.0.grow_limit.is_some_and(|x| l <= x) {
+                return Err(crate::util::io_other_error(crate::readdebt::MessageTooLong(l)));
+            }
+            let mut reply = st.replies.pop_front().unwrap().unwrap();
+            st.first_seq += 1;
+            if l > buf.len() {
+                warn!("Process output of {} bytes is too big for buffer, replying with empty message", l);
+                reply.clear();
+            }
+            if reply.is_empty() {
+                return Err(crate::readdebt::empty_message());
+            }
+            buf[..l].copy_from_slice(&reply);
+            return Ok(l);
+        }
+        if st.shutdown && st.running == 0 {
+            return Ok(0);
+        }
+        st.reader = Some(futures::task::current());
+        Err(std::io::ErrorKind::WouldBlock.into())
+    }
+}
+
+impl Write for PerMessagePeer {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
         {
-            if self.2 {
-                // TODO use nix crate?
-                let pid = self.0.borrow().id();
-                unsafe {
-                    extern crate libc;
-                    libc::kill(pid as libc::pid_t, libc::SIGHUP);
-                }
+            let mut st = self.1.borrow_mut();
+            if st.running >= self.0.max_parallel {
+                st.writer = Some(futures::task::current());
+                return Err(std::io::ErrorKind::WouldBlock.into());
             }
         }
-        let mut c: tokio_process::ChildStdin = self
-            .0
-            .borrow_mut()
-            .stdin()
-            .take()
-            .expect("assertion failed 1425");
-        c.shutdown()
+        self.start_job(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for PerMessagePeer {}
+
+impl AsyncWrite for PerMessagePeer {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        let mut st = self.1.borrow_mut();
+        st.shutdown = true;
+        if let Some(t) = st.reader.take() {
+            t.notify();
+        }
+        Ok(futures::Async::Ready(()))
     }
 }
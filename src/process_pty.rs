@@ -0,0 +1,247 @@
+//! Pseudo-terminal for `exec:`, `sh-c:` and `cmd:` processes (`--exec-pty`)
+
+extern crate libc;
+extern crate tokio_file_unix;
+extern crate tokio_process;
+
+use futures;
+use std;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Result as IoResult;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::process::CommandExt as UnixCommandExt;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use self::tokio_file_unix::File as UnixFile;
+use self::tokio_process::CommandExt;
+
+use super::Peer;
+use super::process_peer::{ChildHandle, SharedExitCodes};
+
+type PtyMaster = ::tokio_reactor::PollEvented<UnixFile<File>>;
+
+/// Terminal size before the first resize request
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// Start the process with a new PTY as its controlling terminal and stdin/stdout/stderr.
+/// Messages starting with `control_prefix` followed by a JSON object are not
+/// sent to the terminal, but interpreted as control requests.
+pub fn pty_connect_peer(
+    mut cmd: Command,
+    control_prefix: Vec<u8>,
+    codes: &SharedExitCodes,
+) -> Result<Peer, Box<dyn std::error::Error>> {
+    let (master, slave) = open_pty()?;
+    set_window_size(master, DEFAULT_SIZE.0, DEFAULT_SIZE.1)?;
+    let master = unsafe { File::from_raw_fd(master) };
+
+    cmd.stdin(Stdio::from(slave.try_clone()?));
+    cmd.stdout(Stdio::from(slave.try_clone()?));
+    cmd.stderr(Stdio::from(slave));
+    if std::env::var_os("TERM").is_none() {
+        cmd.env("TERM", "xterm");
+    }
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = cmd.spawn_async()?;
+    // `cmd` holds the slave side open until dropped here,
+    // after that reading the master signals EOF when the process exits.
+    drop(cmd);
+
+    let fd = std::os::unix::io::AsRawFd::as_raw_fd(&master);
+    let master = UnixFile::new_nb(master)?.into_io(&tokio_reactor::Handle::default())?;
+    let ph = PtyPeer(Rc::new(RefCell::new(PtyShared {
+        master,
+        fd,
+        child: ChildHandle::new(child, codes),
+        exited: false,
+        control_prefix,
+    })));
+    Ok(Peer::new(ph.clone(), ph, None))
+}
+
+/// Returns master file descriptor and opened slave side
+fn open_pty() -> IoResult<(RawFd, File)> {
+    let err = std::io::Error::last_os_error;
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if master == -1 {
+            return Err(err());
+        }
+        // Make sure master gets closed on errors below
+        let master_file = File::from_raw_fd(master);
+        if libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC) == -1
+            || libc::grantpt(master) == -1
+            || libc::unlockpt(master) == -1
+        {
+            return Err(err());
+        }
+        let name = libc::ptsname(master);
+        if name.is_null() {
+            return Err(err());
+        }
+        let name = std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned();
+        debug!("Allocated pseudo-terminal {}", name);
+        let slave = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(name)?;
+        Ok((std::os::unix::io::IntoRawFd::into_raw_fd(master_file), slave))
+    }
+}
+
+fn set_window_size(fd: RawFd, cols: u16, rows: u16) -> IoResult<()> {
+    let ws = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ as _, &ws) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn signal_number(name: &str) -> Option<libc::c_int> {
+    if let Ok(n) = name.parse() {
+        return Some(n);
+    }
+    let name = name.trim_start_matches("SIG");
+    Some(match name {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "TERM" => libc::SIGTERM,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "CONT" => libc::SIGCONT,
+        "WINCH" => libc::SIGWINCH,
+        _ => return None,
+    })
+}
+
+struct PtyShared {
+    master: PtyMaster,
+    fd: RawFd,
+    child: ChildHandle,
+    exited: bool,
+    control_prefix: Vec<u8>,
+}
+
+impl PtyShared {
+    /// Send signal to foreground process group of the terminal,
+    /// falling back to the process we have started.
+    fn kill(&self, sig: libc::c_int) {
+        let pgrp = unsafe { libc::tcgetpgrp(self.fd) };
+        let target = if pgrp > 0 {
+            -pgrp
+        } else {
+            self.child.pid as libc::pid_t
+        };
+        debug!("Sending signal {} to {}", sig, target);
+        unsafe {
+            libc::kill(target, sig);
+        }
+    }
+
+    fn control(&mut self, msg: &serde_json::Value) -> Result<(), String> {
+        let dim = |k| msg.get(k).and_then(|x| x.as_u64()).map(|x| x.min(u16::MAX as u64) as u16);
+        match (dim("cols"), dim("rows")) {
+            (Some(cols), Some(rows)) => {
+                debug!("Resizing terminal to {}x{}", cols, rows);
+                set_window_size(self.fd, cols, rows).map_err(|e| e.to_string())?;
+            }
+            (None, None) => (),
+            _ => return Err("both `cols` and `rows` are needed to resize".to_string()),
+        }
+        if let Some(s) = msg.get("signal") {
+            let name = match s {
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::String(n) => n.clone(),
+                _ => String::new(),
+            };
+            let sig = signal_number(&name).ok_or_else(|| format!("unknown signal {}", s))?;
+            self.kill(sig);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct PtyPeer(Rc<RefCell<PtyShared>>);
+
+impl Read for PtyPeer {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let mut s = self.0.borrow_mut();
+        let r = match s.master.read(buf) {
+            // Linux reports closed slave side as EIO
+            Err(ref e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+            r => r,
+        };
+        if let Ok(0) = r {
+            if !s.exited {
+                // Wait for exit status before reporting EOF
+                match s.child.poll_exit()? {
+                    futures::Async::Ready(status) => {
+                        s.exited = true;
+                        info!("Process {} exited with {}", s.child.pid, status);
+                    }
+                    futures::Async::NotReady => return Err(std::io::ErrorKind::WouldBlock.into()),
+                }
+            }
+        }
+        r
+    }
+}
+
+impl Write for PtyPeer {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let mut s = self.0.borrow_mut();
+        if !s.control_prefix.is_empty() && buf.starts_with(&s.control_prefix) {
+            let j = serde_json::from_slice::<serde_json::Value>(&buf[s.control_prefix.len()..]);
+            match j {
+                Ok(ref j @ serde_json::Value::Object(_)) => {
+                    if let Err(e) = s.control(j) {
+                        warn!("Invalid PTY control message: {}", e);
+                    }
+                    return Ok(buf.len());
+                }
+                _ => debug!("Not a PTY control message, passing it to the terminal"),
+            }
+        }
+        s.master.write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.0.borrow_mut().master.flush()
+    }
+}
+
+impl AsyncRead for PtyPeer {}
+
+impl AsyncWrite for PtyPeer {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        // Like closing a terminal window
+        let s = self.0.borrow();
+        unsafe {
+            libc::kill(-(s.child.pid as libc::pid_t), libc::SIGHUP);
+        }
+        Ok(futures::Async::Ready(()))
+    }
+}
@@ -0,0 +1,413 @@
+use futures::future::Future;
+use futures::{Async, Poll};
+
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+
+use super::{box_up_err, BoxedNewPeerFuture, L2rUser, Peer};
+use super::{ConstructParams, PeerConstructor, Specifier};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[derive(Debug)]
+pub struct ProxyProtoAccept<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for ProxyProtoAccept<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, l2r| {
+            Box::new(ProxyHeaderRead(Some(p), vec![]).map_err(box_up_err).map(
+                move |(p, addrs)| {
+                    if let Some((src, dst)) = addrs {
+                        info!("PROXY protocol: client {}, server {}", src, dst);
+                        if let L2rUser::FillIn(ref y) = l2r {
+                            let mut z = y.borrow_mut();
+                            z.client_addr = Some(format!("{}", src));
+                            z.server_addr = Some(format!("{}", dst));
+                        }
+                    } else {
+                        debug!("PROXY protocol header without addresses");
+                    }
+                    p
+                },
+            )) as BoxedNewPeerFuture
+        })
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = ProxyProtoAcceptClass,
+    target = ProxyProtoAccept,
+    prefixes = ["proxy-protocol:", "proxy-protocol-accept:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Expect PROXY protocol (v1 or v2) header at the beginning of incoming connection,
+as sent by HAProxy, AWS NLB and other load balancers. [A]
+
+Client address from the header replaces the load balancer's address
+in logs and in WEBSOCAT_CLIENT environment variable for `exec:`.
+Connections without a valid header are rejected.
+
+Example: WebSocket server behind a load balancer
+
+    websocat -E ws-u:proxy-protocol:tcp-l:0.0.0.0:8080 sh-c:'echo $WEBSOCAT_CLIENT' -e
+
+Example: the same with TLS terminated by websocat
+
+    websocat -E --pem-cert=c.pem --pem-key=k.pem ws-u:tls-accept:proxy-protocol:tcp-l:0.0.0.0:8443 tcp:127.0.0.1:22
+"#
+);
+
+#[derive(Debug)]
+pub struct ProxyProtoSend<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for ProxyProtoSend<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let v2 = cp.program_options.proxy_protocol_v2;
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, l2r| {
+            let addrs = match l2r {
+                L2rUser::ReadFrom(ref x) => {
+                    let parse = |a: &Option<String>| a.as_ref().and_then(|a| a.parse().ok());
+                    match (parse(&x.client_addr), parse(&x.server_addr)) {
+                        (Some(src), Some(dst)) => Some((src, dst)),
+                        _ => None,
+                    }
+                }
+                L2rUser::FillIn(_) => None,
+            };
+            let header = if v2 {
+                proxy_header_v2(addrs)
+            } else {
+                proxy_header_v1(addrs)
+            };
+            let Peer(r, w, hup, events) = p;
+            Box::new(
+                tokio_io::io::write_all(w, header)
+                    .map(move |(w, _)| Peer(r, w, hup, events))
+                    .map_err(box_up_err),
+            ) as BoxedNewPeerFuture
+        })
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = ProxyProtoSendClass,
+    target = ProxyProtoSend,
+    prefixes = ["proxy-protocol-send:", "send-proxy-protocol:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Send PROXY protocol header with the address of the client connected to the left side
+before any other data. Version 1 (text) is used unless --proxy-protocol-v2 is specified. [A]
+
+If client address is not known (e.g. left side is not a TCP listener),
+`PROXY UNKNOWN` (or v2 `LOCAL` command) is sent.
+
+Example: forward WebSocket connections to a backend which expects PROXY protocol
+
+    websocat -E -b ws-l:0.0.0.0:8080 proxy-protocol-send:tcp:127.0.0.1:1234
+"#
+);
+
+fn proxy_header_v1(addrs: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    match addrs {
+        Some((src, dst)) if src.is_ipv4() == dst.is_ipv4() => format!(
+            "PROXY {} {} {} {} {}\r\n",
+            if src.is_ipv4() { "TCP4" } else { "TCP6" },
+            src.ip(),
+            dst.ip(),
+            src.port(),
+            dst.port()
+        )
+        .into_bytes(),
+        _ => b"PROXY UNKNOWN\r\n".to_vec(),
+    }
+}
+
+fn proxy_header_v2(addrs: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    let mut h = V2_SIGNATURE.to_vec();
+    let mut body = vec![];
+    match addrs {
+        Some((src, dst)) if src.is_ipv4() == dst.is_ipv4() => {
+            match (src.ip(), dst.ip()) {
+                (IpAddr::V4(s), IpAddr::V4(d)) => {
+                    h.extend_from_slice(&[0x21, 0x11]);
+                    body.extend_from_slice(&s.octets());
+                    body.extend_from_slice(&d.octets());
+                }
+                (IpAddr::V6(s), IpAddr::V6(d)) => {
+                    h.extend_from_slice(&[0x21, 0x21]);
+                    body.extend_from_slice(&s.octets());
+                    body.extend_from_slice(&d.octets());
+                }
+                _ => unreachable!(),
+            }
+            body.extend_from_slice(&src.port().to_be_bytes());
+            body.extend_from_slice(&dst.port().to_be_bytes());
+        }
+        _ => h.extend_from_slice(&[0x20, 0x00]),
+    }
+    h.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    h.extend_from_slice(&body);
+    h
+}
+
+type ProxyAddrs = Option<(SocketAddr, SocketAddr)>;
+
+/// Try to parse PROXY protocol header at the beginning of `buf`.
+/// Returns `Ok(None)` if more data is needed, otherwise header length and addresses.
+fn parse_proxy_header(buf: &[u8]) -> Result<Option<(usize, ProxyAddrs)>, String> {
+    let l = buf.len();
+    if l < V2_SIGNATURE.len() && V2_SIGNATURE.starts_with(buf)
+        || l < V1_PREFIX.len() && V1_PREFIX.starts_with(buf)
+    {
+        return Ok(None);
+    }
+    if buf.starts_with(V1_PREFIX) {
+        return parse_proxy_header_v1(buf);
+    }
+    if buf.starts_with(V2_SIGNATURE) {
+        return parse_proxy_header_v2(buf);
+    }
+    Err("No PROXY protocol header in incoming connection".to_string())
+}
+
+fn parse_proxy_header_v1(buf: &[u8]) -> Result<Option<(usize, ProxyAddrs)>, String> {
+    let end = match buf.windows(2).position(|x| x == b"\r\n") {
+        Some(x) => x,
+        None if buf.len() >= V1_MAX_LEN => return Err("PROXY v1 header is too long".to_string()),
+        None => return Ok(None),
+    };
+    let line = String::from_utf8_lossy(&buf[..end]);
+    let bad = || format!("Invalid PROXY v1 header: {}", line);
+    let f: Vec<&str> = line.split(' ').collect();
+    let addrs = match f.get(1).cloned() {
+        Some("UNKNOWN") => None,
+        Some("TCP4") | Some("TCP6") if f.len() == 6 => {
+            let ip = |x: &str| x.parse::<IpAddr>().map_err(|_| bad());
+            let port = |x: &str| x.parse::<u16>().map_err(|_| bad());
+            Some((
+                SocketAddr::new(ip(f[2])?, port(f[4])?),
+                SocketAddr::new(ip(f[3])?, port(f[5])?),
+            ))
+        }
+        _ => return Err(bad()),
+    };
+    Ok(Some((end + 2, addrs)))
+}
+
+fn parse_proxy_header_v2(buf: &[u8]) -> Result<Option<(usize, ProxyAddrs)>, String> {
+    if buf.len() < 16 {
+        return Ok(None);
+    }
+    let len = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if buf.len() < len {
+        return Ok(None);
+    }
+    let (ver_cmd, fam) = (buf[12], buf[13]);
+    if ver_cmd >> 4 != 2 {
+        return Err(format!("Unsupported PROXY protocol version {}", ver_cmd >> 4));
+    }
+    if ver_cmd & 0x0F == 0 {
+        // LOCAL command: connection from the proxy itself, e.g. a health check
+        return Ok(Some((len, None)));
+    }
+    let a = &buf[16..len];
+    let port = |i: usize| u16::from_be_bytes([a[i], a[i + 1]]);
+    let addrs = match fam >> 4 {
+        1 if a.len() >= 12 => {
+            let ip = |i: usize| IpAddr::from([a[i], a[i + 1], a[i + 2], a[i + 3]]);
+            Some((
+                SocketAddr::new(ip(0), port(8)),
+                SocketAddr::new(ip(4), port(10)),
+            ))
+        }
+        2 if a.len() >= 36 => {
+            let ip = |i: usize| {
+                let mut o = [0u8; 16];
+                o.copy_from_slice(&a[i..i + 16]);
+                IpAddr::from(o)
+            };
+            Some((
+                SocketAddr::new(ip(0), port(32)),
+                SocketAddr::new(ip(16), port(34)),
+            ))
+        }
+        0 | 3 => None,
+        _ => return Err("Invalid PROXY v2 address block".to_string()),
+    };
+    Ok(Some((len, addrs)))
+}
+
+/// Reads and strips PROXY protocol header. Data after the header is replayed.
+struct ProxyHeaderRead(Option<Peer>, Vec<u8>);
+
+impl Future for ProxyHeaderRead {
+    type Item = (Peer, ProxyAddrs);
+    type Error = std::io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match parse_proxy_header(&self.1[..]) {
+                Ok(None) => (),
+                Ok(Some((len, addrs))) => {
+                    let p = self.0.take().expect("ProxyHeaderRead polled after completion");
+                    let rest = self.1.split_off(len);
+                    let r = crate::util::Replay::new(rest, p.0);
+                    return Ok(Async::Ready((Peer::new(r, p.1, p.2), addrs)));
+                }
+                Err(e) => return Err(crate::simple_err(e)),
+            }
+            let mut tmp = [0u8; 1024];
+            let n = try_nb!(self.0.as_mut().unwrap().0.read(&mut tmp));
+            if n == 0 {
+                return Err(crate::simple_err(
+                    "Connection closed before PROXY protocol header".to_string(),
+                ));
+            }
+            self.1.extend_from_slice(&tmp[..n]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sa(x: &str) -> SocketAddr {
+        x.parse().unwrap()
+    }
+
+    fn v2(ver_cmd: u8, fam: u8, body: &[u8]) -> Vec<u8> {
+        let mut h = V2_SIGNATURE.to_vec();
+        h.extend_from_slice(&[ver_cmd, fam]);
+        h.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        h.extend_from_slice(body);
+        h
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let h = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET /";
+        let addrs = Some((sa("192.0.2.1:56324"), sa("198.51.100.2:443")));
+        assert_eq!(parse_proxy_header(h), Ok(Some((h.len() - 5, addrs))));
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let h = b"PROXY TCP6 2001:db8::1 ::1 56324 443\r\n";
+        let addrs = Some((sa("[2001:db8::1]:56324"), sa("[::1]:443")));
+        assert_eq!(parse_proxy_header(h), Ok(Some((h.len(), addrs))));
+    }
+
+    #[test]
+    fn v1_unknown() {
+        assert_eq!(parse_proxy_header(b"PROXY UNKNOWN\r\n"), Ok(Some((15, None))));
+        let h = b"PROXY UNKNOWN ffff:f::1 ffff:f::2 1 2\r\n";
+        assert_eq!(parse_proxy_header(h), Ok(Some((h.len(), None))));
+    }
+
+    #[test]
+    fn v1_invalid() {
+        for h in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.2 56324\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 99999\r\n",
+            b"PROXY TCP4 192.0.2.x 198.51.100.2 1 2\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.2 1 2\r\n",
+            b"PROXY\r\n",
+        ] {
+            assert!(parse_proxy_header(h).is_err(), "{:?}", String::from_utf8_lossy(h));
+        }
+        let long = [&b"PROXY "[..], &[b'x'; 200]].concat();
+        assert!(parse_proxy_header(&long).is_err());
+    }
+
+    #[test]
+    fn v1_truncated() {
+        let h = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n";
+        for n in 0..h.len() {
+            assert_eq!(parse_proxy_header(&h[..n]), Ok(None), "{} bytes", n);
+        }
+    }
+
+    #[test]
+    fn v2_local() {
+        assert_eq!(parse_proxy_header(&v2(0x20, 0, &[])), Ok(Some((16, None))));
+        // Address block is skipped for LOCAL
+        let h = v2(0x20, 0x11, &[1; 12]);
+        assert_eq!(parse_proxy_header(&h), Ok(Some((28, None))));
+    }
+
+    #[test]
+    fn v2_ipv4() {
+        let body = [192, 0, 2, 1, 198, 51, 100, 2, 0xdc, 0x04, 0x01, 0xbb];
+        let mut h = v2(0x21, 0x11, &body);
+        let addrs = Some((sa("192.0.2.1:56324"), sa("198.51.100.2:443")));
+        assert_eq!(parse_proxy_header(&h), Ok(Some((28, addrs))));
+        // TLVs after addresses are skipped
+        h[15] += 3;
+        h.extend_from_slice(&[4, 0, 0, 0xff]);
+        assert_eq!(parse_proxy_header(&h), Ok(Some((31, addrs))));
+    }
+
+    #[test]
+    fn v2_ipv6() {
+        let src = sa("[2001:db8::1]:56324");
+        let dst = sa("[::1]:443");
+        let h = proxy_header_v2(Some((src, dst)));
+        assert_eq!(&h[12..16], &[0x21, 0x21, 0, 36]);
+        assert_eq!(parse_proxy_header(&h), Ok(Some((52, Some((src, dst))))));
+    }
+
+    #[test]
+    fn v2_unspec_and_unix() {
+        assert_eq!(parse_proxy_header(&v2(0x21, 0x00, &[])), Ok(Some((16, None))));
+        assert_eq!(parse_proxy_header(&v2(0x21, 0x31, &[0; 216])), Ok(Some((232, None))));
+    }
+
+    #[test]
+    fn v2_invalid() {
+        assert!(parse_proxy_header(&v2(0x11, 0x11, &[0; 12])).is_err());
+        assert!(parse_proxy_header(&v2(0x21, 0x11, &[0; 11])).is_err());
+        assert!(parse_proxy_header(&v2(0x21, 0x21, &[0; 12])).is_err());
+        assert!(parse_proxy_header(&v2(0x21, 0x41, &[])).is_err());
+    }
+
+    #[test]
+    fn v2_truncated() {
+        let h = proxy_header_v2(Some((sa("192.0.2.1:1"), sa("192.0.2.2:2"))));
+        for n in 0..h.len() {
+            assert_eq!(parse_proxy_header(&h[..n]), Ok(None), "{} bytes", n);
+        }
+    }
+
+    #[test]
+    fn bad_signature() {
+        assert!(parse_proxy_header(b"GET / HTTP/1.1\r\n").is_err());
+        assert!(parse_proxy_header(b"PROXz").is_err());
+        assert!(parse_proxy_header(b"\r\n\r\n\0\r\nQUIT!").is_err());
+        assert!(parse_proxy_header(b"\r\n\r\n\0\r\nQUIT\n\x21").is_ok());
+        assert!(parse_proxy_header(b"x").is_err());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let v4 = Some((sa("192.0.2.1:1"), sa("192.0.2.2:2")));
+        let v6 = Some((sa("[2001:db8::1]:1"), sa("[2001:db8::2]:2")));
+        let mixed = Some((sa("192.0.2.1:1"), sa("[2001:db8::2]:2")));
+        for (addrs, expected) in [(v4, v4), (v6, v6), (mixed, None), (None, None)] {
+            let h = proxy_header_v1(addrs);
+            assert_eq!(parse_proxy_header(&h), Ok(Some((h.len(), expected))));
+            let h = proxy_header_v2(addrs);
+            assert_eq!(parse_proxy_header(&h), Ok(Some((h.len(), expected))));
+        }
+    }
+}
@@ -9,7 +9,10 @@ pub enum DebtHandling {
 
 pub enum ZeroMessagesHandling {
     Drop,
+    /// Report as `EmptyMessage` error, distinct from end of stream
     Deliver,
+    /// Zero-length read, i.e. treat it as end of stream
+    Eof,
 }
 
 pub enum ProcessMessageResult {
@@ -17,20 +20,78 @@ pub enum ProcessMessageResult {
     Recurse,
 }
 
-/// A `Read` utility to deal with partial reads
-pub struct ReadDebt(pub Option<Vec<u8>>, pub DebtHandling, pub ZeroMessagesHandling);
+/// Error returned from `read` instead of splitting a message, asking the caller
+/// to retry with a buffer of at least this size
+#[derive(Debug)]
+pub struct MessageTooLong(pub usize);
+
+impl std::fmt::Display for MessageTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Message of {} bytes does not fit in the buffer", self.0)
+    }
+}
+impl std::error::Error for MessageTooLong {}
+
+/// Error returned from `read` of a message-oriented peer when a zero-length message
+/// is received. Plain zero-length read always means end of stream.
+/// Zero-length write is the way to send such message.
+///
+/// Peer halves stay plain `AsyncRead` and `AsyncWrite` instead of carrying a message type,
+/// so this is the only read outcome besides data and end of stream. Wrappers which pass
+/// read errors on need nothing special. Those acting on read errors themselves
+/// (reconnecting, parsing messages) must check `is_empty_message` first.
+/// Events which are not messages go to `Peer`'s `EventSink` instead.
+#[derive(Debug)]
+pub struct EmptyMessage;
+
+impl std::fmt::Display for EmptyMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Zero-length message")
+    }
+}
+impl std::error::Error for EmptyMessage {}
+
+pub fn empty_message() -> std::io::Error {
+    crate::util::io_other_error(EmptyMessage)
+}
+
+pub fn is_empty_message(e: &std::io::Error) -> bool {
+    e.get_ref().is_some_and(|x| x.is::<EmptyMessage>())
+}
+
+/// Buffer size requested by a reader which does not want to split a message
+pub fn requested_buffer_size(e: &std::io::Error) -> Option<usize> {
+    e.get_ref()
+        .and_then(|x| x.downcast_ref::<MessageTooLong>())
+        .map(|x| x.0)
+}
+
+/// A `Read` utility to deal with partial reads.
+/// Messages up to the size in the last field are not split,
+/// but reported as `MessageTooLong` to get a bigger buffer.
+pub struct ReadDebt(
+    pub Option<Vec<u8>>,
+    pub DebtHandling,
+    pub ZeroMessagesHandling,
+    pub Option<usize>,
+);
 impl ReadDebt {
     pub fn process_message(&mut self, buf: &mut [u8], buf_in: &[u8]) -> ProcessMessageResult {
         assert_eq!(self.0, None);
         let mut l = buf_in.len();
+        if l > buf.len() && self.3.is_some_and(|max| l <= max) {
+            debug!("Asking for a bigger buffer for message of {} bytes", l);
+            self.0 = Some(buf_in.to_vec());
+            return ProcessMessageResult::Return(Err(crate::util::io_other_error(MessageTooLong(l))));
+        }
         if l > buf.len() {
             match self.1 {
                 DebtHandling::Silent => (),
                 DebtHandling::Warn => {
-                    warn!("Incoming message too long ({} > {}): splitting it to parts.\nUse -B option to increase buffer size or -S option to drop messages instead of splitting.", l, buf.len());
+                    warn!("Incoming message too long ({} > {}): splitting it to parts.\nUse -B or --max-message-size option to increase buffer size or -S option to drop messages instead of splitting.", l, buf.len());
                 }
                 DebtHandling::DropMessage => {
-                    error!("Dropping too large message ({} > {}). Use -B option to increase buffer size.", l, buf.len());
+                    error!("Dropping too large message ({} > {}). Use -B or --max-message-size option to increase buffer size.", l, buf.len());
                     return ProcessMessageResult::Recurse;
                 }
             }
@@ -45,7 +106,10 @@ impl ReadDebt {
         debug!("Fullfulling the debt of {} bytes", l);
         if l == 0 {
             match self.2 {
-                ZeroMessagesHandling::Deliver => (),
+                ZeroMessagesHandling::Eof => (),
+                ZeroMessagesHandling::Deliver => {
+                    return ProcessMessageResult::Return(Err(empty_message()));
+                }
                 ZeroMessagesHandling::Drop => {
                     info!("Dropping incoming zero-length message");
                     return ProcessMessageResult::Recurse;
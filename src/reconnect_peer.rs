@@ -155,7 +155,10 @@ macro_rules! main_loop {
                                     match $e {
                                         Ok(0) => { do_reconnect = true; }
                                         Err(e) => {
-                                            if e.kind() == ::std::io::ErrorKind::WouldBlock {
+                                            if e.kind() == ::std::io::ErrorKind::WouldBlock
+                                                || $crate::readdebt::requested_buffer_size(&e).is_some()
+                                                || $crate::readdebt::is_empty_message(&e)
+                                            {
                                                 return Err(e);
                                             }
                                             warn!("{}", e);
@@ -0,0 +1,132 @@
+//! Lazy asynchronous hostname resolution for `tcp:`, `udp:` and `wss://`.
+//! Hostnames are resolved anew on each connection attempt,
+//! so e.g. `autoreconnect:tcp:host:port` follows DNS changes.
+
+use futures::future::Future;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use super::Options;
+
+/// Hostname (or IP address) and port, not yet resolved
+#[derive(Debug, Clone)]
+pub struct HostPort {
+    pub host: String,
+    pub port: u16,
+}
+
+impl std::str::FromStr for HostPort {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> crate::Result<HostPort> {
+        let i = s.rfind(':').ok_or("Expected host:port")?;
+        let (host, port) = (&s[..i], &s[i + 1..]);
+        let host = if host.starts_with('[') && host.ends_with(']') {
+            &host[1..host.len() - 1]
+        } else {
+            host
+        };
+        if host.is_empty() {
+            Err("Expected host:port")?;
+        }
+        let port = port
+            .parse()
+            .map_err(|_| format!("Invalid port number `{}`", port))?;
+        Ok(HostPort {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for HostPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Entry of `--resolve` option
+#[derive(Debug, Clone)]
+pub struct ResolveOverride {
+    pub host: String,
+    /// `None` means any port
+    pub port: Option<u16>,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// Parse `<host>:<port>:<addr>[,<addr>...]` argument of --resolve.
+/// Port may be `*`, IPv6 addresses may be enclosed in brackets.
+pub fn interpret_resolve_override(x: &str) -> ::std::result::Result<ResolveOverride, String> {
+    let usage = || "Expected <host>:<port>:<address>[,<address>...]".to_string();
+    let mut parts = x.splitn(3, ':');
+    let host = parts.next().filter(|h| !h.is_empty()).ok_or_else(usage)?;
+    let port = match parts.next().ok_or_else(usage)? {
+        "*" => None,
+        p => Some(p.parse().map_err(|_| format!("Invalid port number `{}`", p))?),
+    };
+    let mut addrs = vec![];
+    for a in parts.next().ok_or_else(usage)?.split(',') {
+        let a = a.trim_start_matches('[').trim_end_matches(']');
+        addrs.push(a.parse().map_err(|_| format!("Invalid IP address `{}`", a))?);
+    }
+    Ok(ResolveOverride {
+        host: host.to_string(),
+        port,
+        addrs,
+    })
+}
+
+pub type ResolveFuture = Box<dyn Future<Item = Vec<SocketAddr>, Error = Box<dyn std::error::Error>>>;
+
+/// Put addresses of preferred family first, keeping order otherwise
+fn apply_preference(mut addrs: Vec<SocketAddr>, prefer_ipv4: bool, prefer_ipv6: bool) -> Vec<SocketAddr> {
+    if prefer_ipv4 {
+        addrs.sort_by_key(|a| a.is_ipv6());
+    } else if prefer_ipv6 {
+        addrs.sort_by_key(|a| a.is_ipv4());
+    }
+    addrs
+}
+
+/// Resolve hostname using `--resolve` overrides or system resolver.
+/// System resolver is blocking, so it is called in a separate thread.
+pub fn resolve(hp: &HostPort, opts: &Options) -> ResolveFuture {
+    if let Ok(ip) = hp.host.parse::<IpAddr>() {
+        return Box::new(futures::future::ok(vec![SocketAddr::new(ip, hp.port)]));
+    }
+
+    for o in &opts.resolve_overrides {
+        if o.host.eq_ignore_ascii_case(&hp.host) && o.port.is_none_or(|p| p == hp.port) {
+            info!("Using --resolve override for {}", hp);
+            let addrs = o.addrs.iter().map(|ip| SocketAddr::new(*ip, hp.port)).collect();
+            return Box::new(futures::future::ok(apply_preference(addrs, opts.prefer_ipv4, opts.prefer_ipv6)));
+        }
+    }
+
+    info!("Resolving hostname {} to IP addresses", hp.host);
+    let (tx, rx) = futures::sync::oneshot::channel();
+    let query = (hp.host.clone(), hp.port);
+    std::thread::spawn(move || {
+        let _ = tx.send(query.to_socket_addrs().map(|x| x.collect::<Vec<_>>()));
+    });
+
+    let hp = hp.clone();
+    let prefer_ipv4 = opts.prefer_ipv4;
+    let prefer_ipv6 = opts.prefer_ipv6;
+    Box::new(
+        rx.map_err(|_| "Hostname resolver thread failed".into())
+            .and_then(move |r| {
+                let addrs = r.map_err(|e| format!("Failed to resolve {}: {}", hp.host, e))?;
+                if addrs.is_empty() {
+                    Err(format!("Failed to resolve {} to IP", hp.host))?;
+                }
+                for addr in &addrs {
+                    info!("Got IP: {}", addr);
+                }
+                Ok(apply_preference(addrs, prefer_ipv4, prefer_ipv6))
+            }),
+    ) as ResolveFuture
+}
@@ -0,0 +1,373 @@
+//! TLS implementation based on rustls, used when Websocat is built with `ssl_rustls` feature instead of `ssl`.
+
+use futures::future::Future;
+use futures::{Async, Poll};
+
+use std::convert::TryFrom;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use super::ssl_peer::{check_pins, log_handshake};
+use super::{box_up_err, peer_err, BoxedNewPeerFuture, Peer};
+use super::{L2rUser, Options};
+use crate::options::TlsVersion;
+use crate::util::peer_err2;
+
+extern crate p12_keystore;
+extern crate readwrite;
+extern crate rustls;
+extern crate rustls_native_certs;
+extern crate rustls_pemfile;
+
+use self::rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use self::rustls::{
+    Certificate, CertificateError, ClientConfig, ClientConnection, Connection, PrivateKey,
+    RootCertStore, ServerConfig, ServerConnection, ServerName, SupportedProtocolVersion,
+};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+pub type TlsAcceptorExt = Arc<ServerConfig>;
+
+type Squashed = readwrite::ReadWriteAsync<Box<dyn AsyncRead>, Box<dyn AsyncWrite>>;
+
+fn protocol_versions(opts: &Options) -> crate::Result<Vec<&'static SupportedProtocolVersion>> {
+    let min = opts.tls_min_version.unwrap_or(TlsVersion::Tls12);
+    let max = opts.tls_max_version.unwrap_or(TlsVersion::Tls13);
+    let mut v = vec![];
+    if min <= TlsVersion::Tls12 && max >= TlsVersion::Tls12 {
+        v.push(&rustls::version::TLS12);
+    }
+    if max >= TlsVersion::Tls13 {
+        v.push(&rustls::version::TLS13);
+    }
+    if v.is_empty() {
+        Err("This Websocat build uses rustls, which supports only TLS 1.2 and 1.3")?;
+    }
+    Ok(v)
+}
+
+fn alpn_protocols(opts: &Options) -> Vec<Vec<u8>> {
+    opts.tls_alpn.iter().map(|x| x.as_bytes().to_vec()).collect()
+}
+
+/// Certificate verifier for `--insecure` and for connections without known domain name
+struct RelaxedVerifier {
+    inner: WebPkiVerifier,
+    accept_invalid_certs: bool,
+}
+
+impl ServerCertVerifier for RelaxedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            Ok(x) => Ok(x),
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName)) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            Err(_) if self.accept_invalid_certs => Ok(ServerCertVerified::assertion()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn client_config(nohost: bool, opts: &Options) -> crate::Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    if !opts.tls_no_system_roots {
+        match rustls_native_certs::load_native_certs() {
+            Ok(certs) => {
+                let certs: Vec<Vec<u8>> = certs.into_iter().map(|x| x.0).collect();
+                let (good, bad) = roots.add_parsable_certificates(&certs[..]);
+                debug!("Loaded {} system root certificates, skipped {}", good, bad);
+            }
+            Err(e) => warn!("Failed to load system root certificates: {}", e),
+        }
+    }
+    for ca in &opts.tls_ca_files {
+        let pem = ::std::fs::read(ca).map_err(|e| format!("Failed to read {:?}: {}", ca, e))?;
+        let certs = rustls_pemfile::certs(&mut &pem[..])?;
+        if certs.is_empty() {
+            Err(format!("No certificates found in {:?}", ca))?;
+        }
+        debug!("Loaded {} CA certificates from {:?}", certs.len(), ca);
+        let (_, bad) = roots.add_parsable_certificates(&certs[..]);
+        if bad > 0 {
+            Err(format!("Failed to use {} of CA certificates from {:?}", bad, ca))?;
+        }
+    }
+    let roots = Arc::new(roots);
+    let mut c = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&protocol_versions(opts)?[..])?
+        .with_root_certificates(roots.clone())
+        .with_no_client_auth();
+    if nohost || opts.tls_insecure {
+        c.dangerous().set_certificate_verifier(Arc::new(RelaxedVerifier {
+            inner: WebPkiVerifier::new(roots, None),
+            accept_invalid_certs: opts.tls_insecure,
+        }));
+    }
+    if nohost {
+        c.enable_sni = false;
+    }
+    c.alpn_protocols = alpn_protocols(opts);
+    Ok(Arc::new(c))
+}
+
+fn build_acceptor(
+    certs: Vec<Certificate>,
+    key: PrivateKey,
+    opts: &Options,
+) -> crate::Result<TlsAcceptorExt> {
+    let mut c = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&protocol_versions(opts)?[..])?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    c.alpn_protocols = alpn_protocols(opts);
+    Ok(Arc::new(c))
+}
+
+pub fn acceptor_from_pem(cert: &[u8], key: &[u8], opts: &Options) -> crate::Result<TlsAcceptorExt> {
+    use self::rustls_pemfile::Item;
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut &cert[..])?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        Err("No certificates found in --pem-cert file")?;
+    }
+    let key = rustls_pemfile::read_all(&mut &key[..])?
+        .into_iter()
+        .filter_map(|x| match x {
+            Item::PKCS8Key(k) | Item::RSAKey(k) | Item::ECKey(k) => Some(PrivateKey(k)),
+            _ => None,
+        })
+        .next()
+        .ok_or("No private key found in --pem-key file")?;
+    build_acceptor(certs, key, opts)
+}
+
+pub fn acceptor_from_pkcs12(der: &[u8], passwd: &str, opts: &Options) -> crate::Result<TlsAcceptorExt> {
+    let ks = p12_keystore::KeyStore::from_pkcs12(der, passwd)?;
+    let (_, chain) = ks
+        .private_key_chain()
+        .ok_or("No private key found in --pkcs12-der archive")?;
+    let certs = chain
+        .chain()
+        .iter()
+        .map(|x| Certificate(x.as_der().to_vec()))
+        .collect();
+    build_acceptor(certs, PrivateKey(chain.key().to_vec()), opts)
+}
+
+/// Adapts rustls connection state machine to tokio-io style non-blocking stream
+struct TlsStream {
+    io: Squashed,
+    conn: Connection,
+    eof: bool,
+    shutdown_sent: bool,
+}
+
+impl TlsStream {
+    fn write_tls(&mut self) -> ::std::io::Result<()> {
+        while self.conn.wants_write() {
+            if self.conn.write_tls(&mut self.io)? == 0 {
+                return Err(IoError::new(ErrorKind::WriteZero, "TLS transport closed"));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_tls(&mut self) -> ::std::io::Result<()> {
+        if self.conn.read_tls(&mut self.io)? == 0 {
+            self.eof = true;
+        }
+        if let Err(e) = self.conn.process_new_packets() {
+            // Try to deliver the alert to the peer
+            let _ = self.write_tls();
+            return Err(IoError::new(ErrorKind::InvalidData, e));
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        loop {
+            match self.conn.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock && !self.eof => (),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(0),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    debug!("TLS connection closed without close_notify");
+                    return Ok(0);
+                }
+                Err(e) => return Err(e),
+            }
+            self.read_tls()?;
+            // Handshake messages like key updates may require a reply
+            match self.write_tls() {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => (),
+                x => x?,
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.write_tls()?;
+        let n = self.conn.writer().write(buf)?;
+        match self.write_tls() {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => (),
+            x => x?,
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.write_tls()?;
+        self.io.flush()
+    }
+}
+
+impl AsyncRead for TlsStream {}
+impl AsyncWrite for TlsStream {
+    fn shutdown(&mut self) -> Poll<(), IoError> {
+        if !self.shutdown_sent {
+            self.conn.send_close_notify();
+            self.shutdown_sent = true;
+        }
+        try_nb!(self.write_tls());
+        self.io.shutdown()
+    }
+}
+
+struct Handshake(Option<TlsStream>);
+
+impl Future for Handshake {
+    type Item = TlsStream;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<TlsStream, IoError> {
+        {
+            let s = self.0.as_mut().expect("Handshake polled after completion");
+            while s.conn.is_handshaking() {
+                if s.conn.wants_write() {
+                    try_nb!(s.write_tls());
+                } else {
+                    try_nb!(s.read_tls());
+                    if s.eof && s.conn.is_handshaking() {
+                        return Err(IoError::new(
+                            ErrorKind::UnexpectedEof,
+                            "Connection closed during TLS handshake",
+                        ));
+                    }
+                }
+            }
+            try_nb!(s.write_tls());
+        }
+        Ok(Async::Ready(self.0.take().unwrap()))
+    }
+}
+
+fn log_rustls_handshake(conn: &Connection, log_certs: bool) {
+    if !log_enabled!(log::Level::Info) {
+        return;
+    }
+    let chain: Vec<Vec<u8>> = match conn.peer_certificates() {
+        Some(x) if log_certs => x.iter().map(|c| c.0.clone()).collect(),
+        _ => vec![],
+    };
+    log_handshake(
+        conn.protocol_version().map(|x| x.get_u16()),
+        conn.negotiated_cipher_suite().map(|x| x.suite().get_u16()),
+        conn.alpn_protocol(),
+        &chain[..],
+    );
+}
+
+fn handshake(r: Box<dyn AsyncRead>, w: Box<dyn AsyncWrite>, conn: Connection) -> Handshake {
+    let io = readwrite::ReadWriteAsync::new(r, w);
+    Handshake(Some(TlsStream {
+        io,
+        conn,
+        eof: false,
+        shutdown_sent: false,
+    }))
+}
+
+pub fn ssl_connect(
+    inner_peer: Peer,
+    _l2r: L2rUser,
+    dom: Option<String>,
+    opts: Rc<Options>,
+) -> BoxedNewPeerFuture {
+    let hup = inner_peer.2;
+    let verified = dom.is_some();
+    let conn = (|| -> crate::Result<Connection> {
+        let config = client_config(dom.is_none(), &opts)?;
+        let dom = dom.as_deref().unwrap_or("domainverificationdisabled");
+        let name = ServerName::try_from(dom).map_err(|e| format!("Invalid TLS domain {:?}: {}", dom, e))?;
+        Ok(ClientConnection::new(config, name)?.into())
+    })();
+    let conn = match conn {
+        Ok(x) => x,
+        Err(e) => return peer_err2(e),
+    };
+
+    info!("Connecting to TLS");
+    Box::new(
+        handshake(inner_peer.0, inner_peer.1, conn)
+            .map_err(box_up_err)
+            .and_then(move |tls_stream| {
+                log_rustls_handshake(&tls_stream.conn, true);
+                let cert = tls_stream.conn.peer_certificates().and_then(|x| x.first());
+                check_pins(cert.map(|x| &x.0[..]), &opts)?;
+                if verified {
+                    info!("Connected to TLS");
+                } else {
+                    warn!("Connected to TLS without proper verification of certificate. Use --tls-domain option.");
+                }
+                let (r, w) = tls_stream.split();
+                Ok(Peer::new(r, w, hup))
+            }),
+    )
+}
+
+pub fn ssl_accept(inner_peer: Peer, _l2r: L2rUser, tls: TlsAcceptorExt) -> BoxedNewPeerFuture {
+    let hup = inner_peer.2;
+    let conn: Connection = match ServerConnection::new(tls) {
+        Ok(x) => x.into(),
+        Err(e) => return peer_err(e),
+    };
+
+    info!("Accepting a TLS connection");
+    Box::new(
+        handshake(inner_peer.0, inner_peer.1, conn)
+            .map_err(box_up_err)
+            .and_then(move |tls_stream| {
+                info!("Connected to TLS");
+                log_rustls_handshake(&tls_stream.conn, false);
+                let (r, w) = tls_stream.split();
+                Ok(Peer::new(r, w, hup))
+            }),
+    )
+}
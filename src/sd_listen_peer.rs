@@ -0,0 +1,159 @@
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::rc::Rc;
+
+use super::{multi, peer_err_s, BoxedNewPeerStream, ConstructParams, L2rUser, Options};
+use super::{PeerConstructor, Specifier};
+
+/// First file descriptor passed by systemd
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+#[derive(Debug, Clone)]
+pub struct SdListen(pub String);
+impl Specifier for SdListen {
+    fn construct(&self, p: ConstructParams) -> PeerConstructor {
+        let fd = {
+            let mut fds = p.global(SdListenFds::from_env);
+            fds.take(&self.0)
+        };
+        multi(match fd {
+            Ok(fd) => sd_listen_peer(fd, p.left_to_right, &p.program_options),
+            Err(e) => peer_err_s(crate::simple_err(e)),
+        })
+    }
+    specifier_boilerplate!(globalstate multiconnect no_subspec);
+}
+specifier_class!(
+    name = SdListenClass,
+    target = SdListen,
+    prefixes = ["sd-listen:", "sd-l:", "listen-sd:", "l-sd:"],
+    arg_handling = into,
+    overlay = false,
+    StreamOriented,
+    MultiConnect,
+    help = r#"
+Accept connections on a listening TCP or UNIX socket passed by systemd
+socket activation (`LISTEN_FDS`), instead of binding it ourselves. [A]
+
+Argument is the socket name from `FileDescriptorName=` (`LISTEN_FDNAMES`)
+or 0-based index among passed sockets. Empty argument means the first socket.
+Each socket can be used only once.
+
+Example: WebSocket server on a socket from `websocat.socket` unit with
+`ListenStream=8080` and `FileDescriptorName=web`
+
+    websocat -E ws-u:sd-listen:web tcp:127.0.0.1:22
+
+Example: the same for secure WebSocket and for plain TCP
+
+    websocat -E --pem-cert=cert.pem --pem-key=key.pem ws-u:tls-accept:sd-listen:web tcp:127.0.0.1:22
+    websocat -E sd-listen:0 ws://127.0.0.1:8080/
+
+Use `--sd-notify` with `Type=notify` services.
+"#
+);
+
+/// Sockets passed by systemd, with "already used" flags
+pub struct SdListenFds(Vec<(String, RawFd, bool)>);
+
+impl SdListenFds {
+    /// Read and unset `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`,
+    /// so that they are not inherited by `exec:` child processes.
+    pub fn from_env() -> SdListenFds {
+        let pid = std::env::var("LISTEN_PID").ok();
+        let n = std::env::var("LISTEN_FDS").ok();
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        for v in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(v);
+        }
+
+        if pid.and_then(|x| x.parse().ok()) != Some(std::process::id()) {
+            return SdListenFds(vec![]);
+        }
+        let n: RawFd = n.and_then(|x| x.parse().ok()).unwrap_or(0);
+        let mut names = names.split(':');
+        SdListenFds(
+            (0..n)
+                .map(|i| {
+                    let name = names.next().unwrap_or("unknown").to_string();
+                    (name, SD_LISTEN_FDS_START + i, false)
+                })
+                .collect(),
+        )
+    }
+
+    fn take(&mut self, id: &str) -> Result<RawFd, String> {
+        if self.0.is_empty() {
+            return Err("No sockets passed by systemd (LISTEN_FDS)".to_string());
+        }
+        let entry = if id.is_empty() {
+            self.0.first_mut()
+        } else if let Ok(i) = id.parse::<usize>() {
+            self.0.get_mut(i)
+        } else {
+            self.0.iter_mut().find(|x| x.0 == id)
+        };
+        match entry {
+            None => {
+                let available: Vec<&str> = self.0.iter().map(|x| &x.0[..]).collect();
+                Err(format!(
+                    "No socket `{}` passed by systemd. Available: {}",
+                    id,
+                    available.join(", ")
+                ))
+            }
+            Some(&mut (ref name, _, true)) => {
+                Err(format!("Socket `{}` from systemd is already used", name))
+            }
+            Some(x) => {
+                x.2 = true;
+                info!("Using socket `{}` (fd {}) from systemd", x.0, x.1);
+                Ok(x.1)
+            }
+        }
+    }
+}
+
+pub fn sd_listen_peer(fd: RawFd, l2r: L2rUser, opts: &Rc<Options>) -> BoxedNewPeerStream {
+    let s = unsafe { socket2::Socket::from_raw_fd(fd) };
+    let r = s.set_cloexec(true).and_then(|()| s.local_addr());
+    let handle = tokio_reactor::Handle::default();
+    match r {
+        Err(e) => peer_err_s(e),
+        Ok(ref a) if a.as_socket().is_some() => {
+            match tokio_tcp::TcpListener::from_std(s.into(), &handle) {
+                Ok(x) => super::net_peer::tcp_serve_listener(x, l2r, opts),
+                Err(e) => peer_err_s(e),
+            }
+        }
+        Ok(_) => {
+            let s: std::os::unix::net::UnixListener = s.into();
+            match tokio_uds::UnixListener::from_std(s, &handle) {
+                Ok(x) => super::unix_peer::unix_serve_listener(x),
+                Err(e) => peer_err_s(e),
+            }
+        }
+    }
+}
+
+/// Send a message like `READY=1` to systemd if `NOTIFY_SOCKET` is set.
+pub fn sd_notify(msg: &str) {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(x) => x,
+        None => return,
+    };
+    let mut path = path.into_vec();
+    if path.first() == Some(&b'@') {
+        // abstract socket
+        path[0] = 0;
+    }
+    let path = std::ffi::OsString::from_vec(path);
+    let r = socket2::SockAddr::unix(path).and_then(|addr| {
+        let s = socket2::Socket::new(socket2::Domain::UNIX, socket2::Type::DGRAM, None)?;
+        s.send_to(msg.as_bytes(), &addr)
+    });
+    match r {
+        Ok(_) => debug!("Sent {} to systemd", msg),
+        Err(e) => warn!("Failed to notify systemd: {}", e),
+    }
+}
@@ -16,6 +16,7 @@ impl Session {
             stop_on_reader_zero_read: !self.opts.no_exit_on_zeromsg,
             once,
             buffer_size: self.opts.buffer_size,
+            max_buffer_size: self.opts.max_message_size,
             skip: false,
             max_ops: self.opts.max_messages,
         };
@@ -113,17 +114,46 @@ pub fn serve<OE>(
 ) -> impl Future<Item = (), Error = ()>
 where
     OE: Fn(Box<dyn std::error::Error>) -> () + 'static,
+{
+    serve_with_exit_code(s1, s2, opts, onerror).map(|_| ())
+}
+
+/// Like `serve`, but resolves to exit code of `exec:` process
+/// when serving only one connection
+pub fn serve_with_exit_code<OE>(
+    s1: Rc<dyn Specifier>,
+    s2: Rc<dyn Specifier>,
+    opts: Options,
+    onerror: std::rc::Rc<OE>,
+) -> impl Future<Item = Option<i32>, Error = ()>
+where
+    OE: Fn(Box<dyn std::error::Error>) + 'static,
 {
     futures::future::ok(()).and_then(|()| serve_impl(s1, s2, opts, onerror))
 }
 
+type ServeFuture = Box<dyn Future<Item = Option<i32>, Error = ()>>;
+
+/// Exit code of `exec:` process to become websocat's exit code
+#[cfg(feature = "tokio-process")]
+fn exit_code(ps: &Rc<RefCell<ProgramState>>) -> ServeFuture {
+    use crate::process_peer::{wait_exit_code, SharedExitCodes};
+    let codes = ps.borrow_mut().0.entry::<SharedExitCodes>().or_insert_with(Default::default).clone();
+    Box::new(wait_exit_code(codes))
+}
+
+#[cfg(not(feature = "tokio-process"))]
+fn exit_code(_ps: &Rc<RefCell<ProgramState>>) -> ServeFuture {
+    Box::new(futures::future::ok(None))
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
 fn serve_impl<OE>(
     s1: Rc<dyn Specifier>,
     s2: Rc<dyn Specifier>,
     opts: Options,
     onerror: std::rc::Rc<OE>,
-) -> Box<dyn Future<Item = (), Error = ()>>
+) -> ServeFuture
 where
     OE: Fn(Box<dyn std::error::Error>) -> () + 'static,
 {
@@ -154,13 +184,20 @@ where
             PeerConstructor::ServeOnce(left.get_only_first_conn(cp.borrow().left_to_right.clone()));
     }
 
+    #[cfg(unix)]
+    {
+        if opts1.sd_notify {
+            crate::sd_listen_peer::sd_notify("READY=1");
+        }
+    }
+
     let max_parallel_conns = opts1.max_parallel_conns;
     let current_parallel_conns = Rc::new(::std::cell::Cell::new(0usize));
 
     match left {
         PeerConstructor::Error(e) => {
             e1(e);
-            Box::new(futures::future::ok(())) as Box<dyn Future<Item = (), Error = ()>>
+            Box::new(futures::future::ok(None)) as ServeFuture
         },
         ServeMultipleTimes(stream) => {
             let runner = stream
@@ -197,7 +234,7 @@ where
                     )
                 })
                 .for_each(|()| futures::future::ok(()));
-            Box::new(runner.map_err(move |e| e2(e))) as Box<dyn Future<Item = (), Error = ()>>
+            Box::new(runner.map(|()| None).map_err(move |e| e2(e))) as ServeFuture
         }
         OverlayM(stream, mapper) => {
             let runner = stream
@@ -242,7 +279,7 @@ where
                     )
                 })
                 .for_each(|()| futures::future::ok(()));
-            Box::new(runner.map_err(move |e| e2(e))) as Box<dyn Future<Item = (), Error = ()>>
+            Box::new(runner.map(|()| None).map_err(move |e| e2(e))) as ServeFuture
         }
         ServeOnce(peer1c) => {
             let runner = peer1c.and_then(move |peer1| {
@@ -253,13 +290,15 @@ where
                 fut.and_then(move |peer2| {
                     let s = Session::new(peer1, peer2, opts2);
                     s.run().map(|()| {
-                        ::std::mem::drop(ps)
+                        let code = exit_code(&ps);
+                        ::std::mem::drop(ps);
                         // otherwise ps will be dropped sooner
                         // and stdin/stdout may become blocking sooner
+                        code
                     })
                 })
             });
-            Box::new(runner.map_err(move |e| e3(e))) as Box<dyn Future<Item = (), Error = ()>>
+            Box::new(runner.map_err(move |e| e3(e)).and_then(|code| code)) as ServeFuture
         }
         Overlay1(peer1c, mapper) => {
             let runner = peer1c.and_then(move |peer1_| {
@@ -273,14 +312,16 @@ where
                     fut.and_then(move |peer2| {
                         let s = Session::new(peer1, peer2, opts2);
                         s.run().map(|()| {
-                            ::std::mem::drop(ps)
+                            let code = exit_code(&ps);
+                            ::std::mem::drop(ps);
                             // otherwise ps will be dropped sooner
                             // and stdin/stdout may become blocking sooner
+                            code
                         })
                     })
                 })
             });
-            Box::new(runner.map_err(move |e| e3(e))) as Box<dyn Future<Item = (), Error = ()>>
+            Box::new(runner.map_err(move |e| e3(e)).and_then(|code| code)) as ServeFuture
         }
     }
 }
@@ -139,7 +139,7 @@ fn read_socks_reply(p: Peer) -> RSRRet {
                                     addrport[3],
                                 );
                                 let host = SocksHostAddr::Ip(IpAddr::V4(ip));
-                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup)))
+                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup, None)))
                             },
                         ))
                     }
@@ -155,7 +155,7 @@ fn read_socks_reply(p: Peer) -> RSRRet {
                                 let mut ip = [0u8; 16];
                                 ip.copy_from_slice(&addrport[0..16]);
                                 let host = SocksHostAddr::Ip(IpAddr::V6(ip.into()));
-                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup)))
+                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup, None)))
                             },
                         ))
                     }
@@ -175,7 +175,7 @@ fn read_socks_reply(p: Peer) -> RSRRet {
                                                 .unwrap_or("(invalid hostname)")
                                                 .to_string(),
                                         );
-                                        ok((SocksSocketAddr { host, port }, Peer(r, w, hup)))
+                                        ok((SocksSocketAddr { host, port }, Peer(r, w, hup, None)))
                                     },
                                 )
                             },
@@ -260,7 +260,7 @@ pub fn socks5_peer(
                             .and_then(move |(w, _)| {
                                 let _reply = [0; 4];
 
-                                read_socks_reply(Peer(r, w, hup)).and_then(move |(addr, p)| {
+                                read_socks_reply(Peer(r, w, hup, None)).and_then(move |(addr, p)| {
                                     info!("SOCKS5 connect/bind: {:?}", addr);
 
                                     if do_bind {
@@ -1,18 +1,29 @@
-use futures::future::{ok, Future};
+use futures::future::Future;
+use futures::{Async, Poll};
 
+use std::collections::HashMap;
+use std::io::Read;
 use std::rc::Rc;
 
-use super::{box_up_err, peer_err, BoxedNewPeerFuture, Peer};
-use super::{ConstructParams, L2rUser, Options, PeerConstructor, Specifier};
+use crate::util::peer_err2;
+use super::{box_up_err, BoxedNewPeerFuture, L2rUser, Peer};
+use super::{ConstructParams, Options, PeerConstructor, Specifier};
 
-pub extern crate native_tls;
-extern crate readwrite;
-extern crate tokio_tls;
-
-use self::native_tls::{Identity as Pkcs12, TlsAcceptor, TlsConnector};
-use self::tokio_tls::{TlsAcceptor as TlsAcceptorExt, TlsConnector as TlsConnectorExt};
+extern crate hex;
+extern crate sha2;
 
 use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tokio_io::AsyncRead;
+
+#[cfg(feature = "ssl")]
+use super::native_tls_peer as backend;
+#[cfg(not(feature = "ssl"))]
+use super::rustls_peer as backend;
+
+pub use self::backend::{ssl_accept, ssl_connect, TlsAcceptorExt};
 
 pub fn interpret_pkcs12(x: &OsStr) -> ::std::result::Result<Vec<u8>, OsString> {
     match (|| {
@@ -41,7 +52,7 @@ impl<T: Specifier> Specifier for TlsConnect<T> {
                 p,
                 l2r,
                 cp.program_options.tls_domain.clone(),
-                cp.program_options.tls_insecure,
+                cp.program_options.clone(),
             )
         })
     }
@@ -51,7 +62,7 @@ impl<T: Specifier> Specifier for TlsConnect<T> {
 specifier_class!(
     name = TlsConnectClass,
     target = TlsConnect,
-    prefixes = ["ssl-connect:","ssl-c","ssl:","tls:","tls-connect:","c-ssl:","connect-ssl:","c-tls:","connect-tls:"],
+    prefixes = ["ssl-connect:","ssl-c","ssl:","tls:","tls-connect:","tls-c:","c-ssl:","connect-ssl:","c-tls:","connect-tls:"],
     arg_handling = subspec,
     overlay = true,
     StreamOriented,
@@ -64,6 +75,13 @@ Example: manually connect to a secure websocket
     websocat -t - ws-c:tls-c:tcp:174.129.224.73:1080 --ws-c-uri ws://echo.websocket.org --tls-domain echo.websocket.org
 
 For a user-friendly solution, see --socks5 command-line option
+
+Server certificate is checked against system roots and certificates from --tls-ca.
+Use --tls-pin-cert-sha256 or --tls-pin-pubkey-sha256 to additionally require specific
+certificate or public key. Those options also apply to `wss://`.
+
+Use --tls-alpn, --tls-min-version and --tls-max-version to adjust the handshake.
+With -v, negotiated version, cipher suite, ALPN protocol and server certificates are logged.
 "#
 );
 
@@ -72,9 +90,26 @@ pub struct TlsAccept<T: Specifier>(pub T);
 impl<T: Specifier> Specifier for TlsAccept<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let inner = self.0.construct(cp.clone());
-        inner.map(move |p, l2r| ssl_accept(p, l2r, cp.program_options.clone()))
+        inner.map(move |p, l2r| {
+            let cp = cp.clone();
+            Box::new(ServerNamePeek(Some(p), vec![]).map_err(box_up_err).and_then(
+                move |(p, server_name)| {
+                    if let Some(ref x) = server_name {
+                        debug!("TLS client requested server name {}", x);
+                    }
+                    if let L2rUser::FillIn(ref y) = l2r {
+                        y.borrow_mut().tls_server_name = server_name.clone();
+                    }
+                    let tls = match get_tls_acceptor(&cp, server_name.as_ref().map(|x| &x[..])) {
+                        Ok(x) => x,
+                        Err(e) => return peer_err2(e),
+                    };
+                    ssl_accept(p, l2r, tls)
+                },
+            )) as BoxedNewPeerFuture
+        })
     }
-    specifier_boilerplate!(noglobalstate has_subspec);
+    specifier_boilerplate!(globalstate has_subspec);
     self_0_is_subspecifier!(proxy_is_multiconnect);
 }
 specifier_class!(
@@ -100,6 +135,19 @@ Accept an TLS connection using arbitrary backing stream. [A]
 Example: The same as in TlsListenClass's example, but with manual acceptor
 
     websocat -E -b --pkcs12-der=q.pkcs12 tls-a:tcp-l:127.0.0.1:1234 mirror:
+
+Certificate and key can also be specified as PEM files using --pem-cert and --pem-key.
+They are re-read when their modification time changes, so renewed certificates
+are picked up by new connections without restarting Websocat.
+
+--tls-alpn, --tls-min-version and --tls-max-version options also apply here.
+
+Additional certificates for specific server names (SNI) can be specified with --sni-pem-cert
+and --sni-pem-key. Use --sni-route to serve each of those names by a different right side specifier:
+
+    websocat -E -b --sni-pem-cert=a.example.com=a.pem --sni-pem-key=a.example.com=a.key \
+        --sni-pem-cert=b.example.com=b.pem --sni-pem-key=b.example.com=b.key \
+        --sni-route=b.example.com=tcp:127.0.0.1:8082 tls-l:0.0.0.0:443 tcp:127.0.0.1:8081
 "#
 );
 
@@ -123,6 +171,10 @@ Example: Non-websocket SSL echo server
 
     websocat -E -b --pkcs12-der=q.pkcs12 ssl-listen:127.0.0.1:1234 mirror:
     socat - ssl:127.0.0.1:1234,verify=0
+
+Example: the same, but with a PEM certificate chain and key (e.g. from Let's Encrypt)
+
+    websocat -E -b --pem-cert=fullchain.pem --pem-key=privkey.pem ssl-listen:127.0.0.1:1234 mirror:
 "#
 );
 
@@ -138,90 +190,555 @@ Example: wss:// echo server + client for testing
     websocat -E -t --pkcs12-der=q.pkcs12 wss-listen:127.0.0.1:1234 mirror:
     websocat --ws-c-uri=wss://localhost/ -t - ws-c:cmd:'socat - ssl:127.0.0.1:1234,verify=0'
 
+Example: the same server, using PEM certificate and key files instead of PKCS12
+
+    websocat -E -t --pem-cert=fullchain.pem --pem-key=privkey.pem wss-listen:127.0.0.1:1234 mirror:
+
 See [moreexamples.md](./moreexamples.md) for info about generation of `q.pkcs12`.
 "#
 );
 
-use tokio_io::AsyncRead;
+/// Parse SHA-256 fingerprint given as hex, optionally with `:` separators.
+pub fn interpret_sha256(x: &str) -> ::std::result::Result<[u8; 32], String> {
+    let h: String = x.chars().filter(|&c| c != ':').collect();
+    let v = hex::decode(h).map_err(|e| format!("Invalid hex in SHA-256 fingerprint: {}", e))?;
+    if v.len() != 32 {
+        return Err(format!("SHA-256 fingerprint should be 32 bytes, not {}", v.len()));
+    }
+    let mut a = [0; 32];
+    a.copy_from_slice(&v[..]);
+    Ok(a)
+}
 
-pub fn ssl_connect(
-    inner_peer: Peer,
-    _l2r: L2rUser,
-    dom: Option<String>,
-    tls_insecure: bool,
-) -> BoxedNewPeerFuture {
-    let hup = inner_peer.2;
-    let squashed_peer = readwrite::ReadWriteAsync::new(inner_peer.0, inner_peer.1);
+/// Tag, content, the whole element and the remaining data
+type DerElement<'a> = (u8, &'a [u8], &'a [u8], &'a [u8]);
 
-    fn gettlsc(nohost: bool, noverify: bool) -> native_tls::Result<TlsConnectorExt> {
-        let mut b = TlsConnector::builder();
-        if nohost {
-            b.danger_accept_invalid_hostnames(true);
+fn der_next(d: &[u8]) -> Option<DerElement<'_>> {
+    if d.len() < 2 {
+        return None;
+    }
+    let (len, hdr) = if d[1] & 0x80 == 0 {
+        (d[1] as usize, 2)
+    } else {
+        let n = (d[1] & 0x7f) as usize;
+        if n == 0 || n > 4 || d.len() < 2 + n {
+            return None;
         }
-        if noverify {
-            b.danger_accept_invalid_hostnames(true);
-            b.danger_accept_invalid_certs(true);
+        let l = d[2..2 + n].iter().fold(0usize, |l, &b| (l << 8) | b as usize);
+        (l, 2 + n)
+    };
+    if d.len() < hdr + len {
+        return None;
+    }
+    Some((d[0], &d[hdr..hdr + len], &d[..hdr + len], &d[hdr + len..]))
+}
+
+/// Get n-th field (0 - serialNumber, 2 - issuer, 4 - subject, 5 - subjectPublicKeyInfo)
+/// of TBSCertificate inside DER-encoded X.509 certificate
+fn tbs_field(cert: &[u8], n: usize) -> Option<&[u8]> {
+    let (_, cert, _, _) = der_next(cert)?;
+    let (_, tbs, _, _) = der_next(cert)?;
+    let (tag, _, _, after_version) = der_next(tbs)?;
+    // version is optional
+    let mut rest = if tag == 0xA0 { after_version } else { tbs };
+    for _ in 0..n {
+        rest = der_next(rest)?.3;
+    }
+    Some(der_next(rest)?.2)
+}
+
+/// Find DER-encoded SubjectPublicKeyInfo inside DER-encoded X.509 certificate
+fn spki_from_cert(cert: &[u8]) -> Option<&[u8]> {
+    tbs_field(cert, 5)
+}
+
+/// Format X.509 Name like `CN=example.com, O=Example`, skipping unknown attributes
+fn format_name(name: &[u8]) -> String {
+    let mut parts = vec![];
+    let mut rdns = der_next(name).map(|x| x.1).unwrap_or(&[]);
+    while let Some((_, rdn, _, rest)) = der_next(rdns) {
+        rdns = rest;
+        let mut atvs = rdn;
+        while let Some((_, atv, _, rest)) = der_next(atvs) {
+            atvs = rest;
+            let (oid, value) = match der_next(atv) {
+                Some((6, oid, _, v)) => (oid, der_next(v).map(|x| x.1).unwrap_or(&[])),
+                _ => continue,
+            };
+            let key = match oid {
+                [0x55, 4, 3] => "CN",
+                [0x55, 4, 6] => "C",
+                [0x55, 4, 7] => "L",
+                [0x55, 4, 8] => "ST",
+                [0x55, 4, 10] => "O",
+                [0x55, 4, 11] => "OU",
+                _ => continue,
+            };
+            parts.push(format!("{}={}", key, String::from_utf8_lossy(value)));
         }
-        let tlsc: TlsConnector = b.build()?;
-        Ok(TlsConnectorExt::from(tlsc))
     }
+    parts.join(", ")
+}
 
-    let tls = match gettlsc(dom.is_none(), tls_insecure) {
-        Ok(x) => x,
-        Err(e) => return peer_err(e),
-    };
+fn version_name(v: u16) -> String {
+    match v {
+        0x0300 => "SSLv3".to_string(),
+        0x0301 => "TLSv1.0".to_string(),
+        0x0302 => "TLSv1.1".to_string(),
+        0x0303 => "TLSv1.2".to_string(),
+        0x0304 => "TLSv1.3".to_string(),
+        x => format!("0x{:04x}", x),
+    }
+}
 
-    info!("Connecting to TLS");
-    if let Some(dom) = dom {
-        Box::new(
-            tls.connect(dom.as_str(), squashed_peer)
-                .map_err(box_up_err)
-                .and_then(move |tls_stream| {
-                    info!("Connected to TLS");
-                    let (r, w) = tls_stream.split();
-                    ok(Peer::new(r, w, hup))
-                }),
-        )
+fn cipher_name(c: u16) -> String {
+    match c {
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0xC02B => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xC02C => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xC02F => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xC030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xCCA8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xCCA9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xC013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xC014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0x009C => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009D => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x002F => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        x => return format!("0x{:04x}", x),
+    }
+    .to_string()
+}
+
+/// Log negotiated TLS parameters and the certificate chain presented by the peer
+pub fn log_handshake(
+    version: Option<u16>,
+    cipher: Option<u16>,
+    alpn: Option<&[u8]>,
+    chain: &[Vec<u8>],
+) {
+    use self::sha2::{Digest, Sha256};
+    info!(
+        "TLS version: {}, cipher: {}, ALPN: {}",
+        version.map(version_name).unwrap_or_else(|| "unknown".to_string()),
+        cipher.map(cipher_name).unwrap_or_else(|| "unknown".to_string()),
+        alpn.map(|x| String::from_utf8_lossy(x).into_owned())
+            .unwrap_or_else(|| "none".to_string()),
+    );
+    for (i, cert) in chain.iter().enumerate() {
+        info!(
+            "TLS certificate #{}: subject: {}, issuer: {}, SHA-256: {}",
+            i,
+            tbs_field(cert, 4).map(format_name).unwrap_or_default(),
+            tbs_field(cert, 2).map(format_name).unwrap_or_default(),
+            hex::encode(&Sha256::digest(&cert[..])[..]),
+        );
+    }
+}
+
+/// Check DER-encoded server certificate against --tls-pin-* options
+pub fn check_pins(cert: Option<&[u8]>, opts: &Options) -> crate::Result<()> {
+    use self::sha2::{Digest, Sha256};
+    if opts.tls_pin_cert_sha256.is_empty() && opts.tls_pin_pubkey_sha256.is_empty() {
+        return Ok(());
+    }
+    let cert = match cert {
+        Some(x) => x,
+        None => Err("TLS server has not presented a certificate to check pins against")?,
+    };
+    let cert_hash = Sha256::digest(cert);
+    debug!("Server certificate SHA-256: {}", hex::encode(&cert_hash[..]));
+    if opts.tls_pin_cert_sha256.iter().any(|x| x[..] == cert_hash[..]) {
+        return Ok(());
+    }
+    if let Some(spki) = spki_from_cert(cert) {
+        let spki_hash = Sha256::digest(spki);
+        debug!("Server public key SHA-256: {}", hex::encode(&spki_hash[..]));
+        if opts.tls_pin_pubkey_sha256.iter().any(|x| x[..] == spki_hash[..]) {
+            return Ok(());
+        }
     } else {
-        Box::new(tls.connect("domainverificationdisabled", squashed_peer).map_err(box_up_err).and_then(move |tls_stream| {
-            warn!("Connected to TLS without proper verification of certificate. Use --tls-domain option.");
-            let (r,w) = tls_stream.split();
-            ok(Peer::new(r,w, hup))
-        }))
+        warn!("Failed to find public key in server certificate");
+    }
+    Err("TLS server certificate does not match any of the pinned SHA-256 fingerprints")?
+}
+
+/// Accepting side TLS configuration cached between connections.
+/// PEM files are checked for modification on each incoming connection.
+#[derive(Default)]
+pub struct TlsAcceptorCache {
+    pkcs12: Option<TlsAcceptorExt>,
+    /// Keyed by certificate and key file names
+    pem: HashMap<(PathBuf, PathBuf), PemAcceptor>,
+}
+
+/// Certificate and key modification times and the acceptor loaded from them
+type PemAcceptor = (Option<SystemTime>, Option<SystemTime>, TlsAcceptorExt);
+
+fn mtime(p: &Path) -> Option<SystemTime> {
+    ::std::fs::metadata(p).and_then(|m| m.modified()).ok()
+}
+
+fn load_pem_acceptor(cert: &Path, key: &Path, opts: &Options) -> crate::Result<TlsAcceptorExt> {
+    let cert = ::std::fs::read(cert).map_err(|e| format!("Failed to read {:?}: {}", cert, e))?;
+    let key = ::std::fs::read(key).map_err(|e| format!("Failed to read {:?}: {}", key, e))?;
+    backend::acceptor_from_pem(&cert[..], &key[..], opts)
+}
+
+fn get_pem_acceptor(
+    cache: &mut TlsAcceptorCache,
+    cert: &Path,
+    key: &Path,
+    opts: &Options,
+) -> crate::Result<TlsAcceptorExt> {
+    let (cert_mtime, key_mtime) = (mtime(cert), mtime(key));
+    let cache_key = (cert.to_path_buf(), key.to_path_buf());
+    if let Some((cm, km, tls)) = cache.pem.get(&cache_key) {
+        if *cm == cert_mtime && *km == key_mtime {
+            return Ok(tls.clone());
+        }
+    }
+    match load_pem_acceptor(cert, key, opts) {
+        Ok(tls) => {
+            let old = cache.pem.insert(cache_key, (cert_mtime, key_mtime, tls.clone()));
+            if old.is_some() {
+                info!("Reloaded TLS certificate and key from {:?} and {:?}", cert, key);
+            }
+            Ok(tls)
+        }
+        Err(e) => {
+            if let Some((_, _, tls)) = cache.pem.get(&cache_key) {
+                // Files may be in the middle of being replaced by a renewal tool
+                warn!("Failed to reload TLS certificate, using the old one: {}", e);
+                Ok(tls.clone())
+            } else {
+                Err(e)
+            }
+        }
     }
 }
 
-pub fn ssl_accept(inner_peer: Peer, _l2r: L2rUser, progopt: Rc<Options>) -> BoxedNewPeerFuture {
-    let hup = inner_peer.2;
-    let squashed_peer = readwrite::ReadWriteAsync::new(inner_peer.0, inner_peer.1);
+fn get_tls_acceptor(cp: &ConstructParams, server_name: Option<&str>) -> crate::Result<TlsAcceptorExt> {
+    let progopt: &Options = &cp.program_options;
+    let mut cache = cp.global(TlsAcceptorCache::default);
 
-    fn gettlsa(cert: &[u8], passwd: &str) -> native_tls::Result<TlsAcceptorExt> {
-        let pkcs12 = Pkcs12::from_pkcs12(&cert[..], passwd)?;
-        Ok(TlsAcceptorExt::from(TlsAcceptor::builder(pkcs12).build()?))
+    if let Some(name) = server_name {
+        let sni_cert = progopt.tls_sni_certs.iter().find(|x| server_name_matches(&x.0, name));
+        if let Some((pattern, cert)) = sni_cert {
+            let key = progopt
+                .tls_sni_keys
+                .iter()
+                .find(|x| x.0 == *pattern)
+                .map(|x| &x.1)
+                .ok_or_else(|| format!("No --sni-pem-key for server name pattern {:?}", pattern))?;
+            return get_pem_acceptor(&mut cache, cert, key, progopt);
+        }
     }
 
-    let der = progopt
-        .pkcs12_der
-        .as_ref()
-        .expect("lint should have cought the missing pkcs12_der option");
+    if let (Some(cert), Some(key)) = (progopt.pem_cert.as_ref(), progopt.pem_key.as_ref()) {
+        return get_pem_acceptor(&mut cache, cert, key, progopt);
+    }
+
+    if let Some(ref tls) = cache.pkcs12 {
+        return Ok(tls.clone());
+    }
+    let der = match progopt.pkcs12_der.as_ref() {
+        Some(x) => x,
+        None => Err(format!(
+            "No TLS certificate for server name {:?}",
+            server_name.unwrap_or("")
+        ))?,
+    };
     let passwd = progopt
         .pkcs12_passwd
         .as_ref()
         .map(|x| x.as_str())
         .unwrap_or("");
-    let tls = match gettlsa(der, passwd) {
-        Ok(x) => x,
-        Err(e) => return peer_err(e),
+    let tls = backend::acceptor_from_pkcs12(der, passwd, progopt)?;
+    cache.pkcs12 = Some(tls.clone());
+    Ok(tls)
+}
+
+/// Parse `<hostname>=<file>` argument of --sni-pem-cert and --sni-pem-key
+pub fn interpret_sni_path(x: &str) -> ::std::result::Result<(String, PathBuf), String> {
+    match x.find('=') {
+        Some(i) if i > 0 => Ok((x[..i].to_string(), PathBuf::from(&x[i + 1..]))),
+        _ => Err("Expected <hostname>=<file>".to_string()),
+    }
+}
+
+/// Parse `<hostname>=<specifier>` argument of --sni-route
+pub fn interpret_sni_route(x: &str) -> ::std::result::Result<(String, String), String> {
+    match x.find('=') {
+        Some(i) if i > 0 => Ok((x[..i].to_string(), x[i + 1..].to_string())),
+        _ => Err("Expected <hostname>=<specifier>".to_string()),
+    }
+}
+
+/// Case-insensitive server name comparison. `*.example.com` matches
+/// `www.example.com`, but not `example.com` or `a.b.example.com`.
+fn server_name_matches(pattern: &str, name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+    if pattern.starts_with("*.") {
+        match name.find('.') {
+            Some(i) => name[i..] == pattern[1..],
+            None => false,
+        }
+    } else {
+        name == pattern
+    }
+}
+
+/// Find server name in TLS ClientHello, which may span multiple records.
+/// Returns `None` if more data is needed.
+fn client_hello_server_name(d: &[u8]) -> Option<Option<String>> {
+    let mut hs = vec![];
+    let mut rest = d;
+    let hello_len = loop {
+        if rest.len() < 5 {
+            return None;
+        }
+        if rest[0] != 22 {
+            // Not a TLS handshake, let the TLS library complain
+            return Some(None);
+        }
+        let len = ((rest[3] as usize) << 8) | rest[4] as usize;
+        if rest.len() < 5 + len {
+            return None;
+        }
+        hs.extend_from_slice(&rest[5..5 + len]);
+        rest = &rest[5 + len..];
+        if hs.len() >= 4 {
+            if hs[0] != 1 {
+                return Some(None);
+            }
+            let hl = ((hs[1] as usize) << 16) | ((hs[2] as usize) << 8) | hs[3] as usize;
+            if hs.len() >= 4 + hl {
+                break hl;
+            }
+        }
     };
+    let b = &hs[4..4 + hello_len];
+    let u16_at = |i: usize| b.get(i..i + 2).map(|x| ((x[0] as usize) << 8) | x[1] as usize);
+
+    // client_version, random
+    let mut i = 34;
+    // session_id
+    i += 1 + *b.get(i)? as usize;
+    // cipher_suites
+    i += 2 + u16_at(i)?;
+    // compression_methods
+    i += 1 + *b.get(i)? as usize;
+    // extensions length
+    i += 2;
+    Some((|| {
+        while let (Some(typ), Some(len)) = (u16_at(i), u16_at(i + 2)) {
+            if typ == 0 {
+                // server_name_list length, name_type, host_name length
+                let name_len = u16_at(i + 7)?;
+                let name = b.get(i + 9..i + 9 + name_len)?;
+                return String::from_utf8(name.to_vec()).ok();
+            }
+            i += 4 + len;
+        }
+        None
+    })())
+}
+
+/// Reads incoming TLS ClientHello to find out requested server name
+/// before choosing a certificate. The data read is then replayed to the TLS library.
+struct ServerNamePeek(Option<Peer>, Vec<u8>);
+
+impl ServerNamePeek {
+    fn finish(&mut self, server_name: Option<String>) -> (Peer, Option<String>) {
+        let p = self.0.take().expect("ServerNamePeek polled after completion");
+        let buf = ::std::mem::take(&mut self.1);
+        let r = crate::util::Replay::new(buf, p.0);
+        (Peer::new(r, p.1, p.2), server_name)
+    }
+}
+
+impl Future for ServerNamePeek {
+    type Item = (Peer, Option<String>);
+    type Error = ::std::io::Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let done = if self.1.len() > 65536 {
+                Some(None)
+            } else {
+                client_hello_server_name(&self.1[..])
+            };
+            if let Some(server_name) = done {
+                return Ok(Async::Ready(self.finish(server_name)));
+            }
+            let mut tmp = [0u8; 4096];
+            let n = try_nb!(self.0.as_mut().unwrap().0.read(&mut tmp));
+            if n == 0 {
+                return Ok(Async::Ready(self.finish(None)));
+            }
+            self.1.extend_from_slice(&tmp[..n]);
+        }
+    }
+}
+
+/// Right side specifier chosen by server name of incoming TLS connection (--sni-route)
+#[derive(Debug)]
+pub struct SniRoute {
+    routes: Vec<(String, Rc<dyn Specifier>)>,
+    default: Rc<dyn Specifier>,
+}
+
+impl SniRoute {
+    pub fn wrap(
+        default: Rc<dyn Specifier>,
+        routes: &[(String, crate::SpecifierStack)],
+    ) -> crate::Result<Rc<dyn Specifier>> {
+        let mut v = vec![];
+        for (name, spec) in routes {
+            v.push((name.clone(), <dyn Specifier>::from_stack(spec)?));
+        }
+        Ok(Rc::new(SniRoute { routes: v, default }))
+    }
+}
+
+impl Specifier for SniRoute {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let server_name = match cp.left_to_right {
+            L2rUser::ReadFrom(ref x) => x.tls_server_name.clone(),
+            L2rUser::FillIn(_) => None,
+        };
+        if let Some(name) = server_name {
+            for (pattern, spec) in &self.routes {
+                if server_name_matches(pattern, &name) {
+                    info!("Routing TLS connection for {} to {:?}", name, spec);
+                    return spec.construct(cp);
+                }
+            }
+        }
+        self.default.construct(cp)
+    }
+    fn is_multiconnect(&self) -> bool {
+        self.default.is_multiconnect() && self.routes.iter().all(|x| x.1.is_multiconnect())
+    }
+    fn uses_global_state(&self) -> bool {
+        self.default.uses_global_state() || self.routes.iter().any(|x| x.1.uses_global_state())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sha2::{Digest, Sha256};
+    use super::*;
+
+    /// Self-signed `CN=example.com, O=Example` certificate with P-256 key
+    const CERT: &str = "\
+        308201a73082014da0030201020214607db28b451a5a76e9fa12f92f5d961556ed6103300a06082a\
+        8648ce3d04030230283114301206035504030c0b6578616d706c652e636f6d3110300e060355040a\
+        0c074578616d706c653020170d3236313031393030313734305a180f323132363039323530303137\
+        34305a30283114301206035504030c0b6578616d706c652e636f6d3110300e060355040a0c074578\
+        616d706c653059301306072a8648ce3d020106082a8648ce3d03010703420004380582ec384ac799\
+        aea2638582c2509aafc213280e2bff67ef4c819e61d8da000362e0fb33569c8d075fa6b64b82278a\
+        82efc4d6f0d7004b436b26d90593c51ea3533051301d0603551d0e04160414d7ae6270091b76bc54\
+        e8f4242835bb39f5d39842301f0603551d23041830168014d7ae6270091b76bc54e8f4242835bb39\
+        f5d39842300f0603551d130101ff040530030101ff300a06082a8648ce3d04030203480030450221\
+        00e22a6d5312cf18e3e3fb38259dda7cb4733690d4e76f29f5faed327a26d90fc10220119f006461\
+        1303e0284c40667879872f7e7ece392c2912bfcc5bb59c95b93f46";
+
+    fn cert() -> Vec<u8> {
+        hex::decode(CERT).unwrap()
+    }
+
+    #[test]
+    fn spki_pin() {
+        let cert = cert();
+        let spki = spki_from_cert(&cert).unwrap();
+        assert_eq!(spki[0], 0x30);
+        let pin = interpret_sha256(
+            "48:d0:4f:59:40:78:00:f3:01:1d:2f:10:c4:f3:d9:b6:67:be:fc:70:d0:9e:8b:f7:45:ac:ec:4c:38:4f:89:d0",
+        )
+        .unwrap();
+        assert_eq!(Sha256::digest(spki)[..], pin[..]);
+    }
+
+    #[test]
+    fn names() {
+        let cert = cert();
+        let subject = tbs_field(&cert, 4).map(format_name).unwrap();
+        assert_eq!(subject, "CN=example.com, O=Example");
+        assert_eq!(tbs_field(&cert, 2).map(format_name).unwrap(), subject);
+    }
 
-    info!("Accepting a TLS connection");
-    Box::new(
-        tls.accept(squashed_peer)
-            .map_err(box_up_err)
-            .and_then(move |tls_stream| {
-                info!("Connected to TLS");
-                let (r, w) = tls_stream.split();
-                ok(Peer::new(r, w, hup))
-            }),
-    )
+    #[test]
+    fn truncated_cert() {
+        let cert = cert();
+        for n in [0, 1, 2, 4, 100, cert.len() - 1] {
+            assert_eq!(spki_from_cert(&cert[..n]), None, "{} bytes", n);
+        }
+    }
+
+    /// TLS 1.2 ClientHello for `example.com` from `openssl s_client`
+    const HELLO: &str = "\
+        16030100960100009203038cd7bcdf4d1f5cf0f30c12fbd2afcaf5f10bc6b66f0619501bd0757234\
+        0550a7000002c02f01000067ff0100010000000010000e00000b6578616d706c652e636f6d000b00\
+        0403000102000a000c000a001d0017001e001800190016000000170000000d002a00280403050306\
+        03080708080809080a080b080408050806040105010601030303010302040205020602";
+
+    fn hello() -> Vec<u8> {
+        hex::decode(HELLO).unwrap()
+    }
+
+    #[test]
+    fn client_hello_sni() {
+        let d = hello();
+        assert_eq!(client_hello_server_name(&d), Some(Some("example.com".to_string())));
+        // Extra data after the ClientHello does not matter
+        let mut more = d.clone();
+        more.extend_from_slice(b"garbage");
+        assert_eq!(client_hello_server_name(&more), Some(Some("example.com".to_string())));
+    }
+
+    #[test]
+    fn client_hello_truncated() {
+        let d = hello();
+        for n in 0..d.len() {
+            assert_eq!(client_hello_server_name(&d[..n]), None, "{} bytes", n);
+        }
+    }
+
+    #[test]
+    fn client_hello_fragmented() {
+        let d = hello();
+        let body = &d[5..];
+        let mut v = vec![];
+        for part in [&body[..50], &body[50..]] {
+            v.extend_from_slice(&[22, 3, 1, 0, part.len() as u8]);
+            v.extend_from_slice(part);
+        }
+        assert_eq!(client_hello_server_name(&v[..60]), None);
+        assert_eq!(client_hello_server_name(&v), Some(Some("example.com".to_string())));
+    }
+
+    #[test]
+    fn client_hello_without_sni() {
+        let mut d = hello();
+        let i = d.windows(6).position(|x| x == [0, 0, 0, 0x10, 0, 0x0e]).unwrap();
+        d[i] = 0xfa;
+        d[i + 1] = 0xfa;
+        assert_eq!(client_hello_server_name(&d), Some(None));
+        assert_eq!(client_hello_server_name(b"GET / HTTP/1.1\r\n\r\n"), Some(None));
+        assert_eq!(client_hello_server_name(&[22, 3, 1, 0, 4, 2, 0, 0, 0]), Some(None));
+    }
+
+    #[test]
+    fn der_lengths() {
+        assert_eq!(der_next(&[4, 1, 9, 7]), Some((4, &[9][..], &[4, 1, 9][..], &[7][..])));
+        assert_eq!(der_next(&[4, 0x81, 1, 9]), Some((4, &[9][..], &[4, 0x81, 1, 9][..], &[][..])));
+        assert_eq!(der_next(&[4, 0x80, 1, 9]), None);
+        assert_eq!(der_next(&[4, 0x82, 1]), None);
+        assert_eq!(der_next(&[4, 2, 9]), None);
+    }
 }
@@ -0,0 +1,132 @@
+//! Generic message transformation wrapper, used by `jq:` and `filter:`.
+
+use futures::future::ok;
+
+use std::collections::VecDeque;
+
+use super::readdebt::{DebtHandling, ProcessMessageResult, ReadDebt, ZeroMessagesHandling};
+use super::{BoxedNewPeerFuture, Peer};
+
+use std::io::{Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use std::io::Error as IoError;
+
+/// Turns one message into zero or more messages
+pub trait MessageTransform {
+    fn transform(&mut self, msg: &[u8]) -> Vec<Vec<u8>>;
+}
+
+/// Apply transforms to messages read from and/or written to the peer
+pub fn transform_peer(
+    inner_peer: Peer,
+    read: Option<Box<dyn MessageTransform>>,
+    write: Option<Box<dyn MessageTransform>>,
+    buffer_size: usize,
+    grow_limit: Option<usize>,
+) -> BoxedNewPeerFuture {
+    let r: Box<dyn AsyncRead> = match read {
+        None => inner_peer.0,
+        Some(t) => Box::new(TransformReader {
+            inner: inner_peer.0,
+            transform: t,
+            buf: vec![0; buffer_size],
+            queue: VecDeque::new(),
+            debt: ReadDebt(None, DebtHandling::Warn, ZeroMessagesHandling::Deliver, grow_limit),
+        }),
+    };
+    let w: Box<dyn AsyncWrite> = match write {
+        None => inner_peer.1,
+        Some(t) => Box::new(TransformWriter {
+            inner: inner_peer.1,
+            transform: t,
+            pending: VecDeque::new(),
+        }),
+    };
+    let thepeer = Peer(r, w, inner_peer.2, inner_peer.3);
+    Box::new(ok(thepeer)) as BoxedNewPeerFuture
+}
+
+struct TransformReader {
+    inner: Box<dyn AsyncRead>,
+    transform: Box<dyn MessageTransform>,
+    buf: Vec<u8>,
+    /// Transformed messages not yet read
+    queue: VecDeque<Vec<u8>>,
+    debt: ReadDebt,
+}
+
+impl Read for TransformReader {
+    fn read(&mut self, b: &mut [u8]) -> Result<usize, IoError> {
+        loop {
+            if let Some(ret) = self.debt.check_debt(b) {
+                return ret;
+            }
+            if let Some(m) = self.queue.pop_front() {
+                match self.debt.process_message(b, &m) {
+                    ProcessMessageResult::Return(x) => return x,
+                    ProcessMessageResult::Recurse => continue,
+                }
+            }
+            let n = match self.inner.read(&mut self.buf) {
+                Ok(0) => return Ok(0),
+                Ok(n) => n,
+                Err(ref e) if super::readdebt::is_empty_message(e) => 0,
+                Err(ref e) if super::readdebt::requested_buffer_size(e).is_some() => {
+                    let n = super::readdebt::requested_buffer_size(e).unwrap();
+                    self.buf.resize(n, 0);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let out = self.transform.transform(&self.buf[..n]);
+            self.queue.extend(out);
+        }
+    }
+}
+impl AsyncRead for TransformReader {}
+
+struct TransformWriter {
+    inner: Box<dyn AsyncWrite>,
+    transform: Box<dyn MessageTransform>,
+    /// Transformed messages not yet accepted by inner writer
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl TransformWriter {
+    fn write_pending(&mut self) -> Result<(), IoError> {
+        while let Some(m) = self.pending.front_mut() {
+            let n = self.inner.write(m)?;
+            if m.is_empty() || n >= m.len() {
+                self.pending.pop_front();
+            } else {
+                drop(m.drain(..n));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for TransformWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.write_pending()?;
+        let out = self.transform.transform(buf);
+        self.pending.extend(out);
+        match self.write_pending() {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+            Ok(()) => (),
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.write_pending()?;
+        self.inner.flush()
+    }
+}
+impl AsyncWrite for TransformWriter {
+    fn shutdown(&mut self) -> futures::Poll<(), IoError> {
+        try_nb!(self.write_pending());
+        self.inner.shutdown()
+    }
+}
@@ -354,6 +354,11 @@ pub fn unix_listen_peer(addr: &Path, opts: &Rc<Options>) -> BoxedNewPeerStream {
         Err(e) => return peer_err_s(e),
     };
     // TODO: chmod
+    unix_serve_listener(bound)
+}
+
+/// Accept connections from already bound UNIX socket listener
+pub fn unix_serve_listener(bound: UnixListener) -> BoxedNewPeerStream {
     use tk_listen::ListenExt;
     Box::new(
         bound
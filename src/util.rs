@@ -1,6 +1,6 @@
 use super::{
     futures, AsyncRead, AsyncWrite, BoxedNewPeerFuture, BoxedNewPeerStream, L2rUser, Peer,
-    PeerConstructor, Rc, HupToken,
+    PeerConstructor, Rc, HupToken, EventSink,
 };
 use super::{Future, Stream};
 
@@ -119,6 +119,47 @@ impl Peer {
             Box::new(r) as Box<dyn AsyncRead>,
             Box::new(w) as Box<dyn AsyncWrite>,
             hup,
+            None,
         )
     }
+    pub fn with_events(mut self, events: EventSink) -> Self {
+        self.3 = Some(events);
+        self
+    }
+}
+
+/// Reader that first returns already consumed bytes, then continues with the inner reader
+pub struct Replay<R>(std::io::Chain<std::io::Cursor<Vec<u8>>, R>);
+impl<R: std::io::Read> Replay<R> {
+    pub fn new(prefix: Vec<u8>, inner: R) -> Self {
+        use std::io::Read;
+        Replay(std::io::Cursor::new(prefix).chain(inner))
+    }
+}
+impl<R: std::io::Read> std::io::Read for Replay<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+impl<R: AsyncRead> AsyncRead for Replay<R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::Replay;
+    use std::io::Read;
+
+    #[test]
+    fn replay() {
+        let mut r = Replay::new(b"abc".to_vec(), &b"defgh"[..]);
+        let mut b = [0; 2];
+        assert_eq!(r.read(&mut b).unwrap(), 2);
+        assert_eq!(&b, b"ab");
+        let mut rest = vec![];
+        r.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"cdefgh");
+
+        let mut r = Replay::new(vec![], &b"x"[..]);
+        assert_eq!(r.read(&mut b).unwrap(), 1);
+        assert_eq!(r.read(&mut b).unwrap(), 0);
+    }
 }
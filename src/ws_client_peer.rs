@@ -1,4 +1,5 @@
 extern crate hyper;
+extern crate url;
 extern crate websocket;
 
 use self::websocket::client::r#async::ClientNew;
@@ -53,10 +54,10 @@ Example: connect to public WebSocket loopback and copy binary chunks from stdin
 "#
 );
 
-#[cfg(feature = "ssl")]
+#[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
 #[derive(Debug, Clone)]
 pub struct WsClientSecure(pub Url);
-#[cfg(feature = "ssl")]
+#[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
 impl Specifier for WsClientSecure {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
         let url = self.0.clone();
@@ -64,7 +65,7 @@ impl Specifier for WsClientSecure {
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec);
 }
-#[cfg(feature = "ssl")]
+#[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
 specifier_class!(
     name = WsClientSecureClass,
     target = WsClientSecure,
@@ -181,28 +182,54 @@ where
 pub fn get_ws_client_peer(uri: &Url, opts: Rc<Options>) -> BoxedNewPeerFuture {
     info!("get_ws_client_peer");
 
-    #[allow(unused)]
-    let tls_insecure = opts.tls_insecure;
+    #[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+    {
+        if uri.scheme() == "wss" {
+            return get_wss_client_peer(uri, opts);
+        }
+    }
     get_ws_client_peer_impl(uri, opts, |before_connect| {
-        #[cfg(feature = "ssl")]
-        let after_connect = {
-            let mut tls_opts = None;
-            if tls_insecure {
-                tls_opts = Some(
-                    super::ssl_peer::native_tls::TlsConnector::builder()
-                        .danger_accept_invalid_certs(true)
-                        .danger_accept_invalid_hostnames(true)
-                        .build()?,
-                );
-            };
-            before_connect.async_connect(tls_opts)
-        };
-        #[cfg(not(feature = "ssl"))]
-        let after_connect = before_connect.async_connect_insecure();
-        Ok(after_connect)
+        Ok(before_connect.async_connect_insecure())
     })
 }
 
+/// Connect TCP, then TLS using the same code as `tls-connect:`, then WebSocket.
+#[cfg(any(feature = "ssl", feature = "ssl_rustls"))]
+fn get_wss_client_peer(uri: &Url, opts: Rc<Options>) -> BoxedNewPeerFuture {
+    let hostport = match (uri.host(), uri.port_or_known_default()) {
+        (Some(h), Some(port)) => super::resolve::HostPort {
+            host: match h {
+                self::url::Host::Domain(d) => d.to_string(),
+                self::url::Host::Ipv4(ip) => ip.to_string(),
+                self::url::Host::Ipv6(ip) => ip.to_string(),
+            },
+            port,
+        },
+        _ => return peer_strerr("WebSocket URL has no host"),
+    };
+    let dom = match (&opts.tls_domain, uri.host()) {
+        (Some(d), _) => d.clone(),
+        (None, Some(self::url::Host::Domain(d))) => d.to_string(),
+        (None, Some(self::url::Host::Ipv4(ip))) => ip.to_string(),
+        (None, Some(self::url::Host::Ipv6(ip))) => ip.to_string(),
+        (None, None) => return peer_strerr("WebSocket URL has no host"),
+    };
+    let uri = uri.clone();
+    let opts2 = opts.clone();
+    Box::new(
+        super::resolve::resolve(&hostport, &opts)
+            .and_then({
+                let opts = opts.clone();
+                move |addrs| super::net_peer::tcp_connect_peer(&addrs[..], &opts)
+            })
+            .and_then(move |p| {
+                let l2r = super::L2rUser::ReadFrom(Rc::new(Default::default()));
+                super::ssl_peer::ssl_connect(p, l2r, Some(dom), opts2)
+            })
+            .and_then(move |p| get_ws_client_peer_wrapped(&uri, p, opts)),
+    ) as BoxedNewPeerFuture
+}
+
 unsafe impl Send for PeerForWs {
     //! https://github.com/cyderize/rust-websocket/issues/168
 }
@@ -440,16 +440,27 @@ pub fn finish_building_ws_peer<S>(opts: &super::Options, duplex: Duplex<S>, clos
         None
     };
 
-    let zmsgh = if opts.no_exit_on_zeromsg {
+    let zmsgh = if opts.websocket_ignore_zeromsg {
         super::readdebt::ZeroMessagesHandling::Drop
     } else {
         super::readdebt::ZeroMessagesHandling::Deliver
     };
     
+    let grow_limit = if opts.whole_messages {
+        Some(opts.max_message_size)
+    } else {
+        None
+    };
+
     let ws_str = WsReadWrapper {
         s: stream,
         pingreply: mpsink.clone(),
-        debt: super::readdebt::ReadDebt(Default::default(), opts.read_debt_handling, zmsgh),
+        debt: super::readdebt::ReadDebt(
+            Default::default(),
+            opts.read_debt_handling,
+            zmsgh,
+            grow_limit,
+        ),
         pong_timeout,
         ping_aborter,
         text_prefix: opts.ws_text_prefix.clone(),